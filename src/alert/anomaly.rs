@@ -0,0 +1,187 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::TemperatureAnomalyConfig,
+    conn_manager::PrinterId,
+    status::{bambu::PrinterStatus, PrinterState},
+};
+
+use super::{Alert, AlertEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Heater {
+    Nozzle,
+    Bed,
+    /// only printers that report a chamber target
+    Chamber,
+}
+
+impl Heater {
+    pub fn to_text(&self) -> &'static str {
+        match self {
+            Self::Nozzle => "Nozzle",
+            Self::Bed => "Bed",
+            Self::Chamber => "Chamber",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Anomaly {
+    NotReached,
+    Drift,
+    RisingWhileOff,
+}
+
+/// Tracking for a single heater, reset whenever the target changes
+struct HeaterState {
+    target: f64,
+    target_since: Instant,
+    reached: bool,
+    out_of_band_since: Option<Instant>,
+    min_while_off: Option<f64>,
+    /// only report each anomaly once per target
+    reported: HashSet<Anomaly>,
+}
+
+impl HeaterState {
+    fn new(target: f64) -> Self {
+        Self {
+            target,
+            target_since: Instant::now(),
+            reached: false,
+            out_of_band_since: None,
+            min_while_off: None,
+            reported: HashSet::new(),
+        }
+    }
+
+    fn update(
+        &mut self,
+        heater: Heater,
+        temp: f64,
+        printing: bool,
+        config: &TemperatureAnomalyConfig,
+    ) -> Option<String> {
+        let target = self.target;
+
+        if target <= 0. {
+            let min = self.min_while_off.get_or_insert(temp);
+            *min = min.min(temp);
+            if temp - *min > config.rise_while_off && self.reported.insert(Anomaly::RisingWhileOff)
+            {
+                return Some(format!(
+                    "{} temperature is rising while the heater is off: {:.0}°C, up from {:.0}°C",
+                    heater.to_text(),
+                    temp,
+                    *min,
+                ));
+            }
+            return None;
+        }
+
+        let in_band = (temp - target).abs() <= config.tolerance;
+
+        if !self.reached {
+            if in_band {
+                self.reached = true;
+            } else if temp < target
+                && self.target_since.elapsed() > Duration::from_secs(config.heat_timeout * 60)
+                && self.reported.insert(Anomaly::NotReached)
+            {
+                return Some(format!(
+                    "{} has not reached {:.0}°C after {} minutes, currently {:.0}°C",
+                    heater.to_text(),
+                    target,
+                    config.heat_timeout,
+                    temp,
+                ));
+            }
+            return None;
+        }
+
+        if in_band || !printing {
+            // recovered, report again if it drifts again
+            self.out_of_band_since = None;
+            self.reported.remove(&Anomaly::Drift);
+            return None;
+        }
+
+        let since = *self.out_of_band_since.get_or_insert_with(Instant::now);
+        if since.elapsed() > Duration::from_secs(config.drift_time)
+            && self.reported.insert(Anomaly::Drift)
+        {
+            return Some(format!(
+                "{} temperature drifted to {:.0}°C during print, target {:.0}°C",
+                heater.to_text(),
+                temp,
+                target,
+            ));
+        }
+
+        None
+    }
+}
+
+/// Watches heater temperatures against their targets for signs of
+/// thermistor, heater or fan failures
+#[derive(Default)]
+pub struct AnomalyDetector {
+    heaters: HashMap<(PrinterId, Heater), HeaterState>,
+}
+
+impl AnomalyDetector {
+    pub fn update(
+        &mut self,
+        id: &PrinterId,
+        name: &str,
+        status: &PrinterStatus,
+        config: &TemperatureAnomalyConfig,
+    ) -> Vec<Alert> {
+        if !config.enabled || status.state == PrinterState::Disconnected {
+            self.heaters.retain(|(p, _), _| p != id);
+            return vec![];
+        }
+
+        let printing = status.state == PrinterState::Printing;
+
+        let readings = [
+            (Heater::Nozzle, status.temp_nozzle, status.temp_tgt_nozzle),
+            (Heater::Bed, status.temp_bed, status.temp_tgt_bed),
+            (Heater::Chamber, status.temp_chamber, status.temp_tgt_chamber),
+        ];
+
+        let mut out = vec![];
+        for (heater, temp, target) in readings {
+            let (Some(temp), Some(target)) = (temp, target) else {
+                continue;
+            };
+
+            let state = self
+                .heaters
+                .entry((id.clone(), heater))
+                .or_insert_with(|| HeaterState::new(target));
+            if state.target != target {
+                *state = HeaterState::new(target);
+            }
+
+            if let Some(msg) = state.update(heater, temp, printing, config) {
+                warn!("temperature anomaly on {}: {}", name, msg);
+                out.push(Alert::new(
+                    id.clone(),
+                    name,
+                    AlertEvent::TemperatureAnomaly,
+                    format!("Temperature Warning: {}", name),
+                    msg,
+                ));
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::config::EmailConfig;
+
+use super::Alert;
+
+const SNAPSHOT_FILENAME: &str = "snapshot.jpg";
+
+/// how long a desktop notification's snapshot file is kept
+const SNAPSHOT_FILE_SECS: u64 = 5 * 60;
+
+pub async fn send_desktop(alert: &Alert, snapshot: Option<&[u8]>) -> Result<()> {
+    /// notification servers want a path, not the image itself
+    let image_path = match snapshot {
+        Some(jpeg) if cfg!(not(target_os = "macos")) => {
+            /// one file per alert, alerts are sent concurrently
+            let path = std::env::temp_dir().join(format!(
+                "bambu_watcher_{}_{}.jpg",
+                alert.printer,
+                alert.time.format("%Y%m%d_%H%M%S_%f")
+            ));
+            tokio::fs::write(&path, jpeg).await?;
+            Some(path)
+        }
+        _ => None,
+    };
+
+    let title = alert.title.clone();
+    let body = alert.body.clone();
+    let image_path2 = image_path.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let mut notification = notify_rust::Notification::new();
+        notification
+            .summary(&title)
+            .body(&body)
+            .appname("Bambu Watcher")
+            .timeout(0);
+
+        #[cfg(not(target_os = "macos"))]
+        if let Some(path) = image_path.as_ref().and_then(|p| p.to_str()) {
+            notification.image_path(path);
+        }
+
+        let _ = notification.show();
+    })
+    .await?;
+
+    /// the notification server reads the image after `show` returns
+    if let Some(path) = image_path2 {
+        tokio::spawn(async move {
+            tokio::time::sleep(tokio::time::Duration::from_secs(SNAPSHOT_FILE_SECS)).await;
+            let _ = tokio::fs::remove_file(path).await;
+        });
+    }
+
+    Ok(())
+}
+
+/// multipart/form-data with the text fields, plus the snapshot as `snapshot` if there is one
+pub async fn send_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    alert: &Alert,
+    snapshot: Option<&[u8]>,
+) -> Result<()> {
+    let mut form = reqwest::multipart::Form::new()
+        .text("time", alert.time.to_rfc3339())
+        .text("printer", alert.printer_name.clone())
+        .text("serial", alert.printer.to_string())
+        .text("event", format!("{:?}", alert.event))
+        .text("title", alert.title.clone())
+        .text("message", alert.body.clone());
+
+    if let Some(jpeg) = snapshot {
+        let part = reqwest::multipart::Part::bytes(jpeg.to_vec())
+            .file_name(SNAPSHOT_FILENAME)
+            .mime_str("image/jpeg")?;
+        form = form.part("snapshot", part);
+    }
+
+    let res = client.post(url).multipart(form).send().await?;
+    if !res.status().is_success() {
+        bail!("webhook returned status: {}", res.status());
+    }
+
+    Ok(())
+}
+
+pub async fn send_email(
+    config: &EmailConfig,
+    alert: &Alert,
+    snapshot: Option<&[u8]>,
+) -> Result<()> {
+    use lettre::{
+        message::{header::ContentType, Attachment, MultiPart, SinglePart},
+        transport::smtp::authentication::Credentials,
+        AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    };
+
+    let mut builder = Message::builder()
+        .from(config.from.parse()?)
+        .subject(&alert.title);
+    for to in config.to.iter() {
+        builder = builder.to(to.parse()?);
+    }
+
+    let text = SinglePart::plain(alert.body.clone());
+    let message = match snapshot {
+        Some(jpeg) => builder.multipart(
+            MultiPart::mixed().singlepart(text).singlepart(
+                Attachment::new(SNAPSHOT_FILENAME.to_string())
+                    .body(jpeg.to_vec(), ContentType::parse("image/jpeg")?),
+            ),
+        )?,
+        None => builder.singlepart(text)?,
+    };
+
+    /// 465 is implicit TLS, everything else uses STARTTLS
+    let port = config.smtp_port.unwrap_or(587);
+    let mut transport = if port == 465 {
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)?
+    }
+    .port(port);
+
+    if !config.username.is_empty() {
+        transport = transport.credentials(Credentials::new(
+            config.username.clone(),
+            config.password.clone(),
+        ));
+    }
+
+    transport.build().send(message).await?;
+
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::HumidityAlertConfig,
+    conn_manager::PrinterId,
+    status::{bambu::PrinterStatus, PrinterState},
+};
+
+use super::{Alert, AlertEvent};
+
+#[derive(Default)]
+struct UnitState {
+    above_since: Option<Instant>,
+    reported: bool,
+}
+
+/// Watches each AMS unit for humidity staying at or above the configured level
+#[derive(Default)]
+pub struct HumidityMonitor {
+    units: HashMap<(PrinterId, i64), UnitState>,
+}
+
+impl HumidityMonitor {
+    pub fn update(
+        &mut self,
+        id: &PrinterId,
+        name: &str,
+        status: &PrinterStatus,
+        config: &HumidityAlertConfig,
+    ) -> Vec<Alert> {
+        let ams = match status.ams.as_ref() {
+            Some(ams) if config.enabled && status.state != PrinterState::Disconnected => ams,
+            _ => {
+                self.units.retain(|(p, _), _| p != id);
+                return vec![];
+            }
+        };
+
+        let mut out = vec![];
+        for unit in ams.units.values() {
+            let Some(humidity) = unit.humidity else {
+                continue;
+            };
+            let state = self.units.entry((id.clone(), unit.id)).or_default();
+
+            if humidity.level() < config.level {
+                // dried out, alert again if it gets humid again
+                *state = UnitState::default();
+                continue;
+            }
+
+            let since = *state.above_since.get_or_insert_with(Instant::now);
+            if !state.reported && since.elapsed() >= Duration::from_secs(config.minutes * 60) {
+                state.reported = true;
+                let msg = format!(
+                    "AMS {} has been {} for over {} minutes, dry the filament or replace the desiccant",
+                    unit.id + 1,
+                    unit.humidity_text().unwrap_or_default(),
+                    config.minutes,
+                );
+                warn!("humidity on {}: {}", name, msg);
+                out.push(Alert::new(
+                    id.clone(),
+                    name,
+                    AlertEvent::AmsHumidity,
+                    format!("AMS Humidity: {}", name),
+                    msg,
+                ));
+            }
+        }
+        out
+    }
+}
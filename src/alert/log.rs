@@ -0,0 +1,186 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use chrono::{DateTime, Local};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use super::Alert;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertLogEntry {
+    pub id: u64,
+    pub alert: Alert,
+    pub acknowledged: Option<DateTime<Local>>,
+    /// last time the alert was sent, either the original or a reminder
+    pub last_sent: DateTime<Local>,
+}
+
+impl AlertLogEntry {
+    pub fn is_acknowledged(&self) -> bool {
+        self.acknowledged.is_some()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AlertLogData {
+    next_id: u64,
+    entries: Vec<AlertLogEntry>,
+}
+
+/// Orders the background writes so an older save can't overwrite a newer one
+#[derive(Default)]
+struct SaveState {
+    lock: parking_lot::Mutex<()>,
+    latest: AtomicU64,
+}
+
+/// Every alert that was sent, shared between the AlertManager and the UI
+#[derive(Clone, Default)]
+pub struct AlertLog {
+    data: Arc<RwLock<AlertLogData>>,
+    save_state: Arc<SaveState>,
+}
+
+impl AlertLog {
+    const FILENAME: &'static str = "alert_log.json";
+    /// oldest acknowledged entries are dropped past this
+    const MAX_ENTRIES: usize = 1000;
+
+    /// a missing or unreadable log starts empty
+    pub fn read_or_default() -> Self {
+        let data = match std::fs::read_to_string(Self::FILENAME) {
+            Ok(s) => match serde_json::from_str(&s) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("failed to parse alert log: {:?}", e);
+                    AlertLogData::default()
+                }
+            },
+            Err(_) => AlertLogData::default(),
+        };
+        Self {
+            data: Arc::new(RwLock::new(data)),
+            save_state: Arc::default(),
+        }
+    }
+
+    /// serialized here, written on another thread so the UI doesn't wait on the disk
+    fn save(&self) {
+        let s = match serde_json::to_string(&*self.data.read()) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("failed to save alert log: {:?}", e);
+                return;
+            }
+        };
+
+        let save_state = self.save_state.clone();
+        let gen = save_state.latest.fetch_add(1, Ordering::SeqCst) + 1;
+        std::thread::spawn(move || {
+            let _guard = save_state.lock.lock();
+            /// a newer save is waiting
+            if save_state.latest.load(Ordering::SeqCst) != gen {
+                return;
+            }
+            /// renamed into place so exiting mid-write can't truncate the log
+            let tmp = format!("{}.tmp", Self::FILENAME);
+            let res = std::fs::write(&tmp, s).and_then(|_| std::fs::rename(&tmp, Self::FILENAME));
+            if let Err(e) = res {
+                error!("failed to save alert log: {:?}", e);
+            }
+        });
+    }
+
+    pub fn push(&self, alert: Alert) {
+        {
+            let mut data = self.data.write();
+            let id = data.next_id;
+            data.next_id += 1;
+
+            let acknowledged = if alert.event.needs_ack() {
+                None
+            } else {
+                Some(alert.time)
+            };
+
+            data.entries.push(AlertLogEntry {
+                id,
+                last_sent: alert.time,
+                alert,
+                acknowledged,
+            });
+
+            while data.entries.len() > Self::MAX_ENTRIES {
+                let Some(i) = data.entries.iter().position(|e| e.is_acknowledged()) else {
+                    break;
+                };
+                data.entries.remove(i);
+            }
+        }
+        self.save();
+    }
+
+    pub fn acknowledge(&self, id: u64) {
+        {
+            let mut data = self.data.write();
+            if let Some(entry) = data.entries.iter_mut().find(|e| e.id == id) {
+                entry.acknowledged.get_or_insert(Local::now());
+            }
+        }
+        self.save();
+    }
+
+    pub fn acknowledge_all(&self) {
+        let now = Local::now();
+        {
+            let mut data = self.data.write();
+            for entry in data.entries.iter_mut() {
+                entry.acknowledged.get_or_insert(now);
+            }
+        }
+        self.save();
+    }
+
+    pub fn num_unacknowledged(&self) -> usize {
+        self.data
+            .read()
+            .entries
+            .iter()
+            .filter(|e| !e.is_acknowledged())
+            .count()
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.data.read().entries.len()
+    }
+
+    /// `i`th newest entry, only the rows being drawn are copied
+    pub fn newest(&self, i: usize) -> Option<AlertLogEntry> {
+        self.data.read().entries.iter().rev().nth(i).cloned()
+    }
+
+    /// Unacknowledged alerts that haven't been sent for `interval`,
+    /// marked as sent again
+    pub fn take_reminders(&self, interval: chrono::Duration) -> Vec<Alert> {
+        let now = Local::now();
+        let mut out = vec![];
+        {
+            let mut data = self.data.write();
+            for entry in data.entries.iter_mut() {
+                if !entry.is_acknowledged() && now - entry.last_sent >= interval {
+                    entry.last_sent = now;
+                    out.push(entry.alert.clone());
+                }
+            }
+        }
+        if !out.is_empty() {
+            self.save();
+        }
+        out
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    config::MilestoneConfig,
+    conn_manager::PrinterId,
+    status::{bambu::PrinterStatus, PrinterState},
+};
+
+use super::{Alert, AlertEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Milestone {
+    Percent(i64),
+    FirstLayer,
+    Layer(i64),
+    /// minutes remaining
+    Eta(i64),
+}
+
+struct JobMilestones {
+    subtask_id: String,
+    fired: HashSet<Milestone>,
+}
+
+/// Tracks which progress milestones have already fired for each printer's current job,
+/// forgotten when the job ends
+#[derive(Default)]
+pub struct MilestoneTracker {
+    jobs: HashMap<PrinterId, JobMilestones>,
+}
+
+impl MilestoneTracker {
+    /// Returns the milestones reached since the last update, each fires once per `subtask_id`
+    pub fn update(
+        &mut self,
+        id: &PrinterId,
+        name: &str,
+        status: &PrinterStatus,
+        config: &MilestoneConfig,
+    ) -> Vec<Alert> {
+        match status.state {
+            PrinterState::Printing => {}
+            /// the job is over, SD card and LAN prints all report subtask "0"
+            /// so the next one can't be told apart by id
+            PrinterState::Idle | PrinterState::Finished | PrinterState::Error(_) => {
+                self.jobs.remove(id);
+                return vec![];
+            }
+            _ => return vec![],
+        }
+        let Some(subtask_id) = status.subtask_id.as_ref() else {
+            return vec![];
+        };
+
+        let reached = Self::reached(status, config);
+
+        let job = match self.jobs.get_mut(id) {
+            Some(job) if &job.subtask_id == subtask_id => job,
+            _ => {
+                /// first seen partway through a job (e.g. app was restarted),
+                /// don't fire everything that has already passed
+                let fired = if status.print_percent.unwrap_or(0) > 0 {
+                    reached.iter().copied().collect()
+                } else {
+                    HashSet::new()
+                };
+                self.jobs.insert(
+                    id.clone(),
+                    JobMilestones {
+                        subtask_id: subtask_id.clone(),
+                        fired,
+                    },
+                );
+                self.jobs.get_mut(id).unwrap()
+            }
+        };
+
+        let file = status.current_file.as_deref().unwrap_or("Unknown File");
+
+        let mut out = vec![];
+        for m in reached {
+            if !job.fired.insert(m) {
+                continue;
+            }
+            debug!("milestone reached: {:?} {:?}", id, m);
+            let total_layers = status.total_layer_num.unwrap_or(0);
+            let (event, title) = match m {
+                Milestone::Percent(p) => (AlertEvent::Milestone, format!("{}: {}% done", name, p)),
+                Milestone::FirstLayer => {
+                    (AlertEvent::Milestone, format!("{}: First layer done", name))
+                }
+                Milestone::Layer(l) => (
+                    AlertEvent::Milestone,
+                    format!("{}: Layer {} of {} reached", name, l, total_layers),
+                ),
+                Milestone::Eta(mins) => (
+                    AlertEvent::FinishingSoon,
+                    format!("{}: Finishing within {} minutes", name, mins),
+                ),
+            };
+            out.push(Alert::new(id.clone(), name, event, title, file.to_string()));
+        }
+        out
+    }
+
+    fn reached(status: &PrinterStatus, config: &MilestoneConfig) -> Vec<Milestone> {
+        let mut out = vec![];
+
+        if let Some(percent) = status.print_percent {
+            for &p in config.percent.iter() {
+                if percent >= p {
+                    out.push(Milestone::Percent(p));
+                }
+            }
+        }
+
+        if let Some(layer) = status.layer_num {
+            /// layer_num is the layer currently printing
+            if config.first_layer && layer >= 2 {
+                out.push(Milestone::FirstLayer);
+            }
+            for &l in config.layers.iter() {
+                if layer >= l {
+                    out.push(Milestone::Layer(l));
+                }
+            }
+        }
+
+        /// remaining time isn't reliable until the print has actually started
+        if let (Some(eta), Some(1..)) = (status.eta, status.print_percent) {
+            let remaining = (eta - chrono::Local::now()).num_minutes();
+            for &mins in config.eta_minutes.iter() {
+                if remaining <= mins {
+                    out.push(Milestone::Eta(mins));
+                }
+            }
+        }
+
+        out
+    }
+}
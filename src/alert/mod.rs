@@ -0,0 +1,279 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+pub mod anomaly;
+pub mod channels;
+pub mod humidity;
+pub mod log;
+pub mod milestones;
+
+use chrono::{DateTime, Local};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use self::log::AlertLog;
+
+use crate::{
+    cloud::streaming::{JpegStreamViewer, WebcamSnapshot},
+    config::ConfigArc,
+    conn_manager::PrinterId,
+    status::{bambu::PrinterStatus, PrinterType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlertEvent {
+    PrintComplete,
+    PrinterError,
+    Milestone,
+    FinishingSoon,
+    TemperatureAnomaly,
+    BedCooled,
+    AmsHumidity,
+    FilamentMismatch,
+    FilamentSwapStalled,
+}
+
+impl AlertEvent {
+    /// stays unacknowledged in the log and is repeated until acknowledged
+    pub fn needs_ack(&self) -> bool {
+        matches!(
+            self,
+            Self::PrinterError | Self::TemperatureAnomaly | Self::FilamentSwapStalled
+        )
+    }
+
+    pub fn to_text(&self) -> &'static str {
+        match self {
+            Self::PrintComplete => "Print Complete",
+            Self::PrinterError => "Printer Error",
+            Self::Milestone => "Milestone",
+            Self::FinishingSoon => "Finishing Soon",
+            Self::TemperatureAnomaly => "Temperature Warning",
+            Self::BedCooled => "Bed Cooled",
+            Self::AmsHumidity => "AMS Humidity",
+            Self::FilamentMismatch => "Filament Mismatch",
+            Self::FilamentSwapStalled => "Filament Swap Stalled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub time: DateTime<Local>,
+    pub printer: PrinterId,
+    pub printer_name: String,
+    pub event: AlertEvent,
+    pub title: String,
+    pub body: String,
+}
+
+impl Alert {
+    pub fn new(
+        printer: PrinterId,
+        printer_name: &str,
+        event: AlertEvent,
+        title: String,
+        body: String,
+    ) -> Self {
+        Self {
+            time: Local::now(),
+            printer,
+            printer_name: printer_name.to_string(),
+            event,
+            title,
+            body,
+        }
+    }
+
+    pub fn print_complete(printer: PrinterId, name: &str, file: &str) -> Self {
+        Self::new(
+            printer,
+            name,
+            AlertEvent::PrintComplete,
+            format!("Print Complete on {}", name),
+            file.to_string(),
+        )
+    }
+
+    pub fn printer_error(printer: PrinterId, name: &str, error: &str) -> Self {
+        Self::new(
+            printer,
+            name,
+            AlertEvent::PrinterError,
+            format!("Printer Error: {}", name),
+            format!("Printer error: {:?}\n\nError: {:?}", name, error),
+        )
+    }
+
+    pub fn bed_cooled(printer: PrinterId, name: &str, threshold: f64) -> Self {
+        Self::new(
+            printer,
+            name,
+            AlertEvent::BedCooled,
+            format!("Bed Cooled on {}", name),
+            format!("Bed cooled below {:.0}°C, safe to remove", threshold),
+        )
+    }
+
+    pub fn filament_swap_stalled(printer: PrinterId, name: &str, step: &str) -> Self {
+        Self::new(
+            printer,
+            name,
+            AlertEvent::FilamentSwapStalled,
+            format!("Filament Swap Stalled on {}", name),
+            format!(
+                "No progress on \"{}\" for over {} minutes",
+                step,
+                crate::status::FILAMENT_SWAP_STALL.as_secs() / 60
+            ),
+        )
+    }
+
+    pub fn reminder(&self) -> Self {
+        Self {
+            title: format!("Reminder: {}", self.title),
+            ..self.clone()
+        }
+    }
+}
+
+/// Receives alerts from PrinterConnManager and sends them out on every configured channel
+pub struct AlertManager {
+    config: ConfigArc,
+    alert_rx: tokio::sync::mpsc::UnboundedReceiver<Alert>,
+    snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+    printer_states: Arc<DashMap<PrinterId, PrinterStatus>>,
+    log: AlertLog,
+    client: reqwest::Client,
+}
+
+impl AlertManager {
+    /// how long to wait for a fresh frame before using the last one received
+    const SNAPSHOT_TIMEOUT: u64 = 15;
+    /// how often to check for unacknowledged alerts that are due a reminder
+    const REMINDER_CHECK_SECS: u64 = 30;
+
+    pub fn new(
+        config: ConfigArc,
+        alert_rx: tokio::sync::mpsc::UnboundedReceiver<Alert>,
+        snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+        printer_states: Arc<DashMap<PrinterId, PrinterStatus>>,
+        log: AlertLog,
+    ) -> Self {
+        Self {
+            config,
+            alert_rx,
+            snapshots,
+            printer_states,
+            log,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let mut reminder_interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(Self::REMINDER_CHECK_SECS));
+
+        loop {
+            tokio::select! {
+                alert = self.alert_rx.recv() => {
+                    let Some(alert) = alert else {
+                        return Ok(());
+                    };
+                    debug!("got alert: {:?}", alert.title);
+                    self.log.push(alert.clone());
+                    self.send(alert);
+                }
+                _ = reminder_interval.tick() => {
+                    let mins = self.config.alerts().reminder_interval;
+                    if mins == 0 {
+                        continue;
+                    }
+                    for alert in self.log.take_reminders(chrono::Duration::minutes(mins as i64)) {
+                        debug!("sending reminder: {:?}", alert.title);
+                        self.send(alert.reminder());
+                    }
+                }
+            }
+        }
+    }
+
+    fn send(&self, alert: Alert) {
+        let config = self.config.clone();
+        let snapshots = self.snapshots.clone();
+        let client = self.client.clone();
+        let printer_type = self
+            .printer_states
+            .get(&alert.printer)
+            .and_then(|s| s.printer_type);
+
+        /// fetching a snapshot can take a few seconds, don't hold up other alerts
+        tokio::spawn(async move {
+            let snapshot =
+                Self::get_snapshot(&config, &snapshots, &alert.printer, printer_type).await;
+            Self::dispatch(&config, &client, &alert, snapshot).await;
+        });
+    }
+
+    async fn get_snapshot(
+        config: &ConfigArc,
+        snapshots: &DashMap<PrinterId, WebcamSnapshot>,
+        id: &PrinterId,
+        printer_type: Option<PrinterType>,
+    ) -> Option<Arc<Vec<u8>>> {
+        let alerts = config.alerts();
+        if !alerts.snapshots {
+            return None;
+        }
+
+        /// X1 cameras don't serve the JPEG stream, but their RTSP stream
+        /// keeps the latest frame under a second old
+        let rtsp = matches!(printer_type, Some(PrinterType::X1C | PrinterType::X1E));
+
+        if alerts.fresh_snapshot && !rtsp {
+            if let Some(printer) = config.get_printer(id) {
+                if !printer.read().await.host.is_empty() {
+                    match tokio::time::timeout(
+                        tokio::time::Duration::from_secs(Self::SNAPSHOT_TIMEOUT),
+                        JpegStreamViewer::fetch_snapshot(printer),
+                    )
+                    .await
+                    {
+                        Ok(Ok(jpeg)) => return Some(Arc::new(jpeg)),
+                        Ok(Err(e)) => warn!("failed to fetch snapshot: {:?}", e),
+                        Err(_) => warn!("timeout fetching snapshot"),
+                    }
+                }
+            }
+        }
+
+        snapshots.get(id).map(|s| s.jpeg.clone())
+    }
+
+    async fn dispatch(
+        config: &ConfigArc,
+        client: &reqwest::Client,
+        alert: &Alert,
+        snapshot: Option<Arc<Vec<u8>>>,
+    ) {
+        let alerts = config.alerts();
+        let snapshot = snapshot.as_deref().map(|s| s.as_slice());
+
+        if let Err(e) = channels::send_desktop(alert, snapshot).await {
+            error!("failed to show notification: {:?}", e);
+        }
+
+        if let Some(url) = alerts.webhook_url.as_ref() {
+            if let Err(e) = channels::send_webhook(client, url, alert, snapshot).await {
+                error!("failed to send webhook: {:?}", e);
+            }
+        }
+
+        if let Some(email) = alerts.email.as_ref() {
+            if let Err(e) = channels::send_email(email, alert, snapshot).await {
+                error!("failed to send email: {:?}", e);
+            }
+        }
+    }
+}
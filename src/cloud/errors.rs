@@ -35,7 +35,8 @@ impl ErrorMap {
         }
     }
 
-    fn read_error_codes() -> Result<Self> {
+    /// only the cached file, for when waiting on a fetch isn't an option
+    pub fn read_error_codes() -> Result<Self> {
         let file = File::open("errors.json")?;
         let reader = std::io::BufReader::new(file);
         let codes: Self = serde_json::from_reader(reader)?;
@@ -202,39 +202,22 @@ impl project_data::ProjectData {
 
         let plate_id = *content.get(0).context("no plates")?;
 
-        let plate = {
-            let plate = json.profiles[0]
-                .context
-                .plates
-                .iter()
-                .find(|p| p.index == plate_id)
-                .context("no plate")?;
-
-            let mut filaments = vec![];
-
-            if let Some(filaments_json) = plate.filaments.as_ref() {
-                for filament in filaments_json.iter() {
-                    let c = egui::Color32::from_hex(&filament.color).unwrap_or_default();
-                    filaments.push(project_data::Filament {
-                        color: [c.r(), c.g(), c.b()],
-                        id: filament.id.parse()?,
-                        type_field: filament.type_field.clone(),
-                        used_g: filament.used_g.parse()?,
-                        used_m: filament.used_m.parse()?,
-                    });
-                }
-            }
-
-            project_data::Plate {
-                index: plate.index,
-                pick_picture: plate.pick_picture.clone(),
-                top_picture: plate.top_picture.clone(),
-                thumbnail: plate.thumbnail.clone(),
-                weight: plate.weight.unwrap_or_default(),
-                time: plate.prediction.unwrap_or_default(),
-                filaments,
-            }
-        };
+        let plate = json.profiles[0]
+            .context
+            .plates
+            .iter()
+            .find(|p| p.index == plate_id)
+            .context("no plate")?;
+        let plate = Self::plate_from_json(plate)?;
+
+        /// every sliced plate, for checking filament before printing
+        let plates = json.profiles[0]
+            .context
+            .plates
+            .iter()
+            .filter(|p| p.filaments.as_ref().is_some_and(|f| !f.is_empty()))
+            .map(Self::plate_from_json)
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(Self {
             name: json.name,
@@ -245,6 +228,34 @@ impl project_data::ProjectData {
             create_time,
             materials,
             plate,
+            plates,
+        })
+    }
+
+    fn plate_from_json(plate: &project_data_json::Plate) -> Result<project_data::Plate> {
+        let mut filaments = vec![];
+
+        if let Some(filaments_json) = plate.filaments.as_ref() {
+            for filament in filaments_json.iter() {
+                let c = egui::Color32::from_hex(&filament.color).unwrap_or_default();
+                filaments.push(project_data::Filament {
+                    color: [c.r(), c.g(), c.b()],
+                    id: filament.id.parse()?,
+                    type_field: filament.type_field.clone(),
+                    used_g: filament.used_g.parse()?,
+                    used_m: filament.used_m.parse()?,
+                });
+            }
+        }
+
+        Ok(project_data::Plate {
+            index: plate.index,
+            pick_picture: plate.pick_picture.clone(),
+            top_picture: plate.top_picture.clone(),
+            thumbnail: plate.thumbnail.clone(),
+            weight: plate.weight.unwrap_or_default(),
+            time: plate.prediction.unwrap_or_default(),
+            filaments,
         })
     }
 }
@@ -269,6 +280,9 @@ pub mod task_data {
         pub length: i64,
         pub cost_time: i64,
         pub plate_index: i64,
+        /// grams used from each tray
+        #[serde(default)]
+        pub ams_detail_mapping: Vec<super::AmsDetailMapping>,
     }
 
     impl TaskData {
@@ -292,6 +306,7 @@ pub mod task_data {
                 length: json.length,
                 cost_time: json.cost_time,
                 plate_index: json.plate_index,
+                ams_detail_mapping: json.ams_detail_mapping.clone(),
             }
         }
     }
@@ -313,6 +328,8 @@ pub mod project_data {
         // pub print_time: i64,
         pub materials: Vec<([u8; 3], String)>,
         pub plate: Plate,
+        /// sliced plates with filament
+        pub plates: Vec<Plate>,
     }
 
     #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
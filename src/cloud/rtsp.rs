@@ -1 +1,360 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
 
+use dashmap::DashMap;
+use futures::StreamExt;
+use openh264::formats::YUVSource;
+use retina::codec::{CodecItem, ParametersRef};
+use rumqttc::tokio_rustls::rustls;
+use std::{sync::Arc, time::Instant};
+use tokio::sync::RwLock;
+
+use crate::{
+    cloud::streaming::{tls_connector, WebcamSnapshot, WebcamTexture},
+    config::PrinterConfig,
+    conn_manager::PrinterId,
+};
+
+/// X1 series camera, RTSPS on port 322.
+/// retina only speaks plain RTSP, so it connects through a local TLS proxy
+pub struct RtspStreamViewer {
+    id: PrinterId,
+    config: Arc<RwLock<PrinterConfig>>,
+    handle: egui::TextureHandle,
+    handles: Arc<DashMap<PrinterId, WebcamTexture>>,
+    snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+    kill_rx: tokio::sync::oneshot::Receiver<()>,
+}
+
+impl RtspStreamViewer {
+    const PORT: u16 = 322;
+    const PATH: &'static str = "/streaming/live/1";
+    const STREAM_TIMEOUT: u64 = 10;
+    /// seconds between JPEG snapshots, encoding every frame is too slow
+    const SNAPSHOT_INTERVAL: u64 = 1;
+    const JPEG_QUALITY: u8 = 85;
+    /// frames waiting to be decoded before the session waits on the decoder
+    const FRAME_QUEUE: usize = 8;
+    /// seconds before reconnecting after an error
+    pub(crate) const RETRY_DELAY: u64 = 5;
+
+    pub(crate) fn new(
+        id: PrinterId,
+        config: Arc<RwLock<PrinterConfig>>,
+        handle: egui::TextureHandle,
+        handles: Arc<DashMap<PrinterId, WebcamTexture>>,
+        snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+        kill_rx: tokio::sync::oneshot::Receiver<()>,
+    ) -> Self {
+        Self {
+            id,
+            config,
+            handle,
+            handles,
+            snapshots,
+            kill_rx,
+        }
+    }
+
+    /// Listens on a random local port and forwards each connection to the printer over TLS
+    async fn spawn_tls_proxy(
+        host: String,
+        serial: String,
+    ) -> Result<(u16, tokio::task::JoinHandle<()>)> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let connector = tls_connector(&serial);
+
+        let task = tokio::task::spawn(async move {
+            loop {
+                let mut local = match listener.accept().await {
+                    Ok((local, _)) => local,
+                    Err(e) => {
+                        error!("rtsp proxy accept error: {:?}", e);
+                        return;
+                    }
+                };
+                let host = host.clone();
+                let connector = connector.clone();
+                tokio::task::spawn(async move {
+                    let res: Result<()> = async {
+                        let stream =
+                            tokio::net::TcpStream::connect(format!("{}:{}", host, Self::PORT))
+                                .await?;
+                        let domain = rustls::pki_types::ServerName::try_from(host)?;
+                        let mut remote = connector.connect(domain, stream).await?;
+                        tokio::io::copy_bidirectional(&mut local, &mut remote).await?;
+                        Ok(())
+                    }
+                    .await;
+                    if let Err(e) = res {
+                        debug!("rtsp proxy connection closed: {:?}", e);
+                    }
+                });
+            }
+        });
+
+        Ok((port, task))
+    }
+
+    pub(crate) async fn run(&mut self) -> Result<()> {
+        let (host, serial, access_code) = {
+            let config = self.config.read().await;
+            (
+                config.host.clone(),
+                (*config.serial).clone(),
+                config.access_code.clone(),
+            )
+        };
+
+        let (port, proxy) = Self::spawn_tls_proxy(host, serial).await?;
+        let res = self.stream(port, access_code).await;
+        proxy.abort();
+        res
+    }
+
+    async fn stream(&mut self, port: u16, access_code: String) -> Result<()> {
+        let url = url::Url::parse(&format!("rtsp://127.0.0.1:{}{}", port, Self::PATH))?;
+        let options = retina::client::SessionOptions::default()
+            .creds(Some(retina::client::Credentials {
+                username: "bblp".to_string(),
+                password: access_code,
+            }))
+            .user_agent("bambu_watcher".to_string());
+
+        let mut session = retina::client::Session::describe(url, options).await?;
+        let stream_i = session
+            .streams()
+            .iter()
+            .position(|s| s.media() == "video" && s.encoding_name() == "h264")
+            .context("no h264 stream")?;
+        session
+            .setup(stream_i, retina::client::SetupOptions::default())
+            .await?;
+        let session = session
+            .play(retina::client::PlayOptions::default())
+            .await?
+            .demuxed()?;
+        debug!("rtsp stream playing: {:?}", self.id);
+
+        /// decoding and encoding run on a blocking thread, fed through a channel
+        let (frame_tx, frame_rx) = tokio::sync::mpsc::channel(Self::FRAME_QUEUE);
+        let decoder = FrameDecoder {
+            id: self.id.clone(),
+            handle: self.handle.clone(),
+            handles: self.handles.clone(),
+            snapshots: self.snapshots.clone(),
+            last_snapshot: None,
+        };
+        let decode_task = tokio::task::spawn_blocking(move || decoder.run(frame_rx));
+
+        let res = self.receive(session, stream_i, frame_tx).await;
+        /// the channel is closed now, so the decoder stops after the queued frames
+        decode_task.await??;
+        res
+    }
+
+    async fn receive(
+        &mut self,
+        mut session: retina::client::Demuxed,
+        stream_i: usize,
+        frame_tx: tokio::sync::mpsc::Sender<Vec<u8>>,
+    ) -> Result<()> {
+        let mut parameters = match session.streams()[stream_i].parameters() {
+            Some(ParametersRef::Video(p)) => avc_config_to_annex_b(p.extra_data())?,
+            _ => vec![],
+        };
+
+        loop {
+            /// sender is dropped or fired by StreamManager::stop_stream
+            if !matches!(
+                self.kill_rx.try_recv(),
+                Err(tokio::sync::oneshot::error::TryRecvError::Empty)
+            ) {
+                debug!("stream killed: {:?}", self.id);
+                break;
+            }
+
+            let item = match tokio::time::timeout(
+                tokio::time::Duration::from_secs(Self::STREAM_TIMEOUT),
+                session.next(),
+            )
+            .await
+            {
+                Ok(Some(item)) => item?,
+                /// an error so StreamManager reconnects
+                Ok(None) => bail!("rtsp stream ended"),
+                Err(_) => {
+                    warn!("timeout reading from rtsp stream");
+                    bail!("timeout reading from rtsp stream");
+                }
+            };
+
+            let CodecItem::VideoFrame(frame) = item else {
+                continue;
+            };
+
+            if frame.has_new_parameters() {
+                if let Some(ParametersRef::Video(p)) = session.streams()[stream_i].parameters() {
+                    parameters = avc_config_to_annex_b(p.extra_data())?;
+                }
+            }
+
+            /// every frame has to be decoded, later frames depend on it
+            let mut nal = vec![];
+            if frame.is_random_access_point() {
+                nal.extend_from_slice(&parameters);
+            }
+            avcc_to_annex_b(frame.data(), &mut nal)?;
+
+            if frame_tx.send(nal).await.is_err() {
+                /// the error is returned from the decode task
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes frames from [`RtspStreamViewer`] into the texture and JPEG snapshots
+struct FrameDecoder {
+    id: PrinterId,
+    handle: egui::TextureHandle,
+    handles: Arc<DashMap<PrinterId, WebcamTexture>>,
+    snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+    last_snapshot: Option<Instant>,
+}
+
+impl FrameDecoder {
+    fn run(mut self, mut frame_rx: tokio::sync::mpsc::Receiver<Vec<u8>>) -> Result<()> {
+        let mut decoder = openh264::decoder::Decoder::new()?;
+        while let Some(nal) = frame_rx.blocking_recv() {
+            let yuv = match decoder.decode(&nal) {
+                Ok(Some(yuv)) => yuv,
+                Ok(None) => continue,
+                Err(e) => {
+                    /// usually frames before the first keyframe
+                    debug!("failed to decode frame: {:?}", e);
+                    continue;
+                }
+            };
+            self.show(&yuv)?;
+        }
+        Ok(())
+    }
+
+    fn show(&mut self, yuv: &openh264::decoder::DecodedYUV) -> Result<()> {
+        let (width, height) = yuv.dimensions();
+
+        /// only convert when the stream is actually shown
+        let visible = self
+            .handles
+            .get(&self.id)
+            .map(|h| h.enabled)
+            .unwrap_or(false);
+
+        if visible {
+            let mut rgba = vec![0u8; width * height * 4];
+            yuv.write_rgba8(&mut rgba);
+            let img = egui::ColorImage::from_rgba_unmultiplied([width, height], &rgba);
+            self.handle.set(img, Default::default());
+        }
+
+        let snapshot_due = self
+            .last_snapshot
+            .map(|t| t.elapsed().as_secs() >= RtspStreamViewer::SNAPSHOT_INTERVAL)
+            .unwrap_or(true);
+        if snapshot_due {
+            let mut rgb = vec![0u8; width * height * 3];
+            yuv.write_rgb8(&mut rgb);
+
+            let mut jpeg = vec![];
+            image::codecs::jpeg::JpegEncoder::new_with_quality(
+                &mut jpeg,
+                RtspStreamViewer::JPEG_QUALITY,
+            )
+            .encode(
+                &rgb,
+                width as u32,
+                height as u32,
+                image::ExtendedColorType::Rgb8,
+            )?;
+
+            self.snapshots
+                .insert(self.id.clone(), WebcamSnapshot::new(jpeg));
+            self.last_snapshot = Some(Instant::now());
+        }
+        Ok(())
+    }
+}
+
+/// retina gives NALs with 4 byte big endian lengths, openh264 wants start codes
+fn avcc_to_annex_b(data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let mut data = data;
+    while !data.is_empty() {
+        ensure!(data.len() >= 4, "truncated nal length");
+        let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        ensure!(data.len() >= 4 + len, "truncated nal");
+        out.extend_from_slice(&[0, 0, 0, 1]);
+        out.extend_from_slice(&data[4..4 + len]);
+        data = &data[4 + len..];
+    }
+    Ok(())
+}
+
+/// SPS and PPS from an AVCDecoderConfigurationRecord, ISO/IEC 14496-15 5.2.4.1
+fn avc_config_to_annex_b(config: &[u8]) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    ensure!(config.len() >= 6, "avc config too short");
+    let mut pos = 5;
+
+    /// number of SPS, then number of PPS after them
+    for mask in [0x1f, 0xff] {
+        ensure!(config.len() > pos, "avc config too short");
+        let count = config[pos] & mask;
+        pos += 1;
+        for _ in 0..count {
+            ensure!(config.len() >= pos + 2, "avc config too short");
+            let len = u16::from_be_bytes([config[pos], config[pos + 1]]) as usize;
+            pos += 2;
+            ensure!(config.len() >= pos + len, "avc config too short");
+            out.extend_from_slice(&[0, 0, 0, 1]);
+            out.extend_from_slice(&config[pos..pos + len]);
+            pos += len;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_prefixed_to_start_codes() {
+        let mut out = vec![];
+        avcc_to_annex_b(&[0, 0, 0, 2, 0x65, 0xaa, 0, 0, 0, 1, 0x41], &mut out).unwrap();
+        assert_eq!(out, [0, 0, 0, 1, 0x65, 0xaa, 0, 0, 0, 1, 0x41]);
+
+        assert!(avcc_to_annex_b(&[0, 0, 0, 5, 0x65], &mut vec![]).is_err());
+        assert!(avcc_to_annex_b(&[0, 0], &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn sps_and_pps_from_config() {
+        let config = [
+            1, 0x64, 0, 0x1f, 0xff, // version, profile, compat, level, length size
+            0xe1, 0, 2, 0x67, 0x64, // one SPS
+            1, 0, 1, 0x68, // one PPS
+        ];
+        assert_eq!(
+            avc_config_to_annex_b(&config).unwrap(),
+            [0, 0, 0, 1, 0x67, 0x64, 0, 0, 0, 1, 0x68]
+        );
+
+        assert!(avc_config_to_annex_b(&config[..9]).is_err());
+        assert!(avc_config_to_annex_b(&[1, 0x64]).is_err());
+    }
+}
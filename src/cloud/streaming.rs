@@ -9,6 +9,7 @@ use tokio::{io::AsyncReadExt, sync::RwLock};
 use crate::{
     config::{ConfigArc, PrinterConfig},
     conn_manager::PrinterId,
+    status::{bambu::PrinterStatus, PrinterType},
 };
 
 #[derive(Debug, Clone)]
@@ -20,7 +21,7 @@ pub enum StreamCmd {
 }
 
 #[derive(Debug, Clone)]
-enum StreamMsg {
+pub(crate) enum StreamMsg {
     Panic(PrinterId),
 }
 
@@ -36,10 +37,28 @@ impl WebcamTexture {
     }
 }
 
+/// Latest frame received from a printer's camera, kept even while the stream is hidden
+#[derive(Clone)]
+pub struct WebcamSnapshot {
+    pub time: chrono::DateTime<chrono::Local>,
+    pub jpeg: Arc<Vec<u8>>,
+}
+
+impl WebcamSnapshot {
+    pub fn new(jpeg: Vec<u8>) -> Self {
+        Self {
+            time: chrono::Local::now(),
+            jpeg: Arc::new(jpeg),
+        }
+    }
+}
+
 pub struct StreamManager {
     configs: ConfigArc,
     // streams: HashMap<PrinterId, JpegStreamViewer>,
     handles: Arc<DashMap<PrinterId, WebcamTexture>>,
+    snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+    printer_states: Arc<DashMap<PrinterId, PrinterStatus>>,
     kill_tx: HashMap<PrinterId, tokio::sync::oneshot::Sender<()>>,
     cmd_rx: tokio::sync::mpsc::UnboundedReceiver<StreamCmd>,
     stream_tx: tokio::sync::mpsc::UnboundedSender<StreamMsg>,
@@ -52,6 +71,8 @@ impl StreamManager {
         configs: ConfigArc,
         // configs: ConfigArc,
         handles: Arc<DashMap<PrinterId, WebcamTexture>>,
+        snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+        printer_states: Arc<DashMap<PrinterId, PrinterStatus>>,
         cmd_rx: tokio::sync::mpsc::UnboundedReceiver<StreamCmd>,
         ctx: egui::Context,
     ) -> Self {
@@ -60,6 +81,8 @@ impl StreamManager {
             configs,
             // streams: HashMap::new(),
             handles,
+            snapshots,
+            printer_states,
             kill_tx: HashMap::new(),
             cmd_rx,
             stream_rx,
@@ -97,16 +120,56 @@ impl StreamManager {
             return Ok(());
         }
 
+        /// restarted by PrinterConnManager once the printer info arrives
+        let Some(printer_type) = self.printer_states.get(&id).and_then(|s| s.printer_type) else {
+            debug!("waiting for printer type before streaming: {:?}", id);
+            return Ok(());
+        };
+
         let handle = self.handles.get(&id).unwrap().handle.clone();
 
         let (kill_tx, kill_rx) = tokio::sync::oneshot::channel();
         self.kill_tx.insert(id.clone(), kill_tx);
 
         let msg_tx = self.stream_tx.clone();
+        let handles = self.handles.clone();
+        let snapshots = self.snapshots.clone();
+
+        /// X1 cameras use RTSP, the others use the JPEG stream on port 6000
+        if matches!(printer_type, PrinterType::X1C | PrinterType::X1E) {
+            tokio::task::spawn(async move {
+                let mut streamer = crate::cloud::rtsp::RtspStreamViewer::new(
+                    id.clone(),
+                    config,
+                    handle,
+                    handles,
+                    snapshots,
+                    kill_rx,
+                );
+                if let Err(e) = streamer.run().await {
+                    error!("rtsp streamer error: {:?}", e);
+                    /// connection errors fail immediately, don't spin
+                    tokio::time::sleep(tokio::time::Duration::from_secs(
+                        crate::cloud::rtsp::RtspStreamViewer::RETRY_DELAY,
+                    ))
+                    .await;
+                    msg_tx.send(StreamMsg::Panic(id)).unwrap();
+                }
+            });
+            return Ok(());
+        }
 
         tokio::task::spawn(async move {
-            if let Ok(mut streamer) =
-                JpegStreamViewer::new(id.clone(), config, handle, kill_rx, msg_tx.clone()).await
+            if let Ok(mut streamer) = JpegStreamViewer::new(
+                id.clone(),
+                config,
+                handle,
+                handles,
+                snapshots,
+                kill_rx,
+                msg_tx.clone(),
+            )
+            .await
             {
                 if let Err(e) = streamer.run().await {
                     error!("streamer error: {:?}", e);
@@ -185,10 +248,20 @@ impl StreamManager {
                             self.start_stream(id, false).await;
                         }
                         Some(StreamCmd::ToggleStream(id)) => {
-                            if self.kill_tx.contains_key(&id) {
-                                self.stop_stream(id, true).await
-                            } else {
-                                self.start_stream(id, true).await
+                            let running = self.kill_tx.contains_key(&id);
+                            let visible =
+                                self.handles.get(&id).map(|h| h.enabled).unwrap_or(false);
+                            let keep_frames =
+                                self.configs.alerts().snapshots || self.timelapse_enabled(&id).await;
+                            match (running, visible) {
+                                /// keep receiving frames for alert snapshots and timelapses,
+                                /// only hide the stream
+                                (true, true) if keep_frames => {
+                                    self.set_visible(&id, false)
+                                }
+                                (true, true) => self.stop_stream(id, true).await,
+                                (true, false) => self.set_visible(&id, true),
+                                (false, _) => self.start_stream(id, true).await,
                             }
                         }
                     }
@@ -211,6 +284,19 @@ impl StreamManager {
         }
     }
 
+    async fn timelapse_enabled(&self, id: &PrinterId) -> bool {
+        match self.configs.get_printer(id) {
+            Some(config) => config.read().await.timelapse.is_some(),
+            None => false,
+        }
+    }
+
+    fn set_visible(&self, id: &PrinterId, visible: bool) {
+        if let Some(mut entry) = self.handles.get_mut(id) {
+            entry.enabled = visible;
+        }
+    }
+
     async fn stop_stream(&mut self, id: PrinterId, set_enabled: bool) {
         // debug!("stopping stream: {:?}", id);
         if let Some(kill_tx) = self.kill_tx.remove(&id) {
@@ -234,6 +320,19 @@ impl StreamManager {
     // }
 }
 
+/// The printers use self-signed certificates, checked against the serial instead
+pub(crate) fn tls_connector(serial: &str) -> tokio_rustls::TlsConnector {
+    let client_config = rustls::ClientConfig::builder()
+        // .with_root_certificates(root_cert_store)
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(crate::mqtt::NoCertificateVerification {
+            serial: serial.to_string(),
+        }))
+        .with_no_client_auth();
+
+    tokio_rustls::TlsConnector::from(Arc::new(client_config))
+}
+
 /// https://github.com/greghesp/ha-bambulab/blob/main/custom_components/bambu_lab/pybambu/bambu_client.py#L68
 pub struct JpegStreamViewer {
     id: PrinterId,
@@ -245,6 +344,8 @@ pub struct JpegStreamViewer {
     // img_tx: tokio::sync::watch::Sender<Vec<u8>>,
     // ctx: egui::Context,
     handle: egui::TextureHandle,
+    handles: Arc<DashMap<PrinterId, WebcamTexture>>,
+    snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
     kill_rx: tokio::sync::oneshot::Receiver<()>,
     msg_tx: tokio::sync::mpsc::UnboundedSender<StreamMsg>,
 }
@@ -275,9 +376,37 @@ impl JpegStreamViewer {
         // img_tx: tokio::sync::watch::Sender<Vec<u8>>,
         // ctx: egui::Context,
         handle: egui::TextureHandle,
+        handles: Arc<DashMap<PrinterId, WebcamTexture>>,
+        snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
         kill_rx: tokio::sync::oneshot::Receiver<()>,
         msg_tx: tokio::sync::mpsc::UnboundedSender<StreamMsg>,
     ) -> Result<Self> {
+        let (tls_stream, auth_data) = Self::connect(&config).await?;
+
+        Ok(Self {
+            id,
+            config: config.clone(),
+            // config,
+            // addr,
+            auth_data,
+            tls_stream,
+            buf: [0u8; Self::READ_CHUNK_SIZE],
+            // img_tx,
+            handle,
+            handles,
+            snapshots,
+            // ctx,
+            kill_rx,
+            msg_tx,
+        })
+    }
+
+    async fn connect(
+        config: &Arc<RwLock<PrinterConfig>>,
+    ) -> Result<(
+        tokio_rustls::client::TlsStream<tokio::net::TcpStream>,
+        Vec<u8>,
+    )> {
         // let config = &configs.get_printer(&id).unwrap();
         let serial = config.read().await.serial.clone();
         let host = config
@@ -290,20 +419,7 @@ impl JpegStreamViewer {
         let addr = format!("{}:6000", host);
         let access_code = config.read().await.access_code.clone();
 
-        let mut root_cert_store = rustls::RootCertStore::empty();
-        root_cert_store.add_parsable_certificates(
-            rustls_native_certs::load_native_certs().expect("could not load platform certs"),
-        );
-
-        let client_config = rustls::ClientConfig::builder()
-            // .with_root_certificates(root_cert_store)
-            .dangerous()
-            .with_custom_certificate_verifier(Arc::new(crate::mqtt::NoCertificateVerification {
-                serial: (*serial).clone(),
-            }))
-            .with_no_client_auth();
-
-        let connector = rumqttc::tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let connector = tls_connector(&serial);
 
         // debug!("Jpeg Viewer Connecting");
         let stream = tokio::net::TcpStream::connect(addr).await?;
@@ -340,58 +456,64 @@ impl JpegStreamViewer {
             auth_data
         };
 
-        Ok(Self {
-            id,
-            config: config.clone(),
-            // config,
-            // addr,
-            auth_data,
-            tls_stream,
-            buf: [0u8; Self::READ_CHUNK_SIZE],
-            // img_tx,
-            handle,
-            // ctx,
-            kill_rx,
-            msg_tx,
-        })
+        Ok((tls_stream, auth_data))
     }
 
-    async fn run(&mut self) -> Result<()> {
-        tokio::io::AsyncWriteExt::write_all(&mut self.tls_stream, &self.auth_data).await?;
+    async fn send_auth(
+        tls_stream: &mut tokio_rustls::client::TlsStream<tokio::net::TcpStream>,
+        auth_data: &[u8],
+    ) -> Result<()> {
+        tokio::io::AsyncWriteExt::write_all(tls_stream, auth_data).await?;
 
         debug!("getting socket status");
-        let status = self.tls_stream.get_ref().0.take_error();
+        let status = tls_stream.get_ref().0.take_error();
         if !matches!(status, Ok(None)) {
             error!("socket status = {:?}", status);
             bail!("socket status = {:?}", status);
         }
         debug!("socket status ok, running loop");
+        Ok(())
+    }
 
+    /// Connects to the camera separately from any running stream and returns a single JPEG frame
+    pub async fn fetch_snapshot(config: Arc<RwLock<PrinterConfig>>) -> Result<Vec<u8>> {
+        let (mut tls_stream, auth_data) = Self::connect(&config).await?;
+        Self::send_auth(&mut tls_stream, &auth_data).await?;
+        let mut buf = [0u8; Self::READ_CHUNK_SIZE];
+        Self::read_frame(&mut tls_stream, &mut buf)
+            .await?
+            .context("camera closed the stream")
+    }
+
+    /// Reads until a full JPEG frame has arrived,
+    /// returns None if the stream was closed or the frame was malformed
+    async fn read_frame(
+        tls_stream: &mut tokio_rustls::client::TlsStream<tokio::net::TcpStream>,
+        buf: &mut [u8; Self::READ_CHUNK_SIZE],
+    ) -> Result<Option<Vec<u8>>> {
         let mut payload_size = 0;
         let mut img_buf: Vec<u8> = vec![];
         let mut got_header = false;
 
         loop {
-            self.buf.fill(0);
+            buf.fill(0);
 
             let n = match tokio::time::timeout(
                 tokio::time::Duration::from_secs(Self::STREAM_TIMEOUT),
-                self.tls_stream.read(&mut self.buf),
+                tls_stream.read(buf),
             )
             .await
             {
                 Ok(n) => n?,
                 Err(_) => {
                     warn!("timeout reading from stream");
-                    self.msg_tx.send(StreamMsg::Panic(self.id.clone())).unwrap();
                     bail!("timeout reading from stream");
                 }
             };
-            // let n = self.tls_stream.read(&mut self.buf).await?;
 
             if got_header {
                 // debug!("extending image by {}", n);
-                img_buf.extend_from_slice(&self.buf[..n]);
+                img_buf.extend_from_slice(&buf[..n]);
 
                 if img_buf.len() > payload_size {
                     warn!(
@@ -408,43 +530,23 @@ impl JpegStreamViewer {
                     // break;
                 }
                 if img_buf.len() == payload_size {
-                    if &img_buf[0..4] != &Self::JPEG_START {
+                    if img_buf[0..4] != Self::JPEG_START {
                         warn!("missing jpeg start bytes");
-                        break;
-                    } else if &img_buf[payload_size - 2..payload_size - 0] != &Self::JPEG_END {
+                        return Ok(None);
+                    } else if img_buf[payload_size - 2..payload_size] != Self::JPEG_END {
                         warn!("missing jpeg end bytes");
-                        break;
+                        return Ok(None);
                     }
 
                     // debug!("got image");
-                    /// use image crate to write jpeg to file
-                    // let mut f = std::fs::File::create("test.jpg")?;
-                    // std::io::Write::write_all(&mut f, &img)?;
-                    let image = match image::load_from_memory(&img_buf) {
-                        Ok(image) => image,
-                        Err(e) => {
-                            error!("failed to load image: {}", e);
-                            break;
-                        }
-                    };
-                    let img_size = [image.width() as _, image.height() as _];
-                    let image_buffer = image.to_rgba8();
-                    let pixels = image_buffer.as_flat_samples();
-                    let img = egui::ColorImage::from_rgba_unmultiplied(img_size, pixels.as_slice());
-
-                    self.handle.set(img, Default::default());
-
-                    got_header = false;
-                    img_buf.clear();
+                    return Ok(Some(img_buf));
                 }
             } else if n == 16 {
                 // debug!("got header");
-                // img.extend_from_slice(&buf);
 
                 // payload_size = int.from_bytes(dr[0:3], byteorder='little')
-                // payload_size = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
                 payload_size =
-                    <byteorder::LittleEndian as byteorder::ByteOrder>::read_u32(&self.buf[0..4])
+                    <byteorder::LittleEndian as byteorder::ByteOrder>::read_u32(&buf[0..4])
                         as usize;
 
                 // debug!("payload_size = {}", payload_size);
@@ -453,8 +555,56 @@ impl JpegStreamViewer {
 
             if n == 0 {
                 debug!("wrong access code");
+                return Ok(None);
+            }
+        }
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        Self::send_auth(&mut self.tls_stream, &self.auth_data).await?;
+
+        loop {
+            /// sender is dropped or fired by StreamManager::stop_stream
+            if !matches!(
+                self.kill_rx.try_recv(),
+                Err(tokio::sync::oneshot::error::TryRecvError::Empty)
+            ) {
+                debug!("stream killed: {:?}", self.id);
                 break;
             }
+
+            let Some(jpeg) = Self::read_frame(&mut self.tls_stream, &mut self.buf).await? else {
+                break;
+            };
+
+            /// only decode when the stream is actually shown
+            let visible = self
+                .handles
+                .get(&self.id)
+                .map(|h| h.enabled)
+                .unwrap_or(false);
+
+            if visible {
+                /// use image crate to write jpeg to file
+                // let mut f = std::fs::File::create("test.jpg")?;
+                // std::io::Write::write_all(&mut f, &img)?;
+                let image = match image::load_from_memory(&jpeg) {
+                    Ok(image) => image,
+                    Err(e) => {
+                        error!("failed to load image: {}", e);
+                        break;
+                    }
+                };
+                let img_size = [image.width() as _, image.height() as _];
+                let image_buffer = image.to_rgba8();
+                let pixels = image_buffer.as_flat_samples();
+                let img = egui::ColorImage::from_rgba_unmultiplied(img_size, pixels.as_slice());
+
+                self.handle.set(img, Default::default());
+            }
+
+            self.snapshots
+                .insert(self.id.clone(), WebcamSnapshot::new(jpeg));
         }
 
         Ok(())
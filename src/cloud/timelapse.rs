@@ -0,0 +1,401 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use dashmap::DashMap;
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Instant};
+
+use crate::{
+    cloud::streaming::WebcamSnapshot,
+    config::{ConfigArc, TimelapseConfig, TimelapseOutput},
+    conn_manager::PrinterId,
+    status::{bambu::PrinterStatus, PrinterState},
+};
+
+/// Frames being saved for one job
+struct Capture {
+    subtask_id: String,
+    dir: PathBuf,
+    config: TimelapseConfig,
+    frames: usize,
+    last_layer: Option<i64>,
+    last_capture: Option<Instant>,
+    /// time of the last saved snapshot, so the same frame isn't saved twice
+    last_frame: Option<chrono::DateTime<chrono::Local>>,
+}
+
+impl Capture {
+    fn new(id: &PrinterId, subtask_id: String, config: TimelapseConfig) -> Result<Self> {
+        /// jobs started from the SD card don't have a subtask
+        let name = if subtask_id.trim().is_empty() || subtask_id == "0" {
+            format!("{}_{}", id, chrono::Local::now().format("%Y%m%d_%H%M%S"))
+        } else {
+            subtask_id.clone()
+        };
+        let dir = PathBuf::from(&config.dir).join(name);
+        std::fs::create_dir_all(&dir).context("creating timelapse dir")?;
+
+        /// app was restarted partway through the job
+        let frames = frame_paths(&dir)?.len();
+        if frames > 0 {
+            debug!("resuming timelapse at frame {}: {:?}", frames, dir);
+        }
+
+        Ok(Self {
+            subtask_id,
+            dir,
+            config,
+            frames,
+            last_layer: None,
+            last_capture: None,
+            last_frame: None,
+        })
+    }
+
+    fn due(&self, state: &PrinterState, layer: Option<i64>) -> bool {
+        if *state != PrinterState::Printing {
+            return false;
+        }
+        match self.config.interval {
+            Some(secs) => self
+                .last_capture
+                .map(|t| t.elapsed().as_secs() >= secs)
+                .unwrap_or(true),
+            None => matches!(layer, Some(l) if l > 0) && layer != self.last_layer,
+        }
+    }
+
+    /// returns false if there was no new frame to save
+    async fn save_frame(&mut self, snapshot: Option<WebcamSnapshot>) -> Result<bool> {
+        let Some(snapshot) = snapshot else {
+            return Ok(false);
+        };
+        if Some(snapshot.time) <= self.last_frame
+            || (chrono::Local::now() - snapshot.time).num_seconds()
+                > TimelapseManager::MAX_FRAME_AGE
+        {
+            return Ok(false);
+        }
+
+        let path = self.dir.join(format!("{:05}.jpg", self.frames));
+        tokio::fs::write(&path, snapshot.jpeg.as_slice())
+            .await
+            .with_context(|| format!("writing frame: {:?}", path))?;
+
+        self.frames += 1;
+        self.last_frame = Some(snapshot.time);
+        self.last_capture = Some(Instant::now());
+        Ok(true)
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.frames == 0 {
+            debug!("no timelapse frames for job {}", self.subtask_id);
+            let _ = std::fs::remove_dir(&self.dir);
+            return Ok(());
+        }
+
+        match self.config.output {
+            TimelapseOutput::Images => {
+                info!("saved {} timelapse frames to {:?}", self.frames, self.dir);
+            }
+            TimelapseOutput::Avi => {
+                let frames = frame_paths(&self.dir)?;
+                let path = self.dir.with_extension("avi");
+                /// falls back to keeping the images
+                if let Err(e) = write_mjpeg_avi(&path, &frames, self.config.fps) {
+                    let _ = std::fs::remove_file(&path);
+                    warn!(
+                        "failed to write timelapse video, kept {} frames in {:?}: {:?}",
+                        self.frames, self.dir, e
+                    );
+                    return Ok(());
+                }
+                info!("saved timelapse: {:?}", path);
+
+                if !self.config.keep_frames {
+                    std::fs::remove_dir_all(&self.dir).context("removing timelapse frames")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Saves the latest camera frame on each layer change or on an interval,
+/// and assembles the frames when the job ends
+pub struct TimelapseManager {
+    config: ConfigArc,
+    printer_states: Arc<DashMap<PrinterId, PrinterStatus>>,
+    snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+    captures: HashMap<PrinterId, Capture>,
+    /// subtask whose capture couldn't be started, not retried until the job ends
+    failed: HashMap<PrinterId, String>,
+}
+
+impl TimelapseManager {
+    /// seconds, older snapshots mean the stream has stalled
+    const MAX_FRAME_AGE: i64 = 30;
+
+    pub fn new(
+        config: ConfigArc,
+        printer_states: Arc<DashMap<PrinterId, PrinterStatus>>,
+        snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>>,
+    ) -> Self {
+        Self {
+            config,
+            printer_states,
+            snapshots,
+            captures: HashMap::new(),
+            failed: HashMap::new(),
+        }
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            for id in self.config.printer_ids_async().await {
+                if let Err(e) = self.update(&id).await {
+                    error!("timelapse error: {:?}", e);
+                }
+            }
+        }
+    }
+
+    async fn update(&mut self, id: &PrinterId) -> Result<()> {
+        let Some(printer) = self.config.get_printer(id) else {
+            return Ok(());
+        };
+        let config = printer.read().await.timelapse.clone();
+
+        /// copied out so the map isn't locked across awaits
+        let Some((state, subtask_id, layer)) = self
+            .printer_states
+            .get(id)
+            .map(|s| (s.state.clone(), s.subtask_id.clone(), s.layer_num))
+        else {
+            return Ok(());
+        };
+
+        let ended = matches!(
+            state,
+            PrinterState::Idle | PrinterState::Finished | PrinterState::Error(_)
+        );
+        let changed_job = self
+            .captures
+            .get(id)
+            .map(|c| Some(&c.subtask_id) != subtask_id.as_ref())
+            .unwrap_or(false);
+
+        if ended {
+            self.failed.remove(id);
+        }
+        if ended || changed_job || config.is_none() {
+            if let Some(mut capture) = self.captures.remove(id) {
+                /// the finished print
+                if ended {
+                    let _ = capture.save_frame(self.snapshot(id)).await;
+                }
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = capture.finish() {
+                        error!("failed to assemble timelapse: {:?}", e);
+                    }
+                });
+            }
+        }
+
+        let (Some(config), Some(subtask_id)) = (config, subtask_id) else {
+            return Ok(());
+        };
+        if state != PrinterState::Printing {
+            return Ok(());
+        }
+
+        if !self.captures.contains_key(id) {
+            if self.failed.get(id) == Some(&subtask_id) {
+                return Ok(());
+            }
+            debug!("starting timelapse for job {}", subtask_id);
+            match Capture::new(id, subtask_id.clone(), config) {
+                Ok(capture) => {
+                    self.captures.insert(id.clone(), capture);
+                }
+                Err(e) => {
+                    self.failed.insert(id.clone(), subtask_id);
+                    return Err(e);
+                }
+            }
+        }
+
+        let snapshot = self.snapshot(id);
+        let capture = self.captures.get_mut(id).unwrap();
+        if capture.due(&state, layer) && capture.save_frame(snapshot).await? {
+            capture.last_layer = layer;
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self, id: &PrinterId) -> Option<WebcamSnapshot> {
+        self.snapshots.get(id).map(|s| s.clone())
+    }
+}
+
+/// sorted `NNNNN.jpg` files in the dir
+fn frame_paths(dir: &std::path::Path) -> Result<Vec<PathBuf>> {
+    let mut out = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("jpg") {
+            out.push(path);
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Writes the JPEG frames unchanged into an AVI container,
+/// every frame is a keyframe so the index is just the chunk offsets
+fn write_mjpeg_avi(path: &std::path::Path, frames: &[PathBuf], fps: u32) -> Result<()> {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use std::io::Write;
+
+    const AVIF_HASINDEX: u32 = 0x10;
+    const AVIIF_KEYFRAME: u32 = 0x10;
+    /// AVI 1.0 players fail on larger files
+    const MAX_AVI_SIZE: u64 = 1 << 30;
+
+    ensure!(!frames.is_empty(), "no frames");
+    let fps = fps.max(1);
+
+    let first = std::fs::read(&frames[0])?;
+    let image = image::load_from_memory(&first).context("decoding first frame")?;
+    let (width, height) = (image.width(), image.height());
+
+    let mut sizes = vec![];
+    for frame in frames.iter() {
+        let size = std::fs::metadata(frame)?.len();
+        ensure!(size <= MAX_AVI_SIZE, "frame too large: {:?}", frame);
+        sizes.push(size as u32);
+    }
+    let max_size = sizes.iter().copied().max().unwrap_or(0);
+    let padded = |n: u32| n + (n & 1);
+
+    /// summed as u64, the limit keeps every size and offset below in a u32
+    fn checked_sum(sizes: impl IntoIterator<Item = u64>) -> Result<u64> {
+        sizes.into_iter().try_fold(0u64, |acc, n| {
+            acc.checked_add(n)
+                .filter(|&n| n <= MAX_AVI_SIZE)
+                .context("timelapse too large for an AVI file")
+        })
+    }
+    /// 'movi' + each '00dc' chunk
+    let movi_size =
+        checked_sum(std::iter::once(4).chain(sizes.iter().map(|&n| 8 + padded(n) as u64)))?;
+    let idx_size = checked_sum(std::iter::repeat(16).take(frames.len()))?;
+    let strl_size: u32 = 4 + (8 + 56) + (8 + 40);
+    let hdrl_size: u32 = 4 + (8 + 56) + (8 + strl_size);
+    let riff_size = checked_sum([4, 8 + hdrl_size as u64, 8 + movi_size, 8 + idx_size])?;
+    let (movi_size, idx_size, riff_size) = (movi_size as u32, idx_size as u32, riff_size as u32);
+
+    let file = std::fs::File::create(path).with_context(|| format!("creating {:?}", path))?;
+    let mut w = std::io::BufWriter::new(file);
+
+    w.write_all(b"RIFF")?;
+    w.write_u32::<LittleEndian>(riff_size)?;
+    w.write_all(b"AVI ")?;
+
+    w.write_all(b"LIST")?;
+    w.write_u32::<LittleEndian>(hdrl_size)?;
+    w.write_all(b"hdrl")?;
+
+    /// main header
+    w.write_all(b"avih")?;
+    w.write_u32::<LittleEndian>(56)?;
+    w.write_u32::<LittleEndian>(1_000_000 / fps)?;
+    w.write_u32::<LittleEndian>(max_size.saturating_mul(fps))?;
+    w.write_u32::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(AVIF_HASINDEX)?;
+    w.write_u32::<LittleEndian>(frames.len() as u32)?;
+    w.write_u32::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(1)?;
+    w.write_u32::<LittleEndian>(max_size)?;
+    w.write_u32::<LittleEndian>(width)?;
+    w.write_u32::<LittleEndian>(height)?;
+    for _ in 0..4 {
+        w.write_u32::<LittleEndian>(0)?;
+    }
+
+    w.write_all(b"LIST")?;
+    w.write_u32::<LittleEndian>(strl_size)?;
+    w.write_all(b"strl")?;
+
+    /// stream header
+    w.write_all(b"strh")?;
+    w.write_u32::<LittleEndian>(56)?;
+    w.write_all(b"vids")?;
+    w.write_all(b"MJPG")?;
+    w.write_u32::<LittleEndian>(0)?;
+    w.write_u16::<LittleEndian>(0)?;
+    w.write_u16::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(1)?;
+    w.write_u32::<LittleEndian>(fps)?;
+    w.write_u32::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(frames.len() as u32)?;
+    w.write_u32::<LittleEndian>(max_size)?;
+    w.write_u32::<LittleEndian>(u32::MAX)?;
+    w.write_u32::<LittleEndian>(0)?;
+    w.write_u16::<LittleEndian>(0)?;
+    w.write_u16::<LittleEndian>(0)?;
+    w.write_u16::<LittleEndian>(width as u16)?;
+    w.write_u16::<LittleEndian>(height as u16)?;
+
+    /// BITMAPINFOHEADER
+    w.write_all(b"strf")?;
+    w.write_u32::<LittleEndian>(40)?;
+    w.write_u32::<LittleEndian>(40)?;
+    w.write_i32::<LittleEndian>(width as i32)?;
+    w.write_i32::<LittleEndian>(height as i32)?;
+    w.write_u16::<LittleEndian>(1)?;
+    w.write_u16::<LittleEndian>(24)?;
+    w.write_all(b"MJPG")?;
+    w.write_u32::<LittleEndian>(width * height * 3)?;
+    for _ in 0..4 {
+        w.write_u32::<LittleEndian>(0)?;
+    }
+
+    w.write_all(b"LIST")?;
+    w.write_u32::<LittleEndian>(movi_size)?;
+    w.write_all(b"movi")?;
+
+    for (frame, &size) in frames.iter().zip(sizes.iter()) {
+        let data = std::fs::read(frame)?;
+        ensure!(
+            data.len() as u32 == size,
+            "frame changed while writing: {:?}",
+            frame
+        );
+        w.write_all(b"00dc")?;
+        w.write_u32::<LittleEndian>(size)?;
+        w.write_all(&data)?;
+        if size & 1 == 1 {
+            w.write_u8(0)?;
+        }
+    }
+
+    /// offsets are from the 'movi' fourcc
+    w.write_all(b"idx1")?;
+    w.write_u32::<LittleEndian>(idx_size)?;
+    let mut offset = 4;
+    for &size in sizes.iter() {
+        w.write_all(b"00dc")?;
+        w.write_u32::<LittleEndian>(AVIIF_KEYFRAME)?;
+        w.write_u32::<LittleEndian>(offset)?;
+        w.write_u32::<LittleEndian>(size)?;
+        offset += 8 + padded(size);
+    }
+
+    w.flush()?;
+    Ok(())
+}
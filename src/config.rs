@@ -35,10 +35,7 @@ impl Default for ConfigArc {
             //     logged_in: false,
             //     printers: HashMap::new(),
             // })),
-            config: Config {
-                ids: Arc::new(RwLock::new(HashSet::new())),
-                printers: Arc::new(DashMap::new()),
-            },
+            config: Config::empty(),
             auth: Arc::new(RwLock::new(crate::auth::AuthDb::empty())),
             logged_in: Arc::new(AtomicBool::new(false)),
         }
@@ -104,6 +101,7 @@ impl ConfigArc {
         config.name = cfg.name.clone();
         config.host = cfg.host.clone();
         config.access_code = cfg.access_code.clone();
+        config.group = Some(cfg.group.trim().to_string()).filter(|g| !g.is_empty());
         // config.color = cfg.color;
     }
 
@@ -146,6 +144,22 @@ impl ConfigArc {
     pub fn get_printer(&self, serial: &PrinterId) -> Option<Arc<RwLock<PrinterConfig>>> {
         self.config.printers.get(serial).map(|v| v.clone())
     }
+
+    pub fn alerts(&self) -> Arc<AlertConfig> {
+        self.config.alerts.clone()
+    }
+
+    pub fn graphs(&self) -> Arc<GraphConfig> {
+        self.config.graphs.clone()
+    }
+
+    pub fn spools(&self) -> Arc<SpoolConfig> {
+        self.config.spools.clone()
+    }
+
+    pub fn costs(&self) -> Arc<CostConfig> {
+        self.config.costs.clone()
+    }
 }
 
 #[derive(Clone)]
@@ -156,11 +170,23 @@ pub struct Config {
     // printers: Arc<DashMap<PrinterId, Arc<RwLock<PrinterConfig>>>>,
     ids: Arc<RwLock<HashSet<PrinterId>>>,
     printers: Arc<DashMap<PrinterId, Arc<RwLock<PrinterConfig>>>>,
+    alerts: Arc<AlertConfig>,
+    graphs: Arc<GraphConfig>,
+    spools: Arc<SpoolConfig>,
+    costs: Arc<CostConfig>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ConfigFile {
     printers: Vec<PrinterConfig>,
+    #[serde(default)]
+    alerts: AlertConfig,
+    #[serde(default)]
+    graphs: GraphConfig,
+    #[serde(default)]
+    spools: SpoolConfig,
+    #[serde(default)]
+    costs: CostConfig,
 }
 
 impl Config {
@@ -168,6 +194,10 @@ impl Config {
         Self {
             ids: Arc::new(RwLock::new(HashSet::new())),
             printers: Arc::new(DashMap::new()),
+            alerts: Arc::new(AlertConfig::default()),
+            graphs: Arc::new(GraphConfig::default()),
+            spools: Arc::new(SpoolConfig::default()),
+            costs: Arc::new(CostConfig::default()),
         }
     }
 
@@ -193,6 +223,10 @@ impl Config {
             // logged_in,
             // auth,
             printers: Arc::new(printers),
+            alerts: Arc::new(config.alerts),
+            graphs: Arc::new(config.graphs),
+            spools: Arc::new(config.spools),
+            costs: Arc::new(config.costs),
         };
 
         Ok((out, auth))
@@ -213,6 +247,15 @@ pub struct PrinterConfig {
     // pub cloud: std::sync::atomic::AtomicBool,
     #[serde(default)]
     pub color: [u8; 3],
+    /// for filtering statistics, e.g. a room or a customer
+    #[serde(default)]
+    pub group: Option<String>,
+    /// average power draw while printing, for electricity costs
+    #[serde(default)]
+    pub watts: Option<f64>,
+    /// save camera frames while printing, disabled if unset
+    #[serde(default)]
+    pub timelapse: Option<TimelapseConfig>,
 }
 
 impl PrinterConfig {
@@ -225,10 +268,244 @@ impl PrinterConfig {
             access_code: device.dev_access_code.clone(),
             serial: id,
             color: [0, 0, 0],
+            group: None,
+            watts: None,
+            timelapse: None,
+        }
+    }
+}
+
+/// Frames are saved to `<dir>/<subtask_id>/` while a job is printing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimelapseConfig {
+    /// seconds between frames, one frame per layer if unset
+    pub interval: Option<u64>,
+    pub output: TimelapseOutput,
+    /// playback speed of the assembled video
+    pub fps: u32,
+    /// keep the numbered frames after the video is written
+    pub keep_frames: bool,
+    pub dir: String,
+}
+
+impl Default for TimelapseConfig {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            output: TimelapseOutput::Avi,
+            fps: 15,
+            keep_frames: false,
+            dir: "timelapse".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelapseOutput {
+    /// MJPEG in an AVI container
+    Avi,
+    /// only the numbered JPEG frames
+    Images,
+}
+
+/// Where alerts are sent, besides the desktop notification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    /// attach the latest webcam frame to alerts
+    pub snapshots: bool,
+    /// grab a new frame from the camera when the alert fires,
+    /// falls back to the latest frame if the camera doesn't respond
+    pub fresh_snapshot: bool,
+    /// POSTed as multipart/form-data
+    pub webhook_url: Option<String>,
+    pub email: Option<EmailConfig>,
+    /// minutes between reminders for unacknowledged errors, 0 to disable
+    pub reminder_interval: u64,
+    pub milestones: MilestoneConfig,
+    pub temperature: TemperatureAnomalyConfig,
+    pub humidity: HumidityAlertConfig,
+    /// compare the loaded filament with the job's cloud task when a print starts
+    pub filament_check: bool,
+    /// °C, alert when a finished print's bed has cooled below this
+    pub bed_cooled: Option<f64>,
+}
+
+/// Progress notifications, each fires at most once per job
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MilestoneConfig {
+    /// e.g. [50, 90]
+    pub percent: Vec<i64>,
+    pub first_layer: bool,
+    pub layers: Vec<i64>,
+    /// alert when the ETA is within this many minutes
+    pub eta_minutes: Vec<i64>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            snapshots: true,
+            fresh_snapshot: false,
+            webhook_url: None,
+            email: None,
+            reminder_interval: 15,
+            milestones: MilestoneConfig::default(),
+            temperature: TemperatureAnomalyConfig::default(),
+            humidity: HumidityAlertConfig::default(),
+            filament_check: true,
+            bed_cooled: None,
+        }
+    }
+}
+
+/// Early warnings of thermistor, heater or fan failures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemperatureAnomalyConfig {
+    pub enabled: bool,
+    /// minutes a heater has to reach its target
+    pub heat_timeout: u64,
+    /// °C either side of the target
+    pub tolerance: f64,
+    /// seconds outside the tolerance band during a print before alerting
+    pub drift_time: u64,
+    /// °C rise allowed while the target is 0
+    pub rise_while_off: f64,
+}
+
+impl Default for TemperatureAnomalyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            heat_timeout: 10,
+            tolerance: 10.,
+            drift_time: 60,
+            rise_while_off: 15.,
+        }
+    }
+}
+
+/// Reminders to dry the filament when an AMS stays humid
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HumidityAlertConfig {
+    pub enabled: bool,
+    /// from 1 (dry) to 5 (wet)
+    pub level: u8,
+    /// minutes at or above the level before alerting
+    pub minutes: u64,
+}
+
+impl Default for HumidityAlertConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            level: 4,
+            minutes: 60,
+        }
+    }
+}
+
+/// How long telemetry is kept, older data is averaged into coarser buckets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphConfig {
+    /// minutes kept at full resolution
+    pub full_resolution: u64,
+    /// hours kept as 1 minute buckets
+    pub minute_buckets: u64,
+    /// days kept as 15 minute buckets, anything older is dropped
+    pub retention: u64,
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        Self {
+            full_resolution: 30,
+            minute_buckets: 24,
+            retention: 7,
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpoolConfig {
+    /// grams, spools below this are highlighted
+    pub low_weight: f64,
+    /// e.g. `http://192.168.1.10:7912`
+    pub spoolman_url: Option<String>,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            low_weight: 150.,
+            spoolman_url: None,
+        }
+    }
+}
+
+/// Prices for estimating the cost of each job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CostConfig {
+    pub currency: String,
+    /// per kg, by material, e.g. `PLA: 20.0`
+    pub filament_prices: HashMap<String, f64>,
+    /// per kg, for materials that aren't listed
+    pub default_filament_price: Option<f64>,
+    /// per kWh, no electricity cost if unset
+    pub electricity_rate: Option<f64>,
+    /// for printers without `watts` set
+    pub default_watts: f64,
+}
+
+impl Default for CostConfig {
+    fn default() -> Self {
+        Self {
+            currency: "$".to_string(),
+            filament_prices: HashMap::new(),
+            default_filament_price: None,
+            electricity_rate: None,
+            default_watts: 150.,
+        }
+    }
+}
+
+impl CostConfig {
+    /// per kg
+    pub fn filament_price(&self, material: &str) -> Option<f64> {
+        self.filament_prices
+            .iter()
+            .find(|(m, _)| m.eq_ignore_ascii_case(material))
+            .map(|(_, p)| *p)
+            .or(self.default_filament_price)
+    }
+
+    pub fn format(&self, amount: f64) -> String {
+        format!("{}{:.2}", self.currency, amount)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    /// defaults to 587 (STARTTLS)
+    #[serde(default)]
+    pub smtp_port: Option<u16>,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
 #[cfg(feature = "nope")]
 mod old {
 
@@ -6,14 +6,27 @@ use tokio::sync::RwLock;
 
 // use bambulab::{Client as BambuClient, Message};
 use crate::{
+    alert::{
+        anomaly::AnomalyDetector, humidity::HumidityMonitor, milestones::MilestoneTracker, Alert,
+        AlertEvent,
+    },
     cloud::{errors::ErrorMap, streaming::StreamCmd},
     config::ConfigArc,
+    jobs::{
+        db::JobDb,
+        filament_check::{FilamentCheck, RequiredFilament},
+        JobRecord, JobTracker,
+    },
     mqtt::{
-        command::Command,
+        command::{ChangeAMSFilamentSetting, Command},
         message::{Message, PrintData},
         BambuClient,
     },
-    status::{bambu::PrinterStatus, PrinterType},
+    spools::{
+        spoolman::{SpoolmanClient, SpoolmanSpool},
+        KRecord, SpoolInventory,
+    },
+    status::{bambu::PrinterStatus, AmsCurrentSlot, AmsSlot, PrinterType},
     ui::ui_types::{NewPrinterEntry, ProjectsList},
 };
 use dashmap::DashMap;
@@ -35,7 +48,11 @@ pub enum PrinterConnMsg {
     StatusReport(PrinterId, PrintData),
     LoggedIn,
     SyncedProjects(crate::ui::ui_types::ProjectsList),
+    SyncedCloudProjects(Vec<crate::cloud::projects::ProjectData>),
     SyncedPrinters,
+    /// a job was started, updated or finished in the local history
+    JobHistoryChanged,
+    SpoolmanSpools(Vec<SpoolmanSpool>),
 }
 
 /// messages from UI to PrinterConnManager
@@ -49,6 +66,10 @@ pub enum PrinterConnCmd {
     SetPrinterCloud(PrinterId, bool),
 
     SyncProjects,
+    /// fetch the cloud projects and their sliced plates, for checking filament before printing
+    SyncCloudProjects,
+    /// fetch the spool list from Spoolman
+    SyncSpoolman,
 
     /// get the status of a printer
     ReportStatus(PrinterId),
@@ -57,6 +78,9 @@ pub enum PrinterConnCmd {
     Login(String, String),
     Logout,
 
+    /// part has been removed from a finished printer
+    ClearBed(PrinterId),
+
     /// Sent with QoS of 1 for higher priority.
     Pause,
     /// Sent with QoS of 1 for higher priority.
@@ -73,18 +97,18 @@ pub enum PrinterConnCmd {
     GCodeLine(String),
     Calibration,
 
-    UnloadFilament,
-
-    /// tray_id
-    ChangeFilament(i64),
-    ChangeAMSFilamentSetting {
-        ams_id: i64,
-        tray_id: i64,
-        tray_color: [u8; 3],
-        nozzle_temp_min: i64,
-        nozzle_temp_max: i64,
-        tray_type: String,
-    },
+    UnloadFilament(PrinterId),
+
+    /// load the filament in a tray or the external spool
+    ChangeFilament(PrinterId, AmsCurrentSlot),
+    /// set the filament in an AMS tray or the external spool
+    ChangeAMSFilamentSetting(PrinterId, ChangeAMSFilamentSetting),
+    /// list the saved pressure advance calibrations
+    GetKProfiles(PrinterId),
+    /// use a saved calibration for a tray by `cali_idx`, -1 for the default
+    SelectKProfile(PrinterId, AmsCurrentSlot, i64),
+    /// set a tray's K
+    SetK(PrinterId, AmsCurrentSlot, f64),
 }
 
 pub struct PrinterConnManager {
@@ -97,6 +121,14 @@ pub struct PrinterConnManager {
     msg_tx: tokio::sync::mpsc::UnboundedSender<PrinterConnMsg>,
     ctx: egui::Context,
     // alert_tx: tokio::sync::mpsc::Sender<(String, String)>,
+    alert_tx: tokio::sync::mpsc::UnboundedSender<Alert>,
+    milestones: MilestoneTracker,
+    anomalies: AnomalyDetector,
+    humidity: HumidityMonitor,
+    job_db: JobDb,
+    job_tracker: JobTracker,
+    spools: SpoolInventory,
+    spoolman: Option<SpoolmanClient>,
     tx: tokio::sync::mpsc::UnboundedSender<(PrinterId, Message)>,
     rx: tokio::sync::mpsc::UnboundedReceiver<(PrinterId, Message)>,
     kill_chans: HashMap<PrinterId, tokio::sync::oneshot::Sender<()>>,
@@ -121,6 +153,9 @@ impl PrinterConnManager {
         stream_cmd_tx: tokio::sync::mpsc::UnboundedSender<StreamCmd>,
         // win_handle: std::num::NonZeroIsize,
         // alert_tx: tokio::sync::mpsc::Sender<(String, String)>,
+        alert_tx: tokio::sync::mpsc::UnboundedSender<Alert>,
+        job_db: JobDb,
+        spools: SpoolInventory,
     ) -> Self {
         // let channel_size = if cfg!(debug_assertions) { 1 } else { 50 };
         // let (tx, mut rx) = tokio::sync::mpsc::channel::<(PrinterId, Message)>(channel_size);
@@ -129,6 +164,15 @@ impl PrinterConnManager {
         /// fetch error codes
         let error_map = ErrorMap::read_or_fetch().await.unwrap_or_default();
 
+        let spoolman_url = config.spools().spoolman_url.clone();
+        let spoolman = spoolman_url.and_then(|url| match SpoolmanClient::new(&url) {
+            Ok(client) => Some(client),
+            Err(e) => {
+                error!("failed to create spoolman client: {:?}", e);
+                None
+            }
+        });
+
         Self {
             config,
             printers: HashMap::new(),
@@ -137,6 +181,14 @@ impl PrinterConnManager {
             cmd_rx,
             msg_tx,
             ctx,
+            alert_tx,
+            milestones: MilestoneTracker::default(),
+            anomalies: AnomalyDetector::default(),
+            humidity: HumidityMonitor::default(),
+            job_tracker: JobTracker::new(&job_db),
+            job_db,
+            spools,
+            spoolman,
             tx,
             rx,
             kill_chans: HashMap::new(),
@@ -153,6 +205,9 @@ impl PrinterConnManager {
             // self.printers.insert(printer.serial.clone(), client);
             self.add_printer(printer.clone(), true).await?;
         }
+        if self.spoolman.is_some() {
+            self.cmd_tx.send(PrinterConnCmd::SyncSpoolman)?;
+        }
         Ok(())
     }
 
@@ -234,6 +289,12 @@ impl PrinterConnManager {
 
 /// handle messages, commands
 impl PrinterConnManager {
+    fn send_alert(&self, alert: Alert) {
+        if let Err(e) = self.alert_tx.send(alert) {
+            error!("error sending alert: {:?}", e);
+        }
+    }
+
     async fn handle_printer_msg(
         &mut self,
         // printer: Arc<PrinterConfig>,
@@ -286,20 +347,29 @@ impl PrinterConnManager {
                     if prev_state != PrinterState::Disconnected
                         && entry.state == PrinterState::Finished
                     {
+                        entry.awaiting_bed_clear = true;
+                        entry.bed_cooled_alerted = false;
+
                         warn!("sent finish notification");
-                        crate::alert::alert_print_complete(
+                        self.send_alert(Alert::print_complete(
+                            printer.serial.clone(),
                             &printer.name,
                             entry
                                 .current_file
                                 .as_ref()
                                 .unwrap_or(&"Unknown File".to_string()),
-                        )
+                        ));
                     }
 
                     /// either print just started, or app was just started
                     if entry.state == PrinterState::Printing && entry.subtask_id.is_some() {
                         entry.current_task_thumbnail_url = None;
                     }
+
+                    /// new job means the plate was cleared
+                    if entry.state == PrinterState::Printing {
+                        entry.clear_bed();
+                    }
                 }
 
                 /// logged in and printing, but no thumbnail
@@ -323,7 +393,118 @@ impl PrinterConnManager {
                     //
                 }
 
-                if !prev_error && entry.is_error() {
+                let alerts = self.config.alerts();
+
+                if let (Some(threshold), Some(temp)) = (alerts.bed_cooled, entry.temp_bed) {
+                    if entry.awaiting_bed_clear && !entry.bed_cooled_alerted && temp < threshold {
+                        entry.bed_cooled_alerted = true;
+                        self.send_alert(Alert::bed_cooled(
+                            printer.serial.clone(),
+                            &printer.name,
+                            threshold,
+                        ));
+                    }
+                }
+
+                if let Some(ams) = entry.ams.as_mut() {
+                    if ams.take_swap_stall() {
+                        let step = ams.swap_step().map(|s| s.to_text()).unwrap_or_default();
+                        warn!("filament swap stalled on {}: {}", printer.name, step);
+                        self.send_alert(Alert::filament_swap_stalled(
+                            printer.serial.clone(),
+                            &printer.name,
+                            step,
+                        ));
+                    }
+                }
+
+                for alert in self.milestones.update(
+                    &printer.serial,
+                    &printer.name,
+                    &entry,
+                    &alerts.milestones,
+                ) {
+                    self.send_alert(alert);
+                }
+                for alert in self.anomalies.update(
+                    &printer.serial,
+                    &printer.name,
+                    &entry,
+                    &alerts.temperature,
+                ) {
+                    self.send_alert(alert);
+                }
+                for alert in
+                    self.humidity
+                        .update(&printer.serial, &printer.name, &entry, &alerts.humidity)
+                {
+                    self.send_alert(alert);
+                }
+
+                /// the spool and job files are written without holding the map
+                let status = entry.clone();
+                let is_error = entry.is_error();
+                let needs_info = entry.printer_type.is_none();
+                drop(entry);
+
+                if let Some(ams) = status.ams.as_ref() {
+                    self.spools.sync_trays(&printer.serial, ams);
+                }
+
+                match self.job_tracker.update(
+                    &self.job_db,
+                    &self.spools,
+                    &self.error_map,
+                    &printer.serial,
+                    &printer.name,
+                    &status,
+                ) {
+                    Ok(true) => {
+                        let _ = self.msg_tx.send(PrinterConnMsg::JobHistoryChanged);
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!("error updating job history: {:?}", e),
+                }
+
+                let ended = self.job_tracker.take_ended();
+                /// so an export run from the command line has the whole job
+                if !ended.is_empty() {
+                    let graphs = self.graphs.clone();
+                    tokio::task::spawn_blocking(move || graphs.save());
+                }
+                for job in ended {
+                    let config2 = self.config.clone();
+                    let msg_tx2 = self.msg_tx.clone();
+                    let job_db = self.job_db.clone();
+                    let spools = self.spools.clone();
+                    let spoolman = self.spoolman.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) =
+                            record_job_usage(config2, msg_tx2, job_db, spools, spoolman, job.id)
+                                .await
+                        {
+                            error!("error recording filament usage: {:?}", e);
+                        }
+                    });
+                }
+
+                for job in self.job_tracker.take_started() {
+                    if !alerts.filament_check {
+                        continue;
+                    }
+                    let config2 = self.config.clone();
+                    let printer_states2 = self.printer_states.clone();
+                    let alert_tx = self.alert_tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) =
+                            check_job_filament(config2, printer_states2, alert_tx, job).await
+                        {
+                            error!("error checking filament: {:?}", e);
+                        }
+                    });
+                }
+
+                if !prev_error && is_error {
                     warn!("printer error: {:?}", &printer.name);
 
                     let error = report
@@ -345,7 +526,11 @@ impl PrinterConnManager {
                         .get_error(error as u64)
                         .unwrap_or("Unknown Error");
 
-                    crate::alert::alert_printer_error(&printer.name, error);
+                    self.send_alert(Alert::printer_error(
+                        printer.serial.clone(),
+                        &printer.name,
+                        error,
+                    ));
                 }
 
                 self.ctx.request_repaint();
@@ -357,7 +542,7 @@ impl PrinterConnManager {
                     error!("error sending status report: {:?}", e);
                 }
 
-                if entry.printer_type.is_none() {
+                if needs_info {
                     self.cmd_tx
                         .send(PrinterConnCmd::ReportInfo(printer.serial.clone()))?;
                 }
@@ -371,13 +556,17 @@ impl PrinterConnManager {
                     &printer.read().await.name
                 );
 
-                let mut entry = self
-                    .printer_states
-                    .entry(printer.read().await.serial.clone())
-                    .or_default();
+                let serial = printer.read().await.serial.clone();
+                let mut entry = self.printer_states.entry(serial.clone()).or_default();
 
+                let first_info = entry.printer_type.is_none();
                 entry.printer_type = Some(crate::utils::get_printer_type(&info.info));
 
+                /// the stream waits for the printer type to pick JPEG or RTSP
+                if first_info {
+                    self.stream_cmd_tx.send(StreamCmd::RestartStream(serial))?;
+                }
+
                 #[cfg(feature = "nope")]
                 for module in info.info.module.iter() {
                     // debug!("module {:?} = {:?}", module.name, module.project_name);
@@ -487,12 +676,37 @@ impl PrinterConnManager {
             PrinterConnCmd::SyncProjects => {
                 let config2 = self.config.clone();
                 let msg_tx2 = self.msg_tx.clone();
+                let job_db = self.job_db.clone();
+                let spools = self.spools.clone();
+                let spoolman = self.spoolman.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = sync_projects(config2, msg_tx2).await {
+                    if let Err(e) = sync_projects(config2, msg_tx2, job_db, spools, spoolman).await
+                    {
                         error!("error syncing projects: {:?}", e);
                     }
                 });
             }
+            PrinterConnCmd::SyncCloudProjects => {
+                let config2 = self.config.clone();
+                let msg_tx2 = self.msg_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = sync_cloud_projects(config2, msg_tx2).await {
+                        error!("error syncing cloud projects: {:?}", e);
+                    }
+                });
+            }
+            PrinterConnCmd::SyncSpoolman => {
+                let Some(spoolman) = self.spoolman.clone() else {
+                    warn!("spoolman_url is not set");
+                    return Ok(());
+                };
+                let msg_tx2 = self.msg_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = sync_spoolman(&spoolman, &msg_tx2).await {
+                        error!("error syncing spoolman: {:?}", e);
+                    }
+                });
+            }
             PrinterConnCmd::ReportInfo(id) => {
                 let client = self
                     .printers
@@ -556,6 +770,13 @@ impl PrinterConnManager {
                 }
             }
 
+            PrinterConnCmd::ClearBed(id) => {
+                if let Some(mut entry) = self.printer_states.get_mut(&id) {
+                    entry.clear_bed();
+                }
+                self.ctx.request_repaint();
+            }
+
             PrinterConnCmd::RemovePrinter(_) => todo!(),
             PrinterConnCmd::UpdatePrinterConfig(id, cfg) => {
                 self.config.update_printer(&id, &cfg).await;
@@ -572,21 +793,207 @@ impl PrinterConnManager {
             PrinterConnCmd::ChangeSpeed(_) => todo!(),
             PrinterConnCmd::GCodeLine(_) => todo!(),
             PrinterConnCmd::Calibration => todo!(),
-            PrinterConnCmd::UnloadFilament => todo!(),
-            PrinterConnCmd::ChangeFilament(_) => todo!(),
-            PrinterConnCmd::ChangeAMSFilamentSetting {
-                ams_id,
-                tray_id,
-                tray_color,
-                nozzle_temp_min,
-                nozzle_temp_max,
-                tray_type,
-            } => todo!(),
+            PrinterConnCmd::UnloadFilament(id) => self.change_filament(&id, None).await?,
+            PrinterConnCmd::ChangeFilament(id, target) => {
+                self.change_filament(&id, Some(target)).await?
+            }
+            PrinterConnCmd::GetKProfiles(id) => self.get_k_profiles(&id).await?,
+            PrinterConnCmd::SelectKProfile(id, tray, cali_idx) => {
+                self.select_k_profile(&id, tray, cali_idx).await?
+            }
+            PrinterConnCmd::SetK(id, tray, k) => self.set_k(&id, tray, k).await?,
+            PrinterConnCmd::ChangeAMSFilamentSetting(id, setting) => {
+                let client = self
+                    .printers
+                    .get(&id)
+                    .with_context(|| format!("printer not found: {:?}", id))?;
+                debug!("setting filament: {:?}", setting);
+                if let Err(e) = client
+                    .publish(Command::ChangeAMSFilamentSetting(setting))
+                    .await
+                {
+                    error!("error setting filament: {:?}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// filament
+impl PrinterConnManager {
+    /// load `target`, or unload the current filament if None
+    async fn change_filament(&self, id: &PrinterId, target: Option<AmsCurrentSlot>) -> Result<()> {
+        let client = self
+            .printers
+            .get(id)
+            .with_context(|| format!("printer not found: {:?}", id))?;
+
+        let (curr_temp, tar_temp) = {
+            let status = self
+                .printer_states
+                .get(id)
+                .with_context(|| format!("printer not found: {:?}", id))?;
+            let ams = status.ams.as_ref();
+            let temp = |slot: Option<AmsCurrentSlot>| {
+                slot.and_then(|s| ams?.get_slot(s))
+                    .map(|s| s.swap_temp())
+                    .unwrap_or(220)
+            };
+            let curr_temp = temp(ams.and_then(|a| a.current_tray));
+            (curr_temp, target.map_or(curr_temp, |t| temp(Some(t))))
+        };
+
+        let target = target.map_or(255, |t| t.to_tray_index());
+        debug!(
+            "changing filament: target = {}, {} -> {} °C",
+            target, curr_temp, tar_temp
+        );
+        if let Err(e) = client
+            .publish(Command::ChangeFilament {
+                target,
+                curr_temp,
+                tar_temp,
+            })
+            .await
+        {
+            error!("error changing filament: {:?}", e);
+        }
+        Ok(())
+    }
+
+    fn nozzle_diameter(&self, id: &PrinterId) -> String {
+        self.printer_states
+            .get(id)
+            .and_then(|s| s.nozzle_diameter.clone())
+            .unwrap_or_else(|| "0.4".to_string())
+    }
+
+    /// the filament loaded in a tray
+    fn tray_slot(&self, id: &PrinterId, tray: AmsCurrentSlot) -> Result<AmsSlot> {
+        let status = self
+            .printer_states
+            .get(id)
+            .with_context(|| format!("printer not found: {:?}", id))?;
+        status
+            .ams
+            .as_ref()
+            .and_then(|ams| ams.get_slot(tray))
+            .cloned()
+            .with_context(|| format!("no filament in {}", tray.to_text()))
+    }
+
+    async fn get_k_profiles(&self, id: &PrinterId) -> Result<()> {
+        let client = self
+            .printers
+            .get(id)
+            .with_context(|| format!("printer not found: {:?}", id))?;
+        let nozzle_diameter = self.nozzle_diameter(id);
+        if let Err(e) = client
+            .publish(Command::ExtrusionCaliGet { nozzle_diameter })
+            .await
+        {
+            error!("error getting K profiles: {:?}", e);
+        }
+        Ok(())
+    }
+
+    async fn select_k_profile(
+        &self,
+        id: &PrinterId,
+        tray: AmsCurrentSlot,
+        cali_idx: i64,
+    ) -> Result<()> {
+        let client = self
+            .printers
+            .get(id)
+            .with_context(|| format!("printer not found: {:?}", id))?;
+        /// the tray may have been emptied since the command was sent
+        let slot = match self.tray_slot(id, tray) {
+            Ok(slot) => slot,
+            Err(e) => {
+                error!("error selecting K profile: {:?}", e);
+                return Ok(());
+            }
+        };
+        let nozzle_diameter = self.nozzle_diameter(id);
+        let profile = self.printer_states.get(id).and_then(|s| {
+            s.k_profiles
+                .iter()
+                .find(|p| p.cali_idx == cali_idx)
+                .cloned()
+        });
+
+        debug!("selecting K profile {} for {}", cali_idx, tray.to_text());
+        if let Err(e) = client
+            .publish(Command::ExtrusionCaliSel {
+                tray_id: tray.to_tray_index(),
+                cali_idx,
+                filament_id: slot.filament_id(),
+                nozzle_diameter: nozzle_diameter.clone(),
+            })
+            .await
+        {
+            error!("error selecting K profile: {:?}", e);
+            return Ok(());
+        }
+
+        if let Some(profile) = profile {
+            self.spools.record_k(KRecord {
+                filament_id: slot.filament_id(),
+                filament_name: slot.filament_name(),
+                nozzle_diameter,
+                k: profile.k,
+                printer: id.clone(),
+                time: chrono::Local::now(),
+            });
         }
         Ok(())
     }
+
+    async fn set_k(&self, id: &PrinterId, tray: AmsCurrentSlot, k: f64) -> Result<()> {
+        let client = self
+            .printers
+            .get(id)
+            .with_context(|| format!("printer not found: {:?}", id))?;
+        let slot = match self.tray_slot(id, tray) {
+            Ok(slot) => slot,
+            Err(e) => {
+                error!("error setting K: {:?}", e);
+                return Ok(());
+            }
+        };
+
+        debug!("setting K {:.3} for {}", k, tray.to_text());
+        if let Err(e) = client
+            .publish(Command::ExtrusionCaliSet {
+                tray_id: tray.to_tray_index(),
+                k,
+                n_coef: DEFAULT_N_COEF,
+                nozzle_temp: slot.swap_temp(),
+                bed_temp: slot.bed_temp.unwrap_or(0),
+            })
+            .await
+        {
+            error!("error setting K: {:?}", e);
+            return Ok(());
+        }
+
+        self.spools.record_k(KRecord {
+            filament_id: slot.filament_id(),
+            filament_name: slot.filament_name(),
+            nozzle_diameter: self.nozzle_diameter(id),
+            k,
+            printer: id.clone(),
+            time: chrono::Local::now(),
+        });
+        Ok(())
+    }
 }
 
+/// n coefficient sent along with a manual K
+const DEFAULT_N_COEF: f64 = 1.4;
+
 async fn login(
     tx: tokio::sync::mpsc::UnboundedSender<PrinterConnMsg>,
     // auth: Arc<tokio::sync::RwLock<crate::auth::AuthDb>>,
@@ -647,6 +1054,9 @@ async fn fetch_printer_task_thumbnail(
 async fn sync_projects(
     config: ConfigArc,
     msg_tx: tokio::sync::mpsc::UnboundedSender<PrinterConnMsg>,
+    job_db: JobDb,
+    spools: SpoolInventory,
+    spoolman: Option<SpoolmanClient>,
 ) -> Result<()> {
     let Some(token) = config.get_token_async().await? else {
         bail!("no token found");
@@ -686,13 +1096,170 @@ async fn sync_projects(
         .hits
         .into_iter()
         .map(|t| crate::cloud::projects::TaskData::from_json(&t))
-        .collect();
+        .collect::<Vec<_>>();
+
+    /// filament weights for local job history come from the cloud tasks
+    match crate::jobs::apply_cloud_tasks(&job_db, &spools, &task_list) {
+        Ok(jobs) if !jobs.is_empty() => msg_tx.send(PrinterConnMsg::JobHistoryChanged)?,
+        Ok(_) => {}
+        Err(e) => error!("failed to update job history from cloud tasks: {:?}", e),
+    }
+
+    /// also retries usage that failed to report on an earlier sync
+    if let Some(spoolman) = spoolman.as_ref() {
+        report_spoolman(spoolman, &job_db, &msg_tx).await;
+    }
 
     let project_list = ProjectsList::new(task_list);
     msg_tx.send(PrinterConnMsg::SyncedProjects(project_list))?;
     Ok(())
 }
 
+/// most recent first, each needs its own request
+const CLOUD_PROJECTS_LIMIT: usize = 20;
+
+async fn sync_cloud_projects(
+    config: ConfigArc,
+    msg_tx: tokio::sync::mpsc::UnboundedSender<PrinterConnMsg>,
+) -> Result<()> {
+    let Some(token) = config.get_token_async().await? else {
+        bail!("no token found");
+    };
+
+    let mut projects = vec![];
+    for project in crate::cloud::get_project_list(&token)
+        .await?
+        .iter()
+        .take(CLOUD_PROJECTS_LIMIT)
+    {
+        let project = match crate::cloud::get_project_info(&token, &project.project_id)
+            .await
+            .and_then(crate::cloud::projects::ProjectData::from_json)
+        {
+            Ok(project) => project,
+            Err(e) => {
+                /// not sliced yet
+                debug!("skipping project {:?}: {:?}", project.name, e);
+                continue;
+            }
+        };
+        projects.push(project);
+    }
+
+    debug!("got {} cloud projects", projects.len());
+    msg_tx.send(PrinterConnMsg::SyncedCloudProjects(projects))?;
+    Ok(())
+}
+
+/// Errors are only logged, anything not reported is retried on the next sync
+async fn report_spoolman(
+    spoolman: &SpoolmanClient,
+    job_db: &JobDb,
+    msg_tx: &tokio::sync::mpsc::UnboundedSender<PrinterConnMsg>,
+) {
+    match spoolman.report_pending(job_db).await {
+        Ok(true) => {
+            if let Err(e) = sync_spoolman(spoolman, msg_tx).await {
+                error!("error syncing spoolman: {:?}", e);
+            }
+        }
+        Ok(false) => {}
+        Err(e) => error!("failed to report usage to spoolman: {:?}", e),
+    }
+}
+
+async fn sync_spoolman(
+    spoolman: &SpoolmanClient,
+    msg_tx: &tokio::sync::mpsc::UnboundedSender<PrinterConnMsg>,
+) -> Result<()> {
+    let spools = spoolman.spools().await?;
+    debug!("got {} spools from spoolman", spools.len());
+    msg_tx.send(PrinterConnMsg::SpoolmanSpools(spools))?;
+    Ok(())
+}
+
+/// Compares the filament a job was sent with against the printer's AMS, needs the cloud task
+async fn check_job_filament(
+    config: ConfigArc,
+    printer_states: Arc<DashMap<PrinterId, PrinterStatus>>,
+    alert_tx: tokio::sync::mpsc::UnboundedSender<Alert>,
+    job: JobRecord,
+) -> Result<()> {
+    let Some(token) = config.get_token_async().await? else {
+        return Ok(());
+    };
+
+    let tasks = crate::cloud::get_task_list(&token, Some(job.printer.clone()), None, Some(5))
+        .await?
+        .hits
+        .iter()
+        .map(crate::cloud::projects::TaskData::from_json)
+        .collect::<Vec<_>>();
+    let Some(task) = crate::jobs::match_cloud_task(&job, &tasks) else {
+        debug!("no cloud task for job {:?}", job.name);
+        return Ok(());
+    };
+
+    let check = {
+        let status = printer_states
+            .get(&job.printer)
+            .with_context(|| format!("printer not found: {:?}", job.printer))?;
+        let Some(ams) = status.ams.as_ref() else {
+            return Ok(());
+        };
+        FilamentCheck::new(RequiredFilament::from_task(task), ams)
+    };
+    if check.is_ok() {
+        return Ok(());
+    }
+
+    warn!(
+        "filament check failed for {:?}:\n{}",
+        job.name,
+        check.summary()
+    );
+    alert_tx.send(Alert::new(
+        job.printer.clone(),
+        &job.printer_name,
+        AlertEvent::FilamentMismatch,
+        format!("Filament Check: {}", job.printer_name),
+        format!("{}\n{}", job.name, check.summary()),
+    ))?;
+    Ok(())
+}
+
+/// Records filament usage for a job that just ended, from the cloud task if logged in,
+/// otherwise from the AMS estimates, and reports it to Spoolman
+async fn record_job_usage(
+    config: ConfigArc,
+    msg_tx: tokio::sync::mpsc::UnboundedSender<PrinterConnMsg>,
+    job_db: JobDb,
+    spools: SpoolInventory,
+    spoolman: Option<SpoolmanClient>,
+    job_id: i64,
+) -> Result<()> {
+    let tasks = match config.get_token_async().await? {
+        Some(token) => crate::cloud::get_task_list(&token, None, None, Some(10))
+            .await?
+            .hits
+            .iter()
+            .map(crate::cloud::projects::TaskData::from_json)
+            .collect(),
+        None => vec![],
+    };
+
+    if crate::jobs::apply_job_usage(&job_db, &spools, job_id, &tasks)?.is_none() {
+        debug!("no filament usage found for job {}", job_id);
+        return Ok(());
+    }
+    msg_tx.send(PrinterConnMsg::JobHistoryChanged)?;
+
+    if let Some(spoolman) = spoolman.as_ref() {
+        report_spoolman(spoolman, &job_db, &msg_tx).await;
+    }
+    Ok(())
+}
+
 async fn sync_printers(
     ctx: egui::Context,
     config: ConfigArc,
@@ -0,0 +1,409 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::{
+    conn_manager::PrinterId,
+    jobs::{cost::JobCost, JobRecord},
+    ui::plotting::{GraphMarker, Graphs},
+};
+
+/// relative to the working directory, like the other data files
+pub const EXPORT_DIR: &str = "exports";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub const ALL: [Self; 2] = [Self::Csv, Self::Json];
+
+    pub fn to_text(&self) -> &'static str {
+        match self {
+            Self::Csv => "CSV",
+            Self::Json => "JSON",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+
+    pub fn from_text(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A time window to export, None is unbounded
+#[derive(Debug, Clone, Copy)]
+pub struct ExportWindow {
+    pub start: Option<DateTime<Local>>,
+    pub end: Option<DateTime<Local>>,
+}
+
+impl ExportWindow {
+    /// a running job ends now
+    pub fn job(job: &JobRecord) -> Self {
+        Self {
+            start: Some(job.start),
+            end: Some(job.end.unwrap_or_else(Local::now)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TelemetryExport {
+    printer: String,
+    start: Option<String>,
+    end: Option<String>,
+    series: Vec<SeriesExport>,
+    markers: Vec<MarkerExport>,
+}
+
+#[derive(Serialize)]
+struct SeriesExport {
+    name: String,
+    samples: Vec<SampleExport>,
+}
+
+/// min, max and count differ from value only for older, averaged data
+#[derive(Serialize)]
+struct SampleExport {
+    time: String,
+    value: f64,
+    min: f64,
+    max: f64,
+    count: u32,
+}
+
+#[derive(Serialize)]
+struct MarkerExport {
+    time: String,
+    marker: GraphMarker,
+}
+
+fn iso(t: DateTime<Local>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+fn iso_from_secs(t: f64) -> String {
+    DateTime::from_timestamp_millis((t * 1000.) as i64)
+        .map(|t| iso(t.with_timezone(&Local)))
+        .unwrap_or_default()
+}
+
+fn collect(graphs: &Graphs, id: &PrinterId, window: ExportWindow) -> Result<TelemetryExport> {
+    let data = graphs
+        .printer_graphs
+        .get(id)
+        .ok_or_else(|| anyhow!("no telemetry recorded for printer: {}", id))?;
+
+    let series = data
+        .available_series()
+        .into_iter()
+        .filter_map(|s| {
+            let samples = data
+                .series(s)?
+                .samples_between(window.start, window.end)
+                .into_iter()
+                .map(|p| SampleExport {
+                    time: iso_from_secs(p.t),
+                    value: p.avg,
+                    min: p.min,
+                    max: p.max,
+                    count: p.count,
+                })
+                .collect::<Vec<_>>();
+            if samples.is_empty() {
+                return None;
+            }
+            Some(SeriesExport {
+                name: s.to_text(),
+                samples,
+            })
+        })
+        .collect();
+
+    let markers = data
+        .markers_between(window.start, window.end)
+        .map(|(t, m)| MarkerExport {
+            time: iso(*t),
+            marker: m.clone(),
+        })
+        .collect();
+
+    Ok(TelemetryExport {
+        printer: id.to_string(),
+        start: window.start.map(iso),
+        end: window.end.map(iso),
+        series,
+        markers,
+    })
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// CSV is one row per sample, markers are rows in the `Marker` series with their text as the value
+pub fn export_telemetry(
+    graphs: &Graphs,
+    id: &PrinterId,
+    window: ExportWindow,
+    format: ExportFormat,
+) -> Result<String> {
+    let export = collect(graphs, id, window)?;
+
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(&export)?),
+        ExportFormat::Csv => {
+            let mut out = String::from("time,series,value,min,max,count\n");
+            for series in export.series.iter() {
+                let name = csv_field(&series.name);
+                for s in series.samples.iter() {
+                    out.push_str(&format!(
+                        "{},{},{},{},{},{}\n",
+                        s.time, name, s.value, s.min, s.max, s.count
+                    ));
+                }
+            }
+            for m in export.markers.iter() {
+                out.push_str(&format!(
+                    "{},Marker,{},,,\n",
+                    m.time,
+                    csv_field(&m.marker.to_text())
+                ));
+            }
+            Ok(out)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CostExport {
+    currency: String,
+    total: f64,
+    jobs: Vec<JobCostExport>,
+}
+
+/// costs that aren't known are left empty, and count as 0 in the total
+#[derive(Serialize)]
+struct JobCostExport {
+    id: i64,
+    printer: String,
+    name: String,
+    start: String,
+    end: Option<String>,
+    hours: f64,
+    result: &'static str,
+    grams: Option<f64>,
+    filament_cost: Option<f64>,
+    electricity_cost: Option<f64>,
+    total: Option<f64>,
+}
+
+fn opt(x: Option<f64>) -> String {
+    x.map(|x| format!("{:.2}", x)).unwrap_or_default()
+}
+
+/// one row per job, for billing
+pub fn export_job_costs(
+    jobs: &[(JobRecord, JobCost)],
+    currency: &str,
+    format: ExportFormat,
+) -> Result<String> {
+    let rows = jobs
+        .iter()
+        .map(|(job, cost)| JobCostExport {
+            id: job.id,
+            printer: job.printer_name.clone(),
+            name: job.name.clone(),
+            start: iso(job.start),
+            end: job.end.map(iso),
+            hours: job.duration().num_seconds() as f64 / 3600.,
+            result: job.result.to_text(),
+            grams: job.filament_grams(),
+            filament_cost: cost.filament,
+            electricity_cost: cost.electricity,
+            total: cost.total(),
+        })
+        .collect::<Vec<_>>();
+
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(&CostExport {
+            currency: currency.to_string(),
+            total: rows.iter().filter_map(|r| r.total).sum(),
+            jobs: rows,
+        })?),
+        ExportFormat::Csv => {
+            let mut out = String::from(
+                "id,printer,name,start,end,hours,result,grams,filament_cost,electricity_cost,total,currency\n",
+            );
+            let currency = csv_field(currency);
+            for r in rows.iter() {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{:.2},{},{},{},{},{},{}\n",
+                    r.id,
+                    csv_field(&r.printer),
+                    csv_field(&r.name),
+                    r.start,
+                    r.end.as_deref().unwrap_or(""),
+                    r.hours,
+                    r.result,
+                    r.grams.map(|g| format!("{:.1}", g)).unwrap_or_default(),
+                    opt(r.filament_cost),
+                    opt(r.electricity_cost),
+                    opt(r.total),
+                    currency,
+                ));
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Writes to `EXPORT_DIR/<name>.<ext>`, returns the path
+pub fn write_export(name: &str, format: ExportFormat, contents: &str) -> Result<PathBuf> {
+    std::fs::create_dir_all(EXPORT_DIR)?;
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let path = PathBuf::from(EXPORT_DIR).join(format!("{}.{}", name, format.extension()));
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
+
+pub const CLI_USAGE: &str = "usage: bambu_watcher export <printer name or serial> \
+[--job <id>] [--from <time>] [--to <time>] [--format csv|json] [--output <path or ->]";
+
+/// `YYYY-MM-DD HH:MM[:SS]` in local time, or RFC 3339
+fn parse_time(s: &str) -> Result<DateTime<Local>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Local));
+    }
+    for fmt in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(t) = chrono::NaiveDateTime::parse_from_str(s, fmt) {
+            if let Some(t) = t.and_local_timezone(Local).earliest() {
+                return Ok(t);
+            }
+        }
+    }
+    bail!("invalid time: {:?}", s)
+}
+
+/// `bambu_watcher export ...`, same output as the export buttons.
+/// Writes to `EXPORT_DIR` by default, since release builds on Windows have no console
+pub fn run_cli(args: &[String]) -> Result<()> {
+    let mut printer = None;
+    let mut job = None;
+    let mut window = ExportWindow {
+        start: None,
+        end: None,
+    };
+    let mut format = ExportFormat::Csv;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("missing value for {}\n{}", arg, CLI_USAGE))
+        };
+        match arg.as_str() {
+            "--job" => job = Some(value()?.parse::<i64>()?),
+            "--from" => window.start = Some(parse_time(value()?)?),
+            "--to" => window.end = Some(parse_time(value()?)?),
+            "--format" => {
+                let v = value()?;
+                format =
+                    ExportFormat::from_text(v).ok_or_else(|| anyhow!("unknown format: {}", v))?;
+            }
+            "--output" => output = Some(value()?.clone()),
+            s if s.starts_with("--") => bail!("unknown option: {}\n{}", s, CLI_USAGE),
+            s => printer = Some(s.to_string()),
+        }
+    }
+    let printer = printer.ok_or_else(|| anyhow!(CLI_USAGE))?;
+
+    let (config, auth) = crate::config::Config::read_from_file("config.yaml")?;
+    let config = crate::config::ConfigArc::new(config, auth);
+    let id = config
+        .printers()
+        .into_iter()
+        .map(|p| p.blocking_read().clone())
+        .find(|p| p.name == printer || p.serial.as_str() == printer)
+        .map(|p| p.serial)
+        .unwrap_or_else(|| std::sync::Arc::new(printer.clone()));
+
+    let mut name = format!("{}_{}", id, Local::now().format("%Y%m%d-%H%M%S"));
+    if let Some(job_id) = job {
+        let db = crate::jobs::db::JobDb::open()?;
+        let job = db
+            .job(job_id)?
+            .ok_or_else(|| anyhow!("no job with id {}", job_id))?;
+        ensure!(
+            job.printer == id,
+            "job {} was printed on {}, not {}",
+            job_id,
+            job.printer_name,
+            printer
+        );
+        window = ExportWindow::job(&job);
+        name = format!("{}_job{}", id, job.id);
+    }
+
+    /// the app saves when a job ends and every few minutes, not on every report
+    let saved = std::fs::metadata(Graphs::FILENAME)
+        .and_then(|m| m.modified())
+        .map(DateTime::<Local>::from)
+        .with_context(|| format!("failed to read {}", Graphs::FILENAME))?;
+    if window.end.unwrap_or_else(Local::now) > saved {
+        /// stderr, so it doesn't end up in `--output -`
+        eprintln!(
+            "warning: {} was last saved at {}, anything recorded since isn't included",
+            Graphs::FILENAME,
+            saved.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+
+    let graphs = Graphs::load_from_file(Graphs::FILENAME, config.graphs())
+        .with_context(|| format!("failed to read {}", Graphs::FILENAME))?;
+    let contents = export_telemetry(&graphs, &id, window, format)?;
+
+    match output.as_deref() {
+        Some("-") => print!("{}", contents),
+        Some(path) => {
+            std::fs::write(path, contents)?;
+            println!("exported to {}", path);
+        }
+        None => {
+            let path = write_export(&name, format, &contents)?;
+            println!("exported to {}", path.display());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{cloud::projects::TaskData, config::CostConfig, spools::SpoolInventory};
+
+use super::JobRecord;
+
+/// None where the weight, duration or price isn't known
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct JobCost {
+    pub filament: Option<f64>,
+    pub electricity: Option<f64>,
+}
+
+impl JobCost {
+    pub fn total(&self) -> Option<f64> {
+        match (self.filament, self.electricity) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0.) + b.unwrap_or(0.)),
+        }
+    }
+
+    fn electricity(costs: &CostConfig, watts: Option<f64>, hours: f64) -> Option<f64> {
+        let rate = costs.electricity_rate?;
+        let watts = watts.unwrap_or(costs.default_watts);
+        Some(hours * watts / 1000. * rate)
+    }
+
+    /// Uses the price of the inventory spool each tray printed from if it has one,
+    /// otherwise the price for the material
+    pub fn job(
+        job: &JobRecord,
+        costs: &CostConfig,
+        spools: &SpoolInventory,
+        watts: Option<f64>,
+    ) -> Self {
+        let mut filament = None;
+        for tray in job.trays.iter() {
+            let Some(grams) = tray.grams else {
+                continue;
+            };
+            let price = tray
+                .spool
+                .and_then(|id| spools.get(id))
+                .and_then(|s| s.price_per_kg)
+                .or_else(|| costs.filament_price(&tray.material));
+            match price {
+                Some(price) => *filament.get_or_insert(0.) += grams / 1000. * price,
+                None => {
+                    // a partial cost would be misleading on a bill
+                    filament = None;
+                    break;
+                }
+            }
+        }
+
+        let hours = job.duration().num_seconds() as f64 / 3600.;
+        Self {
+            filament,
+            electricity: Self::electricity(costs, watts, hours),
+        }
+    }
+
+    /// from the slicer's weights and print time
+    pub fn task(task: &TaskData, costs: &CostConfig, watts: Option<f64>) -> Self {
+        let filament = if task.ams_detail_mapping.is_empty() {
+            costs
+                .default_filament_price
+                .map(|price| task.weight / 1000. * price)
+        } else {
+            task.ams_detail_mapping
+                .iter()
+                .map(|m| {
+                    let material = if m.target_filament_type.is_empty() {
+                        &m.filament_type
+                    } else {
+                        &m.target_filament_type
+                    };
+                    costs
+                        .filament_price(material)
+                        .map(|price| m.weight / 1000. * price)
+                })
+                .sum::<Option<f64>>()
+        };
+
+        let hours = task.cost_time as f64 / 3600.;
+        Self {
+            filament,
+            electricity: Self::electricity(costs, watts, hours),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::{JobResult, JobTray};
+
+    fn job(trays: Vec<JobTray>) -> JobRecord {
+        let start = chrono::Local::now();
+        JobRecord {
+            id: 0,
+            printer: std::sync::Arc::new("serial".to_string()),
+            printer_name: "printer".to_string(),
+            subtask_id: None,
+            name: "job".to_string(),
+            start,
+            end: Some(start + chrono::Duration::hours(2)),
+            result: JobResult::Finished,
+            total_layers: None,
+            layer_reached: None,
+            trays,
+            error_code: None,
+            error_text: None,
+        }
+    }
+
+    fn tray(material: &str, grams: Option<f64>) -> JobTray {
+        JobTray {
+            material: material.to_string(),
+            grams,
+            ..JobTray::external()
+        }
+    }
+
+    fn costs() -> CostConfig {
+        let mut costs = CostConfig::default();
+        costs.filament_prices.insert("PLA".to_string(), 20.);
+        costs
+    }
+
+    #[test]
+    fn filament_priced_by_material() {
+        let cost = JobCost::job(
+            &job(vec![tray("pla", Some(500.)), tray("PLA", Some(250.))]),
+            &costs(),
+            &SpoolInventory::default(),
+            None,
+        );
+        assert_eq!(cost.filament, Some(15.));
+        assert_eq!(cost.electricity, None);
+        assert_eq!(cost.total(), Some(15.));
+    }
+
+    #[test]
+    fn missing_price_drops_filament_cost() {
+        let cost = JobCost::job(
+            &job(vec![tray("PLA", Some(500.)), tray("PETG", Some(100.))]),
+            &costs(),
+            &SpoolInventory::default(),
+            None,
+        );
+        assert_eq!(cost.filament, None);
+        assert_eq!(cost.total(), None);
+    }
+
+    #[test]
+    fn default_price_covers_unlisted_materials() {
+        let mut costs = costs();
+        costs.default_filament_price = Some(30.);
+        let cost = JobCost::job(
+            &job(vec![tray("PETG", Some(100.))]),
+            &costs,
+            &SpoolInventory::default(),
+            None,
+        );
+        assert_eq!(cost.filament, Some(3.));
+    }
+
+    #[test]
+    fn unknown_weight_is_skipped() {
+        let cost = JobCost::job(
+            &job(vec![tray("PETG", None)]),
+            &costs(),
+            &SpoolInventory::default(),
+            None,
+        );
+        assert_eq!(cost.filament, None);
+    }
+
+    #[test]
+    fn electricity_only() {
+        let costs = CostConfig {
+            electricity_rate: Some(0.5),
+            ..Default::default()
+        };
+        let cost = JobCost::job(
+            &job(vec![tray("PETG", Some(100.))]),
+            &costs,
+            &SpoolInventory::default(),
+            Some(250.),
+        );
+        assert_eq!(cost.filament, None);
+        assert_eq!(cost.electricity, Some(0.25));
+        assert_eq!(cost.total(), Some(0.25));
+    }
+}
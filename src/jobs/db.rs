@@ -0,0 +1,223 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::sync::Arc;
+
+use crate::conn_manager::PrinterId;
+
+use super::{JobRecord, JobResult};
+
+/// Local print history, shared between PrinterConnManager and the UI
+#[derive(Clone)]
+pub struct JobDb {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl JobDb {
+    const FILENAME: &'static str = "jobs.db";
+
+    const COLUMNS: &'static str = "id, printer, printer_name, subtask_id, name, start, end, \
+         result, total_layers, layer_reached, trays, error_code, error_text";
+
+    pub fn open() -> Result<Self> {
+        let conn = Connection::open(Self::FILENAME)?;
+        Self::init(conn)
+    }
+
+    /// used when the file can't be opened, so history is only lost instead of blocking startup
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        Self::init(conn)
+    }
+
+    pub fn open_or_in_memory() -> Self {
+        match Self::open() {
+            Ok(db) => db,
+            Err(e) => {
+                error!("failed to open job history, using in-memory db: {:?}", e);
+                Self::open_in_memory().expect("failed to create in-memory db")
+            }
+        }
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id              INTEGER PRIMARY KEY,
+                printer         TEXT NOT NULL,
+                printer_name    TEXT NOT NULL,
+                subtask_id      TEXT,
+                name            TEXT NOT NULL,
+                start           TEXT NOT NULL,
+                end             TEXT,
+                result          TEXT NOT NULL,
+                total_layers    INTEGER,
+                layer_reached   INTEGER,
+                trays           TEXT NOT NULL,
+                error_code      INTEGER,
+                error_text      TEXT
+            );
+            CREATE INDEX IF NOT EXISTS jobs_printer ON jobs (printer, start);",
+        )?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<JobRecord> {
+        let printer: String = row.get(1)?;
+        let result: String = row.get(7)?;
+        let trays: String = row.get(10)?;
+        Ok(JobRecord {
+            id: row.get(0)?,
+            printer: Arc::new(printer),
+            printer_name: row.get(2)?,
+            subtask_id: row.get(3)?,
+            name: row.get(4)?,
+            start: row.get(5)?,
+            end: row.get(6)?,
+            result: JobResult::from_text(&result),
+            total_layers: row.get(8)?,
+            layer_reached: row.get(9)?,
+            trays: serde_json::from_str(&trays).unwrap_or_default(),
+            error_code: row.get(11)?,
+            error_text: row.get(12)?,
+        })
+    }
+
+    pub fn insert(&self, job: &JobRecord) -> Result<i64> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT INTO jobs (printer, printer_name, subtask_id, name, start, end, result,
+                total_layers, layer_reached, trays, error_code, error_text)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                job.printer.as_str(),
+                job.printer_name,
+                job.subtask_id,
+                job.name,
+                job.start,
+                job.end,
+                job.result.to_text(),
+                job.total_layers,
+                job.layer_reached,
+                serde_json::to_string(&job.trays)?,
+                job.error_code,
+                job.error_text,
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn update(&self, job: &JobRecord) -> Result<()> {
+        Self::update_with(&self.conn.lock(), job)
+    }
+
+    fn update_with(conn: &Connection, job: &JobRecord) -> Result<()> {
+        conn.execute(
+            "UPDATE jobs SET end = ?2, result = ?3, total_layers = ?4, layer_reached = ?5,
+                trays = ?6, error_code = ?7, error_text = ?8
+             WHERE id = ?1",
+            params![
+                job.id,
+                job.end,
+                job.result.to_text(),
+                job.total_layers,
+                job.layer_reached,
+                serde_json::to_string(&job.trays)?,
+                job.error_code,
+                job.error_text,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Fills in the filament weights of an ended job that has none yet and saves it.
+    /// The connection stays locked from the read to the write, so only one caller
+    /// can record a job's usage. Trays mapped to Spoolman are marked as pending.
+    /// Returns the job if `f` filled in any weights
+    pub fn claim_usage(
+        &self,
+        id: i64,
+        f: impl FnOnce(&mut JobRecord),
+    ) -> Result<Option<JobRecord>> {
+        let conn = self.conn.lock();
+        let Some(mut job) = conn
+            .query_row(
+                &format!("SELECT {} FROM jobs WHERE id = ?1", Self::COLUMNS),
+                params![id],
+                Self::from_row,
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        if job.result == JobResult::Running || job.filament_grams().is_some() {
+            return Ok(None);
+        }
+
+        f(&mut job);
+        if job.filament_grams().is_none() {
+            return Ok(None);
+        }
+        for tray in job.trays.iter_mut() {
+            tray.spoolman_pending =
+                tray.spoolman_id.is_some() && tray.grams.is_some_and(|g| g > 0.);
+        }
+        Self::update_with(&conn, &job)?;
+        Ok(Some(job))
+    }
+
+    pub fn job(&self, id: i64) -> Result<Option<JobRecord>> {
+        let conn = self.conn.lock();
+        let job = conn
+            .query_row(
+                &format!("SELECT {} FROM jobs WHERE id = ?1", Self::COLUMNS),
+                params![id],
+                Self::from_row,
+            )
+            .optional()?;
+        Ok(job)
+    }
+
+    /// jobs still marked as running, oldest first
+    pub fn open_jobs(&self) -> Result<Vec<JobRecord>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE result = ?1 ORDER BY start ASC",
+            Self::COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map(params![JobResult::Running.to_text()], Self::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs)
+    }
+
+    /// oldest first
+    pub fn all_jobs(&self) -> Result<Vec<JobRecord>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs ORDER BY start ASC",
+            Self::COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map([], Self::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs)
+    }
+
+    /// newest first
+    pub fn jobs(&self, limit: usize) -> Result<Vec<JobRecord>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs ORDER BY start DESC LIMIT ?1",
+            Self::COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map(params![limit as i64], Self::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs)
+    }
+}
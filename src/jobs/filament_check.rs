@@ -0,0 +1,225 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use egui::Color32;
+
+use crate::{
+    cloud::projects::{project_data::Plate, TaskData},
+    status::{AmsCurrentSlot, AmsSlot, AmsStatus},
+};
+
+/// RGB distance under which two colors count as the same
+const COLOR_TOLERANCE: f32 = 60.;
+
+/// A filament the job needs, from the sliced plate or its cloud task
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequiredFilament {
+    pub material: String,
+    pub color: Color32,
+    pub grams: f64,
+    /// tray the job was sent with, None if it hasn't been mapped
+    pub mapped: Option<AmsCurrentSlot>,
+}
+
+impl RequiredFilament {
+    /// before printing, nothing is mapped to a tray yet
+    pub fn from_plate(plate: &Plate) -> Vec<Self> {
+        plate
+            .filaments
+            .iter()
+            .map(|f| Self {
+                material: f.type_field.clone(),
+                color: Color32::from_rgb(f.color[0], f.color[1], f.color[2]),
+                grams: f.used_g,
+                mapped: None,
+            })
+            .collect()
+    }
+
+    /// Cloud tray indices are `ams_id * 4 + tray_id`, 254 and 255 are the external spool
+    pub fn from_task(task: &TaskData) -> Vec<Self> {
+        task.ams_detail_mapping
+            .iter()
+            .map(|m| Self {
+                material: m.filament_type.clone(),
+                color: Color32::from_hex(&format!("#{}", m.source_color.get(..6).unwrap_or("")))
+                    .unwrap_or(Color32::GRAY),
+                grams: m.weight,
+                mapped: match m.ams {
+                    0..=15 => AmsCurrentSlot::from_tray_index(m.ams as u64),
+                    254 | 255 => Some(AmsCurrentSlot::ExternalSpool),
+                    _ => None,
+                },
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilamentIssue {
+    /// no loaded tray has the material
+    Missing,
+    /// nothing loaded in the mapped tray
+    Empty,
+    /// the tray has a different material
+    WrongMaterial(String),
+    /// right material, different color
+    ColorDifference(Color32),
+}
+
+impl FilamentIssue {
+    pub fn to_text(&self) -> String {
+        match self {
+            Self::Missing => "No tray has this material".to_string(),
+            Self::Empty => "Nothing loaded".to_string(),
+            Self::WrongMaterial(m) => format!("Tray has {}", m),
+            Self::ColorDifference(c) => {
+                format!("Tray color is #{:02X}{:02X}{:02X}", c.r(), c.g(), c.b())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilamentMatch {
+    pub required: RequiredFilament,
+    /// the mapped tray, or the closest match if the job isn't mapped
+    pub tray: Option<AmsCurrentSlot>,
+    pub issue: Option<FilamentIssue>,
+    /// a better tray when there's an issue with this one
+    pub suggestion: Option<AmsCurrentSlot>,
+}
+
+/// Compares the filaments a job needs with what's loaded in the printer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilamentCheck {
+    pub filaments: Vec<FilamentMatch>,
+}
+
+impl FilamentCheck {
+    pub fn new(required: Vec<RequiredFilament>, ams: &AmsStatus) -> Self {
+        let trays = loaded_trays(ams);
+        let mut used = vec![];
+
+        let mut filaments = vec![];
+        for required in required {
+            let best = best_tray(&required, &trays, &used);
+            let tray = required.mapped.or(best);
+            let issue = match tray {
+                Some(tray) => tray_issue(&required, ams.get_slot(tray)),
+                None => Some(FilamentIssue::Missing),
+            };
+            /// the best tray always has the right material, but may not have the right color
+            let suggestion = best.filter(|b| {
+                Some(*b) != tray
+                    && match issue {
+                        None => false,
+                        Some(FilamentIssue::ColorDifference(_)) => {
+                            tray_issue(&required, ams.get_slot(*b)).is_none()
+                        }
+                        Some(_) => true,
+                    }
+            });
+
+            used.extend(suggestion.or(tray));
+            filaments.push(FilamentMatch {
+                required,
+                tray,
+                issue,
+                suggestion,
+            });
+        }
+
+        Self { filaments }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.filaments.iter().all(|f| f.issue.is_none())
+    }
+
+    pub fn num_issues(&self) -> usize {
+        self.filaments.iter().filter(|f| f.issue.is_some()).count()
+    }
+
+    /// one line per filament with an issue
+    pub fn summary(&self) -> String {
+        self.filaments
+            .iter()
+            .filter_map(|f| {
+                let issue = f.issue.as_ref()?;
+                let mut line = format!(
+                    "{} ({:.1} g) in {}: {}",
+                    f.required.material,
+                    f.required.grams,
+                    f.tray
+                        .map(|t| t.to_text())
+                        .unwrap_or_else(|| "no tray".to_string()),
+                    issue.to_text()
+                );
+                if let Some(s) = f.suggestion {
+                    line.push_str(&format!(", use {} instead", s.to_text()));
+                }
+                Some(line)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn loaded_trays(ams: &AmsStatus) -> Vec<(AmsCurrentSlot, &AmsSlot)> {
+    let mut out = vec![];
+    for ams_id in ams.unit_ids() {
+        for tray_id in 0..4 {
+            let tray = AmsCurrentSlot::Tray {
+                ams_id: ams_id as u64,
+                tray_id,
+            };
+            if let Some(slot) = ams.get_slot(tray) {
+                out.push((tray, slot));
+            }
+        }
+    }
+    if let Some(slot) = ams.external.as_ref() {
+        out.push((AmsCurrentSlot::ExternalSpool, slot));
+    }
+    out
+}
+
+/// same material and the closest color, preferring trays no other filament uses
+fn best_tray(
+    required: &RequiredFilament,
+    trays: &[(AmsCurrentSlot, &AmsSlot)],
+    used: &[AmsCurrentSlot],
+) -> Option<AmsCurrentSlot> {
+    let score = |(tray, slot): &(AmsCurrentSlot, &AmsSlot)| {
+        let penalty = if used.contains(tray) { 1000. } else { 0. };
+        color_distance(required.color, slot.color) + penalty
+    };
+    trays
+        .iter()
+        .filter(|(_, slot)| same_material(&slot.material, &required.material))
+        .min_by(|a, b| score(a).total_cmp(&score(b)))
+        .map(|(tray, _)| *tray)
+}
+
+fn tray_issue(required: &RequiredFilament, slot: Option<&AmsSlot>) -> Option<FilamentIssue> {
+    let Some(slot) = slot else {
+        return Some(FilamentIssue::Empty);
+    };
+    if !same_material(&slot.material, &required.material) {
+        return Some(FilamentIssue::WrongMaterial(slot.material.clone()));
+    }
+    if color_distance(required.color, slot.color) > COLOR_TOLERANCE {
+        return Some(FilamentIssue::ColorDifference(slot.color));
+    }
+    None
+}
+
+fn same_material(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+fn color_distance(a: Color32, b: Color32) -> f32 {
+    let d = |x: u8, y: u8| (x as f32 - y as f32).powi(2);
+    (d(a.r(), b.r()) + d(a.g(), b.g()) + d(a.b(), b.b())).sqrt()
+}
@@ -0,0 +1,538 @@
+pub mod cost;
+pub mod db;
+pub mod filament_check;
+pub mod stats;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    cloud::{errors::ErrorMap, projects::TaskData},
+    conn_manager::PrinterId,
+    spools::{SpoolInventory, TrayLocation},
+    status::{bambu::PrinterStatus, AmsCurrentSlot, PrintError, PrinterState},
+};
+
+use self::db::JobDb;
+
+/// print_error reported when a print is stopped from the printer or app
+pub const CANCELLED_ERROR: i64 = 0x0300400C;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobResult {
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+    /// the printer went idle or moved on without reporting an end, e.g. it was rebooted
+    Interrupted,
+}
+
+impl JobResult {
+    pub fn to_text(&self) -> &'static str {
+        match self {
+            Self::Running => "Running",
+            Self::Finished => "Finished",
+            Self::Failed => "Failed",
+            Self::Cancelled => "Cancelled",
+            Self::Interrupted => "Interrupted",
+        }
+    }
+
+    pub fn from_text(s: &str) -> Self {
+        match s {
+            "Finished" => Self::Finished,
+            "Failed" => Self::Failed,
+            "Cancelled" => Self::Cancelled,
+            "Interrupted" => Self::Interrupted,
+            _ => Self::Running,
+        }
+    }
+}
+
+/// A tray that was fed from during a job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobTray {
+    /// None for the external spool
+    pub ams_id: Option<u64>,
+    pub tray_id: Option<u64>,
+    pub material: String,
+    pub color: [u8; 3],
+    /// from the matching cloud task, None if unknown
+    #[serde(default)]
+    pub grams: Option<f64>,
+    /// inventory spool loaded in the tray when it was used
+    #[serde(default)]
+    pub spool: Option<u64>,
+    /// Spoolman spool mapped to the tray when it was used
+    #[serde(default)]
+    pub spoolman_id: Option<u64>,
+    /// AMS remaining percent when the tray was first used, for estimating usage
+    #[serde(default)]
+    pub remain_start: Option<i64>,
+    /// from the change in remaining percent, used when there's no cloud task
+    #[serde(default)]
+    pub estimated_grams: Option<f64>,
+    /// usage hasn't been reported to Spoolman yet, retried on every sync
+    #[serde(default)]
+    pub spoolman_pending: bool,
+}
+
+impl JobTray {
+    fn external() -> Self {
+        Self {
+            ams_id: None,
+            tray_id: None,
+            material: String::new(),
+            color: [0, 0, 0],
+            grams: None,
+            spool: None,
+            spoolman_id: None,
+            remain_start: None,
+            estimated_grams: None,
+            spoolman_pending: false,
+        }
+    }
+
+    fn same_tray(&self, other: &Self) -> bool {
+        self.ams_id == other.ams_id
+            && self.tray_id == other.tray_id
+            && self.material == other.material
+            && self.color == other.color
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JobRecord {
+    /// row id, 0 until inserted
+    pub id: i64,
+    pub printer: PrinterId,
+    pub printer_name: String,
+    pub subtask_id: Option<String>,
+    /// file or subtask name
+    pub name: String,
+    pub start: DateTime<Local>,
+    pub end: Option<DateTime<Local>>,
+    pub result: JobResult,
+    pub total_layers: Option<i64>,
+    pub layer_reached: Option<i64>,
+    pub trays: Vec<JobTray>,
+    pub error_code: Option<i64>,
+    pub error_text: Option<String>,
+}
+
+impl JobRecord {
+    pub fn duration(&self) -> chrono::Duration {
+        self.end.unwrap_or_else(Local::now) - self.start
+    }
+
+    pub fn filament_grams(&self) -> Option<f64> {
+        let mut out = None;
+        for g in self.trays.iter().filter_map(|t| t.grams) {
+            *out.get_or_insert(0.) += g;
+        }
+        out
+    }
+
+    /// Cloud tray indices are `ams_id * 4 + tray_id`, 254 and 255 are the external spool.
+    /// Cloud weights are for the whole plate, so jobs that didn't finish are scaled by layers
+    fn apply_cloud_task(&mut self, task: &TaskData) {
+        let scale = match (self.result, self.layer_reached, self.total_layers) {
+            (JobResult::Finished, _, _) => 1.,
+            (_, Some(layer), Some(total)) if total > 0 => (layer as f64 / total as f64).min(1.),
+            _ => 1.,
+        };
+
+        if task.ams_detail_mapping.is_empty() {
+            if self.trays.len() == 1 {
+                self.trays[0].grams = Some(task.weight * scale);
+            } else {
+                self.trays.push(JobTray {
+                    grams: Some(task.weight * scale),
+                    ..JobTray::external()
+                });
+            }
+            return;
+        }
+
+        for mapping in task.ams_detail_mapping.iter() {
+            let (ams_id, tray_id) = match mapping.ams {
+                0..=253 => (Some(mapping.ams as u64 / 4), Some(mapping.ams as u64 % 4)),
+                _ => (None, None),
+            };
+            let material = if mapping.target_filament_type.is_empty() {
+                mapping.filament_type.clone()
+            } else {
+                mapping.target_filament_type.clone()
+            };
+            let color = if mapping.target_color.is_empty() {
+                &mapping.source_color
+            } else {
+                &mapping.target_color
+            };
+            let color = egui::Color32::from_hex(&format!("#{}", color.get(..6).unwrap_or("")))
+                .map(|c| [c.r(), c.g(), c.b()])
+                .unwrap_or_default();
+
+            match self
+                .trays
+                .iter_mut()
+                .find(|t| t.ams_id == ams_id && t.tray_id == tray_id && t.grams.is_none())
+            {
+                Some(tray) => {
+                    tray.grams = Some(mapping.weight * scale);
+                    if tray.material.is_empty() {
+                        tray.material = material;
+                        tray.color = color;
+                    }
+                }
+                None => self.trays.push(JobTray {
+                    ams_id,
+                    tray_id,
+                    material,
+                    color,
+                    grams: Some(mapping.weight * scale),
+                    ..JobTray::external()
+                }),
+            }
+        }
+    }
+
+    fn is_same_job(&self, status: &PrinterStatus) -> bool {
+        match (&self.subtask_id, &status.subtask_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => Some(&self.name) == status.current_file.as_ref(),
+        }
+    }
+}
+
+/// Builds job records from printer state transitions
+pub struct JobTracker {
+    jobs: HashMap<PrinterId, JobRecord>,
+    /// waiting for their filament usage to be recorded
+    ended: Vec<JobRecord>,
+    /// waiting for their filament to be checked
+    started: Vec<JobRecord>,
+}
+
+impl JobTracker {
+    /// picks up the jobs that were running when the app was closed
+    pub fn new(db: &JobDb) -> Self {
+        let mut jobs = HashMap::new();
+        match db.open_jobs() {
+            /// the newest open job on each printer wins
+            Ok(open) => {
+                for job in open {
+                    debug!("resuming job record: {:?}", job.name);
+                    jobs.insert(job.printer.clone(), job);
+                }
+            }
+            Err(e) => error!("failed to load open jobs: {:?}", e),
+        }
+        Self {
+            jobs,
+            ended: vec![],
+            started: vec![],
+        }
+    }
+
+    pub fn take_ended(&mut self) -> Vec<JobRecord> {
+        std::mem::take(&mut self.ended)
+    }
+
+    pub fn take_started(&mut self) -> Vec<JobRecord> {
+        std::mem::take(&mut self.started)
+    }
+
+    /// from how much the remaining percent of each AMS tray dropped
+    fn estimate_usage(job: &mut JobRecord, status: &PrinterStatus) {
+        let Some(ams) = status.ams.as_ref() else {
+            return;
+        };
+        for tray in job.trays.iter_mut() {
+            let (Some(ams_id), Some(tray_id), Some(start)) =
+                (tray.ams_id, tray.tray_id, tray.remain_start)
+            else {
+                continue;
+            };
+            let Some(slot) = ams
+                .units
+                .get(&(ams_id as i64))
+                .and_then(|u| u.slots.get(tray_id as usize))
+                .and_then(|s| s.as_ref())
+            else {
+                continue;
+            };
+            if let (Some(remain), Some(weight)) = (slot.remain, slot.tray_weight) {
+                if remain <= start {
+                    tray.estimated_grams = Some((start - remain) as f64 / 100. * weight);
+                }
+            }
+        }
+    }
+
+    /// Returns true if a job started or ended, progress is saved without reporting it
+    pub fn update(
+        &mut self,
+        db: &JobDb,
+        spools: &SpoolInventory,
+        error_map: &ErrorMap,
+        id: &PrinterId,
+        name: &str,
+        status: &PrinterStatus,
+    ) -> Result<bool> {
+        let error_code = match status.print_error {
+            Some(PrintError::Unknown(code)) if code != 0 => Some(code),
+            _ => None,
+        };
+
+        let result = match &status.state {
+            PrinterState::Finished => Some(JobResult::Finished),
+            PrinterState::Error(_) if status.job_failed() => {
+                if error_code == Some(CANCELLED_ERROR) {
+                    Some(JobResult::Cancelled)
+                } else {
+                    Some(JobResult::Failed)
+                }
+            }
+            /// a cancel reports FAILED with CANCELLED_ERROR, IDLE is a reboot or reconnect
+            PrinterState::Idle => Some(JobResult::Interrupted),
+            PrinterState::Printing | PrinterState::Paused | PrinterState::Error(_) => None,
+            PrinterState::Disconnected | PrinterState::Unknown(_) => return Ok(false),
+        };
+
+        if let Some(result) = result {
+            let Some(mut job) = self.jobs.remove(id) else {
+                return Ok(false);
+            };
+            if !job.is_same_job(status) && result != JobResult::Interrupted {
+                // printer finished something else while we weren't watching
+                job.result = JobResult::Interrupted;
+            } else {
+                job.result = result;
+            }
+            job.end = Some(Local::now());
+            job.layer_reached = status.layer_num.or(job.layer_reached);
+            if result != JobResult::Finished {
+                job.error_code = error_code.or(job.error_code);
+                job.error_text = job.error_code.map(|c| {
+                    error_map
+                        .get_error(c as u64)
+                        .unwrap_or("Unknown Error")
+                        .to_string()
+                });
+            }
+            Self::estimate_usage(&mut job, status);
+            info!("job ended: {:?} {:?}", job.name, job.result);
+            db.update(&job)?;
+            self.ended.push(job);
+            return Ok(true);
+        }
+
+        /// still waiting for the job name
+        if status.subtask_id.is_none() && status.current_file.is_none() {
+            return Ok(false);
+        }
+
+        let mut changed = false;
+
+        if let Some(job) = self.jobs.get_mut(id) {
+            if !job.is_same_job(status) {
+                job.result = JobResult::Interrupted;
+                job.end = Some(Local::now());
+                db.update(job)?;
+                if let Some(job) = self.jobs.remove(id) {
+                    self.ended.push(job);
+                }
+                changed = true;
+            }
+        }
+
+        let job = match self.jobs.entry(id.clone()) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                let mut job = JobRecord {
+                    id: 0,
+                    printer: id.clone(),
+                    printer_name: name.to_string(),
+                    subtask_id: status.subtask_id.clone(),
+                    name: status
+                        .current_file
+                        .clone()
+                        .or(status.subtask_id.clone())
+                        .unwrap_or_default(),
+                    start: Local::now(),
+                    end: None,
+                    result: JobResult::Running,
+                    total_layers: status.total_layer_num,
+                    layer_reached: status.layer_num,
+                    trays: vec![],
+                    error_code: None,
+                    error_text: None,
+                };
+                job.id = db.insert(&job)?;
+                info!("job started: {:?}", job.name);
+                self.started.push(job.clone());
+                changed = true;
+                e.insert(job)
+            }
+        };
+
+        let mut dirty = false;
+
+        if status.layer_num.is_some() && status.layer_num != job.layer_reached {
+            job.layer_reached = status.layer_num;
+            dirty = true;
+        }
+        if status.total_layer_num.is_some() && status.total_layer_num != job.total_layers {
+            job.total_layers = status.total_layer_num;
+            dirty = true;
+        }
+        if let Some(tray) = Self::current_tray(spools, id, status) {
+            if !job.trays.iter().any(|t| t.same_tray(&tray)) {
+                job.trays.push(tray);
+                dirty = true;
+            }
+        }
+        if error_code.is_some() && error_code != job.error_code {
+            job.error_code = error_code;
+            job.error_text = error_code.map(|c| {
+                error_map
+                    .get_error(c as u64)
+                    .unwrap_or("Unknown Error")
+                    .to_string()
+            });
+            dirty = true;
+        }
+
+        if dirty {
+            db.update(job)?;
+        }
+
+        Ok(changed)
+    }
+
+    fn current_tray(
+        spools: &SpoolInventory,
+        id: &PrinterId,
+        status: &PrinterStatus,
+    ) -> Option<JobTray> {
+        let ams = status.ams.as_ref()?;
+        match ams.active_slot()? {
+            AmsCurrentSlot::ExternalSpool => {
+                let location = TrayLocation::external(id.clone());
+                let mut tray = JobTray {
+                    spool: spools.spool_at(&location).map(|s| s.id),
+                    spoolman_id: spools.spoolman_at(&location),
+                    ..JobTray::external()
+                };
+                if let Some(slot) = ams.external.as_ref() {
+                    tray.material = slot.material.clone();
+                    tray.color = [slot.color.r(), slot.color.g(), slot.color.b()];
+                }
+                Some(tray)
+            }
+            AmsCurrentSlot::Tray { ams_id, tray_id } => {
+                let slot = ams.get_slot(AmsCurrentSlot::Tray { ams_id, tray_id })?;
+                let location = TrayLocation::tray(id.clone(), ams_id, tray_id);
+                Some(JobTray {
+                    ams_id: Some(ams_id),
+                    tray_id: Some(tray_id),
+                    material: slot.material.clone(),
+                    color: [slot.color.r(), slot.color.g(), slot.color.b()],
+                    grams: None,
+                    spool: spools.spool_at(&location).map(|s| s.id),
+                    spoolman_id: spools.spoolman_at(&location),
+                    remain_start: slot.remain,
+                    estimated_grams: None,
+                    spoolman_pending: false,
+                })
+            }
+        }
+    }
+}
+
+/// cloud tasks are matched to local jobs on the same printer started within this many minutes
+const CLOUD_MATCH_MINUTES: i64 = 30;
+
+pub fn match_cloud_task<'a>(job: &JobRecord, tasks: &'a [TaskData]) -> Option<&'a TaskData> {
+    let offset = |t: &TaskData| {
+        (t.start_time.with_timezone(&Local) - job.start)
+            .num_seconds()
+            .abs()
+    };
+    tasks
+        .iter()
+        .filter(|t| t.device_id == *job.printer && t.weight > 0.)
+        .min_by_key(|t| offset(t))
+        .filter(|t| offset(t) <= CLOUD_MATCH_MINUTES * 60)
+}
+
+/// subtracts the weights of a claimed job from the inventory spools that were used
+fn record_usage(spools: &SpoolInventory, job: &JobRecord) {
+    for tray in job.trays.iter() {
+        if let (Some(spool), Some(grams)) = (tray.spool, tray.grams) {
+            spools.use_filament(spool, grams);
+        }
+    }
+}
+
+/// Fills in filament weights for ended jobs from synced cloud tasks,
+/// returns the jobs that changed
+pub fn apply_cloud_tasks(
+    db: &JobDb,
+    spools: &SpoolInventory,
+    tasks: &[TaskData],
+) -> Result<Vec<JobRecord>> {
+    let mut out = vec![];
+    for job in db.all_jobs()? {
+        if job.result == JobResult::Running || job.filament_grams().is_some() {
+            continue;
+        }
+        if match_cloud_task(&job, tasks).is_none() {
+            continue;
+        }
+
+        /// checked again under the lock, the job may have been claimed since the read
+        let claimed = db.claim_usage(job.id, |job| {
+            if let Some(task) = match_cloud_task(job, tasks) {
+                debug!("matched job {:?} to cloud task {}", job.name, task.id);
+                job.apply_cloud_task(task);
+            }
+        })?;
+        if let Some(job) = claimed {
+            record_usage(spools, &job);
+            out.push(job);
+        }
+    }
+    Ok(out)
+}
+
+/// Fills in filament weights for a job that just ended, from its cloud task if there is one,
+/// otherwise from the AMS estimates. Returns the job if any were found
+pub fn apply_job_usage(
+    db: &JobDb,
+    spools: &SpoolInventory,
+    id: i64,
+    tasks: &[TaskData],
+) -> Result<Option<JobRecord>> {
+    let claimed = db.claim_usage(id, |job| {
+        if let Some(task) = match_cloud_task(job, tasks) {
+            debug!("matched job {:?} to cloud task {}", job.name, task.id);
+            job.apply_cloud_task(task);
+        } else {
+            for tray in job.trays.iter_mut() {
+                tray.grams = tray.estimated_grams;
+            }
+        }
+    })?;
+
+    if let Some(job) = claimed.as_ref() {
+        record_usage(spools, job);
+    }
+    Ok(claimed)
+}
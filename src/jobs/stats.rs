@@ -0,0 +1,360 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+
+use crate::{cloud::errors::ErrorMap, config::PrinterConfig, conn_manager::PrinterId};
+
+use super::{cost::JobCost, JobRecord, JobResult, CANCELLED_ERROR};
+
+/// None is unbounded, jobs are counted by when they started
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsFilter {
+    pub start: Option<DateTime<Local>>,
+    pub end: Option<DateTime<Local>>,
+    pub group: Option<String>,
+}
+
+impl StatsFilter {
+    fn contains(&self, t: DateTime<Local>) -> bool {
+        self.start.is_none_or(|s| t >= s) && self.end.is_none_or(|e| t < e)
+    }
+
+    /// jobs from printers that were removed only match when not filtering by group
+    fn in_group(&self, job: &JobRecord, printers: &[PrinterConfig]) -> bool {
+        match printers.iter().find(|p| p.serial == job.printer) {
+            Some(p) => self.group.is_none() || p.group == self.group,
+            None => self.group.is_none(),
+        }
+    }
+
+    pub fn matches(&self, job: &JobRecord, printers: &[PrinterConfig]) -> bool {
+        self.in_group(job, printers) && self.contains(job.start)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrinterStats {
+    pub printer: PrinterId,
+    pub name: String,
+    pub group: Option<String>,
+    pub jobs: usize,
+    pub finished: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub interrupted: usize,
+    /// only the part of each job inside the window
+    pub hours: f64,
+    /// fraction of the window spent printing
+    pub utilization: f64,
+    /// filament and electricity, for jobs where it's known
+    pub cost: f64,
+}
+
+impl PrinterStats {
+    fn new(printer: PrinterId, name: String, group: Option<String>) -> Self {
+        Self {
+            printer,
+            name,
+            group,
+            jobs: 0,
+            finished: 0,
+            failed: 0,
+            cancelled: 0,
+            interrupted: 0,
+            hours: 0.,
+            utilization: 0.,
+            cost: 0.,
+        }
+    }
+
+    fn ended(&self) -> usize {
+        self.finished + self.failed + self.cancelled + self.interrupted
+    }
+
+    /// of jobs that have ended, None if none have
+    pub fn success_rate(&self) -> Option<f64> {
+        let ended = self.ended();
+        (ended > 0).then(|| self.finished as f64 / ended as f64)
+    }
+
+    pub fn failure_rate(&self) -> Option<f64> {
+        let ended = self.ended();
+        (ended > 0).then(|| self.failed as f64 / ended as f64)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FilamentStats {
+    pub material: String,
+    pub color: [u8; 3],
+    pub grams: f64,
+    pub jobs: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorStats {
+    pub code: i64,
+    pub text: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FleetStats {
+    /// printers in the group with no jobs are still listed
+    pub printers: Vec<PrinterStats>,
+    /// heaviest first
+    pub filament: Vec<FilamentStats>,
+    /// jobs with no weights yet, synced from cloud tasks
+    pub jobs_without_filament: usize,
+    /// most common first, cancellations are left out
+    pub errors: Vec<ErrorStats>,
+    pub window_hours: f64,
+}
+
+impl FleetStats {
+    pub fn compute(
+        jobs: &[JobRecord],
+        printers: &[PrinterConfig],
+        error_map: Option<&ErrorMap>,
+        filter: &StatsFilter,
+        now: DateTime<Local>,
+        cost: impl Fn(&JobRecord) -> JobCost,
+    ) -> Self {
+        let mut per_printer: Vec<PrinterStats> = printers
+            .iter()
+            .filter(|p| filter.group.is_none() || p.group == filter.group)
+            .map(|p| PrinterStats::new(p.serial.clone(), p.name.clone(), p.group.clone()))
+            .collect();
+
+        let jobs = jobs
+            .iter()
+            .filter(|j| filter.in_group(j, printers))
+            .collect::<Vec<_>>();
+
+        let window_end = filter.end.map_or(now, |e| e.min(now));
+        let window_start = filter
+            .start
+            .or_else(|| jobs.iter().map(|j| j.start).min())
+            .unwrap_or(window_end);
+        let window_hours = hours(window_end - window_start).max(0.);
+
+        let mut filament: HashMap<(String, [u8; 3]), FilamentStats> = HashMap::new();
+        let mut errors: HashMap<i64, ErrorStats> = HashMap::new();
+        let mut jobs_without_filament = 0;
+
+        for job in jobs {
+            let idx = match per_printer.iter().position(|p| p.printer == job.printer) {
+                Some(idx) => idx,
+                None => {
+                    per_printer.push(PrinterStats::new(
+                        job.printer.clone(),
+                        job.printer_name.clone(),
+                        None,
+                    ));
+                    per_printer.len() - 1
+                }
+            };
+            let stats = &mut per_printer[idx];
+
+            let start = job.start.max(window_start);
+            let end = job.end.unwrap_or(now).min(window_end);
+            if end > start {
+                stats.hours += hours(end - start);
+            }
+
+            if !filter.contains(job.start) {
+                continue;
+            }
+
+            stats.jobs += 1;
+            stats.cost += cost(job).total().unwrap_or(0.);
+            match job.result {
+                JobResult::Running => {}
+                JobResult::Finished => stats.finished += 1,
+                JobResult::Failed => stats.failed += 1,
+                JobResult::Cancelled => stats.cancelled += 1,
+                JobResult::Interrupted => stats.interrupted += 1,
+            }
+
+            if job.filament_grams().is_none() {
+                if job.result != JobResult::Running {
+                    jobs_without_filament += 1;
+                }
+            } else {
+                for tray in job.trays.iter() {
+                    let Some(grams) = tray.grams else {
+                        continue;
+                    };
+                    let material = if tray.material.is_empty() {
+                        "Unknown".to_string()
+                    } else {
+                        tray.material.clone()
+                    };
+                    let entry = filament
+                        .entry((material.clone(), tray.color))
+                        .or_insert_with(|| FilamentStats {
+                            material,
+                            color: tray.color,
+                            grams: 0.,
+                            jobs: 0,
+                        });
+                    entry.grams += grams;
+                    entry.jobs += 1;
+                }
+            }
+
+            if let Some(code) = job.error_code.filter(|c| *c != CANCELLED_ERROR) {
+                errors
+                    .entry(code)
+                    .or_insert_with(|| ErrorStats {
+                        code,
+                        text: error_map
+                            .and_then(|m| m.get_error(code as u64))
+                            .map(|s| s.to_string())
+                            .or_else(|| job.error_text.clone())
+                            .unwrap_or_else(|| "Unknown Error".to_string()),
+                        count: 0,
+                    })
+                    .count += 1;
+            }
+        }
+
+        for stats in per_printer.iter_mut() {
+            if window_hours > 0. {
+                stats.utilization = (stats.hours / window_hours).min(1.);
+            }
+        }
+        per_printer.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut filament = filament.into_values().collect::<Vec<_>>();
+        filament.sort_by(|a, b| b.grams.total_cmp(&a.grams));
+
+        let mut errors = errors.into_values().collect::<Vec<_>>();
+        errors.sort_by(|a, b| b.count.cmp(&a.count).then(a.code.cmp(&b.code)));
+
+        Self {
+            printers: per_printer,
+            filament,
+            jobs_without_filament,
+            errors,
+            window_hours,
+        }
+    }
+
+    pub fn total_jobs(&self) -> usize {
+        self.printers.iter().map(|p| p.jobs).sum()
+    }
+
+    pub fn total_hours(&self) -> f64 {
+        self.printers.iter().map(|p| p.hours).sum()
+    }
+
+    pub fn total_cost(&self) -> f64 {
+        self.printers.iter().map(|p| p.cost).sum()
+    }
+
+    pub fn total_grams(&self) -> f64 {
+        self.filament.iter().map(|f| f.grams).sum()
+    }
+
+    /// across all printers in the filter
+    pub fn success_rate(&self) -> Option<f64> {
+        let finished = self.printers.iter().map(|p| p.finished).sum::<usize>();
+        let ended = self.printers.iter().map(|p| p.ended()).sum::<usize>();
+        (ended > 0).then(|| finished as f64 / ended as f64)
+    }
+
+    pub fn failure_rate(&self) -> Option<f64> {
+        let failed = self.printers.iter().map(|p| p.failed).sum::<usize>();
+        let ended = self.printers.iter().map(|p| p.ended()).sum::<usize>();
+        (ended > 0).then(|| failed as f64 / ended as f64)
+    }
+}
+
+fn hours(d: chrono::Duration) -> f64 {
+    d.num_seconds() as f64 / 3600.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(printer: &str, start: DateTime<Local>, hours: i64, result: JobResult) -> JobRecord {
+        JobRecord {
+            id: 0,
+            printer: std::sync::Arc::new(printer.to_string()),
+            printer_name: printer.to_string(),
+            subtask_id: None,
+            name: "job".to_string(),
+            start,
+            end: (result != JobResult::Running).then(|| start + chrono::Duration::hours(hours)),
+            result,
+            total_layers: None,
+            layer_reached: None,
+            trays: vec![],
+            error_code: None,
+            error_text: None,
+        }
+    }
+
+    #[test]
+    fn counts_and_rates() {
+        let now = Local::now();
+        let day = now - chrono::Duration::hours(24);
+        let mut failed = job("a", day, 1, JobResult::Failed);
+        failed.error_code = Some(1);
+        let mut cancelled = job("a", day, 1, JobResult::Cancelled);
+        cancelled.error_code = Some(CANCELLED_ERROR);
+        let jobs = [
+            job("a", day, 2, JobResult::Finished),
+            failed,
+            cancelled,
+            job("a", day, 1, JobResult::Interrupted),
+            job("b", now - chrono::Duration::hours(1), 0, JobResult::Running),
+        ];
+
+        let stats = FleetStats::compute(&jobs, &[], None, &StatsFilter::default(), now, |_| {
+            JobCost::default()
+        });
+
+        assert_eq!(stats.total_jobs(), 5);
+        assert_eq!(stats.window_hours, 24.);
+        let a = &stats.printers[0];
+        assert_eq!(
+            (a.finished, a.failed, a.cancelled, a.interrupted),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(a.hours, 5.);
+        assert_eq!(a.success_rate(), Some(0.25));
+        assert_eq!(stats.printers[1].success_rate(), None);
+        assert_eq!(stats.printers[1].hours, 1.);
+        assert_eq!(stats.failure_rate(), Some(0.25));
+        assert_eq!(stats.jobs_without_filament, 4);
+        assert_eq!(stats.errors.len(), 1);
+        assert_eq!(stats.errors[0].code, 1);
+    }
+
+    #[test]
+    fn hours_clipped_to_window() {
+        let now = Local::now();
+        let filter = StatsFilter {
+            start: Some(now - chrono::Duration::hours(10)),
+            ..Default::default()
+        };
+        let jobs = [job(
+            "a",
+            now - chrono::Duration::hours(12),
+            4,
+            JobResult::Finished,
+        )];
+
+        let stats = FleetStats::compute(&jobs, &[], None, &filter, now, |_| JobCost::default());
+
+        /// started before the window, so its time counts but the job doesn't
+        assert_eq!(stats.total_jobs(), 0);
+        assert_eq!(stats.printers[0].hours, 2.);
+        assert_eq!(stats.printers[0].utilization, 0.2);
+    }
+}
@@ -13,9 +13,12 @@ pub mod alert;
 pub mod auth;
 pub mod config;
 pub mod conn_manager;
+pub mod export;
+pub mod jobs;
 // pub mod ftp;
 pub mod logging;
 pub mod mqtt;
+pub mod spools;
 pub mod status;
 // pub mod tray;
 pub mod cloud;
@@ -40,7 +43,7 @@ use std::{collections::HashMap, env, sync::Arc, time::Duration, usize};
 // use bambulab::{Command, Message};
 
 use crate::{
-    cloud::streaming::{StreamCmd, WebcamTexture},
+    cloud::streaming::{StreamCmd, WebcamSnapshot, WebcamTexture},
     config::ConfigArc,
     conn_manager::{PrinterConnCmd, PrinterConnManager, PrinterConnMsg, PrinterId},
     status::bambu::PrinterStatus,
@@ -349,6 +352,16 @@ fn main() -> eframe::Result<()> {
         write!(file, "{}", panic_info).unwrap();
     }));
 
+    /// command line export, runs without the UI
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(|a| a.as_str()) == Some("export") {
+        if let Err(e) = export::run_cli(&args[1..]) {
+            eprintln!("export failed: {:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // let icon: egui::IconData = {
     //     let icon = include_bytes!("../icon.png");
     //     let icon = image::load_from_memory(icon).unwrap();
@@ -622,7 +635,7 @@ fn main() -> eframe::Result<()> {
 
     let cmd_tx2 = cmd_tx.clone();
 
-    let graphs = ui::plotting::Graphs::new();
+    let graphs = ui::plotting::Graphs::load_or_new(config.graphs());
     // let graphs = {
     //     warn!("using debug graph data");
     //     let id0 = config.printer_ids()[0].clone();
@@ -634,6 +647,23 @@ fn main() -> eframe::Result<()> {
     let handles: Arc<DashMap<PrinterId, WebcamTexture>> = Arc::new(DashMap::new());
     let handles2 = handles.clone();
 
+    let snapshots: Arc<DashMap<PrinterId, WebcamSnapshot>> = Arc::new(DashMap::new());
+    let snapshots2 = snapshots.clone();
+    let snapshots_timelapse = snapshots.clone();
+    let printer_states_timelapse = printer_states.clone();
+    let printer_states_stream = printer_states.clone();
+    let printer_states_alerts = printer_states.clone();
+
+    let (alert_tx, alert_rx) = tokio::sync::mpsc::unbounded_channel::<alert::Alert>();
+    let alert_log = alert::log::AlertLog::read_or_default();
+    let alert_log2 = alert_log.clone();
+
+    let job_db = jobs::db::JobDb::open_or_in_memory();
+    let job_db2 = job_db.clone();
+
+    let spools = spools::SpoolInventory::read_or_default();
+    let spools2 = spools.clone();
+
     // #[cfg(feature = "nope")]
     /// tokio thread
     std::thread::spawn(|| {
@@ -643,10 +673,13 @@ fn main() -> eframe::Result<()> {
             let ctx2 = ctx.clone();
 
             let config3 = config2.clone();
+            let snapshots3 = snapshots2.clone();
             tokio::task::spawn(async move {
                 let mut manager = crate::cloud::streaming::StreamManager::new(
                     config3.clone(),
                     handles2,
+                    snapshots3,
+                    printer_states_stream,
                     stream_cmd_rx,
                     ctx2,
                 );
@@ -656,6 +689,47 @@ fn main() -> eframe::Result<()> {
                 }
             });
 
+            /// periodically save graphs, also saved on exit
+            let graphs3 = graphs2.clone();
+            tokio::task::spawn(async move {
+                let mut interval =
+                    tokio::time::interval(Duration::from_secs(ui::plotting::Graphs::SAVE_INTERVAL));
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    let graphs = graphs3.clone();
+                    let _ = tokio::task::spawn_blocking(move || graphs.save()).await;
+                }
+            });
+
+            let config3 = config2.clone();
+            tokio::task::spawn(async move {
+                let mut manager = crate::cloud::timelapse::TimelapseManager::new(
+                    config3,
+                    printer_states_timelapse,
+                    snapshots_timelapse,
+                );
+
+                if let Err(e) = manager.run().await {
+                    error!("timelapse manager error: {:?}", e);
+                }
+            });
+
+            let config3 = config2.clone();
+            tokio::task::spawn(async move {
+                let mut manager = alert::AlertManager::new(
+                    config3,
+                    alert_rx,
+                    snapshots2,
+                    printer_states_alerts,
+                    alert_log2,
+                );
+
+                if let Err(e) = manager.run().await {
+                    error!("alert manager error: {:?}", e);
+                }
+            });
+
             // /// spawn image streamers
             // for printer in config2.printer_ids() {
             //     let config3 = config2.clone();
@@ -682,6 +756,9 @@ fn main() -> eframe::Result<()> {
                 ctx,
                 graphs2,
                 stream_cmd_tx2,
+                alert_tx,
+                job_db2,
+                spools2,
             )
             .await;
             // PrinterConnManager::new(config2, printer_states2, cmd_rx, msg_tx, ctx, alert_tx);
@@ -818,6 +895,9 @@ fn main() -> eframe::Result<()> {
                 stream_cmd_tx,
                 handles,
                 graphs,
+                alert_log,
+                job_db,
+                spools,
             ))
         }),
     )
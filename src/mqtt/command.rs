@@ -17,16 +17,70 @@ pub enum Command {
     SendGCodeTemplate(String),
     GetAccessories,
     ChangeAMSFilamentSetting(ChangeAMSFilamentSetting),
+    /// Load a tray (`ams_id * 4 + tray_id`, 254 for the external spool),
+    /// or unload with 255. Temperatures are for the loaded and the new filament.
+    ChangeFilament {
+        target: u64,
+        curr_temp: i64,
+        tar_temp: i64,
+    },
+    /// List the pressure advance calibrations saved for a nozzle diameter
+    ExtrusionCaliGet {
+        nozzle_diameter: String,
+    },
+    /// Use a saved calibration for a tray, -1 for the default K
+    ExtrusionCaliSel {
+        tray_id: u64,
+        cali_idx: i64,
+        filament_id: String,
+        nozzle_diameter: String,
+    },
+    /// Set a tray's K directly
+    ExtrusionCaliSet {
+        tray_id: u64,
+        k: f64,
+        n_coef: f64,
+        nozzle_temp: i64,
+        bed_temp: i64,
+    },
 }
 
+/// ams_id 255 and tray_id 254 for the external spool
 #[derive(Debug, Clone)]
 pub struct ChangeAMSFilamentSetting {
-    ams_id: i64,
-    tray_id: i64,
-    tray_color: [u8; 3],
-    nozzle_temp_min: i64,
-    nozzle_temp_max: i64,
-    tray_type: String,
+    pub ams_id: i64,
+    pub tray_id: i64,
+    /// Bambu filament preset, e.g. `GFL99` for Generic PLA
+    pub tray_info_idx: String,
+    pub tray_color: [u8; 3],
+    pub nozzle_temp_min: i64,
+    pub nozzle_temp_max: i64,
+    pub tray_type: String,
+}
+
+impl ChangeAMSFilamentSetting {
+    pub const EXTERNAL_AMS_ID: i64 = 255;
+    pub const EXTERNAL_TRAY_ID: i64 = 254;
+}
+
+/// tray_type, generic preset id, nozzle temp range
+pub const GENERIC_FILAMENTS: [(&str, &str, i64, i64); 9] = [
+    ("PLA", "GFL99", 190, 240),
+    ("PETG", "GFG99", 220, 260),
+    ("ABS", "GFB99", 240, 270),
+    ("ASA", "GFB98", 240, 270),
+    ("TPU", "GFU99", 200, 250),
+    ("PA", "GFN99", 260, 290),
+    ("PC", "GFC99", 260, 280),
+    ("PVA", "GFS99", 190, 240),
+    ("HIPS", "GFS98", 220, 270),
+];
+
+pub fn generic_filament(tray_type: &str) -> Option<(&'static str, &'static str, i64, i64)> {
+    GENERIC_FILAMENTS
+        .iter()
+        .find(|(t, _, _, _)| t.eq_ignore_ascii_case(tray_type))
+        .copied()
 }
 
 impl Command {
@@ -46,32 +100,83 @@ impl Command {
             }
             Self::SendGCodeTemplate(gcode) => SEND_GCODE_TEMPLATE_PAYLOAD.replace("<GCODE>", gcode),
             Self::GetAccessories => GET_ACCESSORIES_PAYLOAD.into(),
-            Self::ChangeAMSFilamentSetting(setting) => {
-                // format!(
-                //     r#"
-                //     "print": {{
-                //         "sequence_id": "0",
-                //         "command": "ams_filament_setting",
-                //         "ams_id": {},
-                //         "tray_id": {},
-                //         "tray_info_idx": "",
-                //         "tray_color": "{:02X}{:02X}{:02X}",
-                //         "nozzle_temp_min": {},
-                //         "nozzle_temp_max": {},
-                //         "tray_type": "{}"
-                //     }}
-                //     "#,
-                //     setting.ams_id,
-                //     setting.tray_id,
-                //     setting.tray_color[0],
-                //     setting.tray_color[1],
-                //     setting.tray_color[2],
-                //     setting.nozzle_temp_min,
-                //     setting.nozzle_temp_max,
-                //     setting.tray_type,
-                // )
-                panic!("ChangeAMSFilamentSetting is not implemented");
-            }
+            Self::ChangeAMSFilamentSetting(setting) => serde_json::json!({
+                "print": {
+                    "sequence_id": "0",
+                    "command": "ams_filament_setting",
+                    "ams_id": setting.ams_id,
+                    "tray_id": setting.tray_id,
+                    "tray_info_idx": setting.tray_info_idx,
+                    "tray_color": format!(
+                        "{:02X}{:02X}{:02X}FF",
+                        setting.tray_color[0], setting.tray_color[1], setting.tray_color[2]
+                    ),
+                    "nozzle_temp_min": setting.nozzle_temp_min,
+                    "nozzle_temp_max": setting.nozzle_temp_max,
+                    "tray_type": setting.tray_type,
+                }
+            })
+            .to_string(),
+            Self::ChangeFilament {
+                target,
+                curr_temp,
+                tar_temp,
+            } => serde_json::json!({
+                "print": {
+                    "sequence_id": "0",
+                    "command": "ams_change_filament",
+                    "target": target,
+                    "curr_temp": curr_temp,
+                    "tar_temp": tar_temp,
+                }
+            })
+            .to_string(),
+            Self::ExtrusionCaliGet { nozzle_diameter } => serde_json::json!({
+                "print": {
+                    "sequence_id": "0",
+                    "command": "extrusion_cali_get",
+                    "filament_id": "",
+                    "nozzle_diameter": nozzle_diameter,
+                }
+            })
+            .to_string(),
+            Self::ExtrusionCaliSel {
+                tray_id,
+                cali_idx,
+                filament_id,
+                nozzle_diameter,
+            } => serde_json::json!({
+                "print": {
+                    "sequence_id": "0",
+                    "command": "extrusion_cali_sel",
+                    "tray_id": tray_id,
+                    "cali_idx": cali_idx,
+                    "filament_id": filament_id,
+                    "nozzle_diameter": nozzle_diameter,
+                }
+            })
+            .to_string(),
+            Self::ExtrusionCaliSet {
+                tray_id,
+                k,
+                n_coef,
+                nozzle_temp,
+                bed_temp,
+            } => serde_json::json!({
+                "print": {
+                    "sequence_id": "0",
+                    "command": "extrusion_cali_set",
+                    "tray_id": tray_id,
+                    "setting_id": "",
+                    "name": "",
+                    "k_value": k,
+                    "n_coef": n_coef,
+                    "nozzle_temp": nozzle_temp,
+                    "bed_temp": bed_temp,
+                    "max_volumetric_speed": 0,
+                }
+            })
+            .to_string(),
         }
     }
 }
@@ -32,6 +32,8 @@ pub struct PrintData {
     pub bed_temper: Option<f64>,
     pub bed_target_temper: Option<f64>,
     pub chamber_temper: Option<f64>,
+    /// only sent by printers with a heated chamber
+    pub chamber_target_temper: Option<f64>,
     pub mc_print_stage: Option<String>,
     pub heatbreak_fan_speed: Option<String>,
     pub cooling_fan_speed: Option<String>,
@@ -85,6 +87,19 @@ pub struct PrintData {
     pub command: Option<String>,
     pub msg: Option<i64>,
     pub sequence_id: Option<String>,
+    /// saved calibrations, in the reply to `extrusion_cali_get`
+    pub filaments: Option<Vec<PrintCaliFilament>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrintCaliFilament {
+    pub cali_idx: i64,
+    pub filament_id: String,
+    pub name: String,
+    pub k_value: Value,
+    pub n_coef: Value,
+    pub nozzle_diameter: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -122,7 +137,11 @@ pub struct PrintAms {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PrintAmsData {
     pub id: String,
+    /// level from 1 to 5
     pub humidity: String,
+    /// percent, newer firmware only
+    #[serde(default)]
+    pub humidity_raw: Option<String>,
     pub temp: String,
     pub tray: Vec<PrintTray>,
 }
@@ -0,0 +1,372 @@
+pub mod spoolman;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use chrono::{DateTime, Local};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{
+    conn_manager::PrinterId,
+    status::{AmsSlot, AmsStatus},
+};
+
+/// Where a spool is loaded
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TrayLocation {
+    pub printer: PrinterId,
+    /// None for the external spool
+    pub ams_id: Option<u64>,
+    pub tray_id: Option<u64>,
+}
+
+impl TrayLocation {
+    pub fn tray(printer: PrinterId, ams_id: u64, tray_id: u64) -> Self {
+        Self {
+            printer,
+            ams_id: Some(ams_id),
+            tray_id: Some(tray_id),
+        }
+    }
+
+    pub fn external(printer: PrinterId) -> Self {
+        Self {
+            printer,
+            ams_id: None,
+            tray_id: None,
+        }
+    }
+
+    /// without the printer name
+    pub fn to_text(&self) -> String {
+        match (self.ams_id, self.tray_id) {
+            (Some(ams), Some(tray)) => format!("AMS {} Tray {}", ams + 1, tray + 1),
+            _ => "External Spool".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spool {
+    pub id: u64,
+    pub brand: String,
+    pub material: String,
+    pub color: [u8; 3],
+    /// grams of filament, not counting the spool itself
+    pub initial_weight: f64,
+    pub remaining_weight: f64,
+    /// Bambu RFID spools are matched to trays by this automatically
+    pub tray_uuid: Option<String>,
+    pub location: Option<TrayLocation>,
+    pub added: DateTime<Local>,
+    /// overrides the material price in the cost config
+    #[serde(default)]
+    pub price_per_kg: Option<f64>,
+}
+
+impl Spool {
+    pub fn new(brand: &str, material: &str, color: [u8; 3], initial_weight: f64) -> Self {
+        Self {
+            id: 0,
+            brand: brand.to_string(),
+            material: material.to_string(),
+            color,
+            initial_weight,
+            remaining_weight: initial_weight,
+            tray_uuid: None,
+            location: None,
+            added: Local::now(),
+            price_per_kg: None,
+        }
+    }
+
+    /// for a new RFID spool, using what the AMS read from the tag
+    fn from_slot(slot: &AmsSlot, uuid: &str) -> Self {
+        let initial = slot.tray_weight.unwrap_or(1000.);
+        let mut spool = Self::new(
+            slot.tray_sub_brands.as_deref().unwrap_or("Bambu"),
+            &slot.material,
+            [slot.color.r(), slot.color.g(), slot.color.b()],
+            initial,
+        );
+        if let Some(remain) = slot.remain {
+            spool.remaining_weight = initial * remain as f64 / 100.;
+        }
+        spool.tray_uuid = Some(uuid.to_string());
+        spool
+    }
+
+    pub fn name(&self) -> String {
+        format!("{} {}", self.brand, self.material)
+    }
+
+    pub fn remaining_fraction(&self) -> f64 {
+        if self.initial_weight <= 0. {
+            return 0.;
+        }
+        (self.remaining_weight / self.initial_weight).clamp(0., 1.)
+    }
+}
+
+/// A K value applied to a tray, kept so it can be re-applied after reloading a spool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KRecord {
+    /// filament preset, or the material for spools without one
+    pub filament_id: String,
+    pub filament_name: String,
+    pub nozzle_diameter: String,
+    pub k: f64,
+    pub printer: PrinterId,
+    pub time: DateTime<Local>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SpoolData {
+    next_id: u64,
+    spools: Vec<Spool>,
+    /// Spoolman spool ids, for when the inventory is kept there instead
+    #[serde(default)]
+    spoolman: Vec<(TrayLocation, u64)>,
+    /// oldest first
+    #[serde(default)]
+    k_history: Vec<KRecord>,
+}
+
+impl SpoolData {
+    fn assign(&mut self, id: u64, location: Option<TrayLocation>) {
+        if let Some(loc) = location.as_ref() {
+            for s in self.spools.iter_mut() {
+                if s.id != id && s.location.as_ref() == Some(loc) {
+                    s.location = None;
+                }
+            }
+        }
+        if let Some(s) = self.spools.iter_mut().find(|s| s.id == id) {
+            s.location = location;
+        }
+    }
+
+    /// Returns true if anything changed
+    fn match_tray(&mut self, location: TrayLocation, slot: Option<&AmsSlot>) -> bool {
+        let Some((slot, uuid)) = slot.and_then(|s| Some((s, s.rfid_uuid()?))) else {
+            /// the tray is empty or a third party spool replaced an RFID spool,
+            /// hand assignments are left alone
+            let mut changed = false;
+            for s in self.spools.iter_mut() {
+                if s.tray_uuid.is_some() && s.location.as_ref() == Some(&location) {
+                    s.location = None;
+                    changed = true;
+                }
+            }
+            return changed;
+        };
+
+        let id = match self
+            .spools
+            .iter()
+            .find(|s| s.tray_uuid.as_deref() == Some(uuid))
+        {
+            Some(s) if s.location.as_ref() == Some(&location) => return false,
+            Some(s) => s.id,
+            None => {
+                let mut spool = Spool::from_slot(slot, uuid);
+                spool.id = self.next_id;
+                self.next_id += 1;
+                info!("new RFID spool: {} {:?}", spool.name(), uuid);
+                let id = spool.id;
+                self.spools.push(spool);
+                id
+            }
+        };
+        self.assign(id, Some(location));
+        true
+    }
+}
+
+/// Filament spools on hand, shared between PrinterConnManager and the UI
+#[derive(Clone, Default)]
+pub struct SpoolInventory {
+    data: Arc<RwLock<SpoolData>>,
+}
+
+impl SpoolInventory {
+    const FILENAME: &'static str = "spools.json";
+
+    /// a missing or unreadable inventory starts empty
+    pub fn read_or_default() -> Self {
+        let data = match std::fs::read_to_string(Self::FILENAME) {
+            Ok(s) => match serde_json::from_str(&s) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("failed to parse spool inventory: {:?}", e);
+                    SpoolData::default()
+                }
+            },
+            Err(_) => SpoolData::default(),
+        };
+        Self {
+            data: Arc::new(RwLock::new(data)),
+        }
+    }
+
+    fn save(&self) {
+        let data = self.data.read();
+        let res = serde_json::to_string_pretty(&*data)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| std::fs::write(Self::FILENAME, s).map_err(anyhow::Error::from));
+        if let Err(e) = res {
+            error!("failed to save spool inventory: {:?}", e);
+        }
+    }
+
+    pub fn spools(&self) -> Vec<Spool> {
+        self.data.read().spools.clone()
+    }
+
+    /// newest first
+    pub fn k_history(&self, filament_id: &str, nozzle_diameter: &str) -> Vec<KRecord> {
+        self.data
+            .read()
+            .k_history
+            .iter()
+            .rev()
+            .filter(|r| r.filament_id == filament_id && r.nozzle_diameter == nozzle_diameter)
+            .cloned()
+            .collect()
+    }
+
+    /// skipped if it's the same as the last value for the filament and nozzle
+    pub fn record_k(&self, record: KRecord) {
+        {
+            let mut data = self.data.write();
+            let last = data.k_history.iter().rev().find(|r| {
+                r.filament_id == record.filament_id && r.nozzle_diameter == record.nozzle_diameter
+            });
+            if last.is_some_and(|r| (r.k - record.k).abs() < 0.0005) {
+                return;
+            }
+            info!(
+                "K {:.3} for {} with a {} mm nozzle",
+                record.k, record.filament_name, record.nozzle_diameter
+            );
+            data.k_history.push(record);
+        }
+        self.save();
+    }
+
+    pub fn get(&self, id: u64) -> Option<Spool> {
+        self.data.read().spools.iter().find(|s| s.id == id).cloned()
+    }
+
+    pub fn spool_at(&self, location: &TrayLocation) -> Option<Spool> {
+        self.data
+            .read()
+            .spools
+            .iter()
+            .find(|s| s.location.as_ref() == Some(location))
+            .cloned()
+    }
+
+    pub fn spoolman_at(&self, location: &TrayLocation) -> Option<u64> {
+        self.data
+            .read()
+            .spoolman
+            .iter()
+            .find(|(l, _)| l == location)
+            .map(|(_, id)| *id)
+    }
+
+    pub fn set_spoolman(&self, location: TrayLocation, id: Option<u64>) {
+        {
+            let mut data = self.data.write();
+            data.spoolman.retain(|(l, _)| *l != location);
+            if let Some(id) = id {
+                data.spoolman.push((location, id));
+            }
+        }
+        self.save();
+    }
+
+    /// Returns the new id
+    pub fn add(&self, mut spool: Spool) -> u64 {
+        let id = {
+            let mut data = self.data.write();
+            let id = data.next_id;
+            data.next_id += 1;
+            spool.id = id;
+            let location = spool.location.take();
+            data.spools.push(spool);
+            data.assign(id, location);
+            id
+        };
+        self.save();
+        id
+    }
+
+    /// replaces the spool with the same id, the location is set with `assign`
+    pub fn update(&self, spool: Spool) {
+        {
+            let mut data = self.data.write();
+            if let Some(s) = data.spools.iter_mut().find(|s| s.id == spool.id) {
+                let location = s.location.take();
+                *s = spool;
+                s.location = location;
+            }
+        }
+        self.save();
+    }
+
+    pub fn remove(&self, id: u64) {
+        self.data.write().spools.retain(|s| s.id != id);
+        self.save();
+    }
+
+    /// any other spool at the location is unassigned
+    pub fn assign(&self, id: u64, location: Option<TrayLocation>) {
+        self.data.write().assign(id, location);
+        self.save();
+    }
+
+    pub fn use_filament(&self, id: u64, grams: f64) {
+        {
+            let mut data = self.data.write();
+            let Some(s) = data.spools.iter_mut().find(|s| s.id == id) else {
+                warn!("used filament from a spool that was removed: {}", id);
+                return;
+            };
+            s.remaining_weight = (s.remaining_weight - grams).max(0.);
+            debug!(
+                "used {:.1} g from {}, {:.0} g left",
+                grams,
+                s.name(),
+                s.remaining_weight
+            );
+        }
+        self.save();
+    }
+
+    /// Matches RFID spools to the trays they were read in, adding any that are new,
+    /// and takes them out of trays that are now empty
+    pub fn sync_trays(&self, printer: &PrinterId, ams: &AmsStatus) {
+        let mut changed = false;
+        {
+            let mut data = self.data.write();
+            for unit in ams.units.values() {
+                for (i, slot) in unit.slots.iter().enumerate() {
+                    let slot = slot
+                        .as_ref()
+                        .filter(|_| !ams.tray_flags(unit.id as u64, i as u64).is_empty());
+                    let location = TrayLocation::tray(printer.clone(), unit.id as u64, i as u64);
+                    changed |= data.match_tray(location, slot);
+                }
+            }
+            let location = TrayLocation::external(printer.clone());
+            changed |= data.match_tray(location, ams.external.as_ref());
+        }
+        if changed {
+            self.save();
+        }
+    }
+}
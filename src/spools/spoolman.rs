@@ -0,0 +1,135 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use serde::Deserialize;
+
+use std::sync::Arc;
+
+use crate::jobs::db::JobDb;
+
+/// only the fields we use
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpoolmanSpool {
+    pub id: u64,
+    pub filament: SpoolmanFilament,
+    /// None if the filament has no weight set
+    #[serde(default)]
+    pub remaining_weight: Option<f64>,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpoolmanFilament {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub vendor: Option<SpoolmanVendor>,
+    #[serde(default)]
+    pub material: Option<String>,
+    /// RRGGBB, without the #
+    #[serde(default)]
+    pub color_hex: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpoolmanVendor {
+    pub name: String,
+}
+
+impl SpoolmanSpool {
+    pub fn name(&self) -> String {
+        let f = &self.filament;
+        let name = f.name.as_ref().or(f.material.as_ref());
+        match (f.vendor.as_ref(), name) {
+            (Some(v), Some(n)) => format!("{} {}", v.name, n),
+            (None, Some(n)) => n.clone(),
+            _ => format!("Spool #{}", self.id),
+        }
+    }
+
+    pub fn color(&self) -> Option<egui::Color32> {
+        let hex = self.filament.color_hex.as_ref()?;
+        egui::Color32::from_hex(&format!("#{}", hex.get(..6)?)).ok()
+    }
+}
+
+/// Client for the Spoolman REST API
+#[derive(Clone)]
+pub struct SpoolmanClient {
+    client: reqwest::Client,
+    url: String,
+    /// held while reporting so two syncs can't report the same tray
+    report_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl SpoolmanClient {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::ClientBuilder::new().use_rustls_tls().build()?,
+            url: url.trim_end_matches('/').to_string(),
+            report_lock: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    /// not archived
+    pub async fn spools(&self) -> Result<Vec<SpoolmanSpool>> {
+        let res = self
+            .client
+            .get(format!("{}/api/v1/spool", self.url))
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            bail!("spoolman returned status: {}", res.status());
+        }
+        Ok(res.json().await?)
+    }
+
+    pub async fn use_weight(&self, id: u64, grams: f64) -> Result<SpoolmanSpool> {
+        let res = self
+            .client
+            .put(format!("{}/api/v1/spool/{}/use", self.url, id))
+            .json(&serde_json::json!({ "use_weight": grams }))
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            bail!("spoolman returned status: {}", res.status());
+        }
+        Ok(res.json().await?)
+    }
+
+    /// Reports usage for every job tray still pending, a tray that fails stays pending
+    /// for the next call. Returns true if anything was reported
+    pub async fn report_pending(&self, db: &JobDb) -> Result<bool> {
+        let _guard = self.report_lock.lock().await;
+        let mut reported = false;
+        for mut job in db.all_jobs()? {
+            let mut changed = false;
+            for tray in job.trays.iter_mut().filter(|t| t.spoolman_pending) {
+                let (Some(id), Some(grams)) = (tray.spoolman_id, tray.grams) else {
+                    tray.spoolman_pending = false;
+                    changed = true;
+                    continue;
+                };
+                match self.use_weight(id, grams).await {
+                    Ok(spool) => {
+                        info!(
+                            "reported {:.1} g to spoolman for {}, {:.0} g left",
+                            grams,
+                            spool.name(),
+                            spool.remaining_weight.unwrap_or(0.)
+                        );
+                        tray.spoolman_pending = false;
+                        changed = true;
+                        reported = true;
+                    }
+                    Err(e) => error!("failed to report usage for spool {}: {:?}", id, e),
+                }
+            }
+            if changed {
+                db.update(&job)?;
+            }
+        }
+        Ok(reported)
+    }
+}
@@ -11,11 +11,13 @@ use std::{
 
 use crate::{
     config::PrinterConfig,
-    mqtt::message::{PrintAms, PrintData},
+    mqtt::message::{PrintAms, PrintData, PrintVtTray},
     status::PrinterState,
 };
 
-use super::{AmsCurrentSlot, AmsSlot, AmsStatus, AmsUnit, PrintError, PrinterType};
+use super::{
+    AmsCurrentSlot, AmsHumidity, AmsSlot, AmsStatus, AmsUnit, KProfile, PrintError, PrinterType,
+};
 
 #[derive(Default, Debug, Clone)]
 pub struct PrinterStatus {
@@ -54,17 +56,29 @@ pub struct PrinterStatus {
 
     pub chamber_light: Option<bool>,
 
+    /// e.g. "0.4"
+    pub nozzle_diameter: Option<String>,
+    /// from the last `extrusion_cali_get`
+    pub k_profiles: Vec<KProfile>,
+
     pub temp_nozzle: Option<f64>,
     pub temp_tgt_nozzle: Option<f64>,
     pub temp_bed: Option<f64>,
     pub temp_tgt_bed: Option<f64>,
     pub temp_chamber: Option<f64>,
+    /// only printers with a heated chamber
+    pub temp_tgt_chamber: Option<f64>,
 
     pub fan_gear: Option<i64>,
     pub heatbreak_fan_speed: Option<i64>,
     pub cooling_fan_speed: Option<i64>,
     pub aux_fan_speed: Option<i64>,
     pub chamber_fan_speed: Option<i64>,
+
+    /// set when a print finishes, until the plate is cleared or a new job starts
+    pub awaiting_bed_clear: bool,
+    /// "bed cooled" alert already sent for the finished print
+    pub bed_cooled_alerted: bool,
 }
 
 #[cfg(feature = "nope")]
@@ -84,8 +98,30 @@ impl PrinterStatus {
         matches!(self.state, PrinterState::Error(_))
     }
 
+    /// keeps the plate-clearing state, which isn't reported by the printer
     pub fn reset(&mut self) {
-        *self = Self::default();
+        *self = Self {
+            awaiting_bed_clear: self.awaiting_bed_clear,
+            bed_cooled_alerted: self.bed_cooled_alerted,
+            /// doesn't change on reconnect, and the stream was picked from it
+            printer_type: self.printer_type,
+            ..Self::default()
+        };
+    }
+
+    /// gcode_state FAILED, either an error or cancelled
+    pub fn job_failed(&self) -> bool {
+        matches!(&self.state, PrinterState::Error(s) if s == "Failed")
+    }
+
+    /// finished and the part hasn't been removed, the card is highlighted and "Clear Plate" shown
+    pub fn needs_plate_cleared(&self) -> bool {
+        self.awaiting_bed_clear && self.state == PrinterState::Finished
+    }
+
+    pub fn clear_bed(&mut self) {
+        self.awaiting_bed_clear = false;
+        self.bed_cooled_alerted = false;
     }
 
     fn get_state(report: &PrintData) -> Option<PrinterState> {
@@ -157,6 +193,27 @@ impl PrinterStatus {
             }
         }
 
+        if let Some(d) = report.nozzle_diameter.as_ref() {
+            self.nozzle_diameter = Some(d.clone());
+        }
+
+        if report.command.as_deref() == Some("extrusion_cali_get") {
+            if let Some(filaments) = report.filaments.as_ref() {
+                self.k_profiles = filaments
+                    .iter()
+                    .map(|f| KProfile {
+                        cali_idx: f.cali_idx,
+                        filament_id: f.filament_id.clone(),
+                        name: f.name.clone(),
+                        k: value_f64(&f.k_value).unwrap_or(0.),
+                        n_coef: value_f64(&f.n_coef).unwrap_or(0.),
+                        nozzle_diameter: f.nozzle_diameter.clone(),
+                    })
+                    .collect();
+                debug!("got {} K profiles", self.k_profiles.len());
+            }
+        }
+
         if let Some(s) = report.mc_print_sub_stage {
             self.sub_stage = Some(s);
         }
@@ -240,6 +297,9 @@ impl PrinterStatus {
         if let Some(t) = report.chamber_temper {
             self.temp_chamber = Some(t);
         }
+        if let Some(t) = report.chamber_target_temper {
+            self.temp_tgt_chamber = Some(t);
+        }
 
         // if let Some(t) = report.heatbreak_fan_speed {
         //     self.heatbreak_fan_speed = Some(t);
@@ -249,9 +309,11 @@ impl PrinterStatus {
             self.fan_gear = Some(t);
         }
 
-        if let Some(t) = self.heatbreak_fan_speed.as_ref() {
-            let t = (*t as f32 / 1.5).round() as i64 * 10;
-            self.heatbreak_fan_speed = Some(t);
+        if let Some(t) = report.heatbreak_fan_speed.as_ref() {
+            if let Ok(t) = t.parse::<i64>() {
+                let t = (t as f32 / 1.5).round() as i64 * 10;
+                self.heatbreak_fan_speed = Some(t);
+            }
         }
 
         if let Some(t) = report.cooling_fan_speed.as_ref() {
@@ -280,29 +342,34 @@ impl PrinterStatus {
         }
 
         if let Some(ams) = report.ams.as_ref() {
-            self.ams = Some(self.update_ams(ams, self.ams_status)?);
+            self.ams = Some(self.update_ams(ams)?);
+        }
+
+        /// also sent by printers without an AMS
+        if let Some(vt) = report.vt_tray.as_ref() {
+            self.ams.get_or_insert_with(AmsStatus::default).external = vt_tray_slot(vt);
+        }
+
+        /// the state is sent without the rest of the AMS during a filament swap
+        if report.ams.is_some() || report.ams_status.is_some() {
+            if let (Some(ams), Some(code)) = (self.ams.as_mut(), self.ams_status) {
+                ams.set_state(code);
+            }
         }
 
         Ok(())
     }
 
-    fn update_ams(&mut self, ams: &PrintAms, status_code: Option<i64>) -> Result<AmsStatus> {
+    fn update_ams(&mut self, ams: &PrintAms) -> Result<AmsStatus> {
         let mut out = self.ams.take().unwrap_or_default();
 
         // debug!("ams = {:#?}", ams);
 
-        /// 254 if external spool / vt_tray,
+        /// 254 if external spool / vt_tray, 255 if unloaded,
         /// otherwise is ((ams_id * 4) + tray_id) for current tray
         /// (ams 2 tray 2 would be (1*4)+1 = 5)
         if let Some(current) = ams.tray_now.as_ref().and_then(|t| t.parse::<u64>().ok()) {
-            out.current_tray = if current == 254 {
-                Some(AmsCurrentSlot::ExternalSpool)
-            } else {
-                Some(AmsCurrentSlot::Tray {
-                    ams_id: current / 4,
-                    tray_id: current % 4,
-                })
-            };
+            out.current_tray = AmsCurrentSlot::from_tray_index(current);
         } else {
             // out.current_tray = None;
         }
@@ -325,6 +392,21 @@ impl PrinterStatus {
                         material: slot.tray_type.clone().unwrap_or("Unknown".to_string()),
                         k: slot.k.unwrap_or(0.),
                         color,
+                        remain: slot.remain.filter(|r| *r >= 0),
+                        tray_weight: slot
+                            .tray_weight
+                            .as_ref()
+                            .and_then(|w| w.parse::<f64>().ok())
+                            .filter(|w| *w > 0.),
+                        tray_sub_brands: slot.tray_sub_brands.clone().filter(|s| !s.is_empty()),
+                        tray_diameter: parse_nonzero(slot.tray_diameter.as_deref()),
+                        nozzle_temp_min: parse_nonzero(slot.nozzle_temp_min.as_deref()),
+                        nozzle_temp_max: parse_nonzero(slot.nozzle_temp_max.as_deref()),
+                        bed_temp: parse_nonzero(slot.bed_temp.as_deref()),
+                        tray_id_name: slot.tray_id_name.clone().filter(|s| !s.is_empty()),
+                        tray_info_idx: slot.tray_info_idx.clone().filter(|s| !s.is_empty()),
+                        tag_uid: slot.tag_uid.clone().filter(|s| s.chars().any(|c| c != '0')),
+                        tray_uuid: slot.tray_uuid.clone(),
                     });
                 }
 
@@ -340,7 +422,8 @@ impl PrinterStatus {
                     id,
                     AmsUnit {
                         id,
-                        humidity: unit.humidity.parse().unwrap_or(0),
+                        humidity: AmsHumidity::from_level(&unit.humidity),
+                        humidity_percent: parse_nonzero(unit.humidity_raw.as_deref()),
                         temp: unit.temp.parse().unwrap_or(0.),
                         slots,
                     },
@@ -348,12 +431,25 @@ impl PrinterStatus {
             }
         }
 
-        if let Some(bits) = ams.ams_exist_bits.as_ref() {
-            out.ams_exist_bits = Some(bits.clone());
+        if let Some(bits) = parse_bits(ams.ams_exist_bits.as_deref()) {
+            out.ams_exist_bits = Some(bits);
+
+            /// drop units that have been disconnected
+            let ids = out.unit_ids();
+            out.units.retain(|id, _| ids.contains(id));
         }
 
-        if let Some(bits) = ams.tray_exist_bits.as_ref() {
-            out.tray_exist_bits = Some(bits.clone());
+        if let Some(bits) = parse_bits(ams.tray_exist_bits.as_deref()) {
+            out.tray_exist_bits = Some(bits);
+        }
+        if let Some(bits) = parse_bits(ams.tray_is_bbl_bits.as_deref()) {
+            out.tray_is_bbl_bits = Some(bits);
+        }
+        if let Some(bits) = parse_bits(ams.tray_reading_bits.as_deref()) {
+            out.tray_reading_bits = Some(bits);
+        }
+        if let Some(bits) = parse_bits(ams.tray_read_done_bits.as_deref()) {
+            out.tray_read_done_bits = Some(bits);
         }
 
         if let Some(now) = ams.tray_now.as_ref() {
@@ -370,23 +466,83 @@ impl PrinterStatus {
             out.version = Some(v);
         }
 
-        if let Some(status_code) = status_code {
-            if status_code == 768 {
-                out.state = None;
-            } else {
-                let state = crate::utils::parse_ams_status(&out, status_code);
-
-                // match state {
-                //     crate::ui::ui_types::AmsState::FilamentChange(_) => {
-                //         // out.current_tray
-                //     },
-                //     _ => {}
-                // }
+        Ok(out)
+    }
+}
 
-                out.state = Some(state);
-            }
+/// the AMS bitfields are hex strings, e.g. "f" for the four trays of the first unit
+fn parse_bits(s: Option<&str>) -> Option<u32> {
+    let s = s?.trim();
+    if s.is_empty() {
+        return None;
+    }
+    match u32::from_str_radix(s, 16) {
+        Ok(bits) => Some(bits),
+        Err(e) => {
+            warn!("invalid AMS bits {:?}: {:?}", s, e);
+            None
         }
+    }
+}
 
-        Ok(out)
+/// numbers are sent as either strings or numbers
+fn value_f64(v: &serde_json::Value) -> Option<f64> {
+    match v {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// the printer sends "0" or "" for fields that aren't set
+fn parse_nonzero<T: std::str::FromStr + PartialOrd + Default>(s: Option<&str>) -> Option<T> {
+    s?.trim().parse::<T>().ok().filter(|v| *v > T::default())
+}
+
+/// None when no filament is set. There's no sensor on the external spool, so no remaining percent
+fn vt_tray_slot(vt: &PrintVtTray) -> Option<AmsSlot> {
+    if vt.tray_type.is_empty() {
+        return None;
+    }
+    let color =
+        Color32::from_hex(&format!("#{}", vt.tray_color)).unwrap_or(Color32::from_rgb(255, 0, 255));
+    Some(AmsSlot {
+        material: vt.tray_type.clone(),
+        k: vt.k,
+        color,
+        remain: None,
+        tray_weight: parse_nonzero(Some(&vt.tray_weight)),
+        tray_sub_brands: Some(vt.tray_sub_brands.clone()).filter(|s| !s.is_empty()),
+        tray_diameter: parse_nonzero(Some(&vt.tray_diameter)),
+        nozzle_temp_min: parse_nonzero(Some(&vt.nozzle_temp_min)),
+        nozzle_temp_max: parse_nonzero(Some(&vt.nozzle_temp_max)),
+        bed_temp: parse_nonzero(Some(&vt.bed_temp)),
+        tray_id_name: Some(vt.tray_id_name.clone()).filter(|s| !s.is_empty()),
+        tray_info_idx: Some(vt.tray_info_idx.clone()).filter(|s| !s.is_empty()),
+        tag_uid: Some(vt.tag_uid.clone()).filter(|s| s.chars().any(|c| c != '0')),
+        tray_uuid: Some(vt.tray_uuid.clone()).filter(|s| !s.is_empty()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_are_hex() {
+        assert_eq!(parse_bits(Some("f")), Some(0xf));
+        assert_eq!(parse_bits(Some(" 1F0 ")), Some(0x1f0));
+        assert_eq!(parse_bits(Some("0")), Some(0));
+        assert_eq!(parse_bits(Some("")), None);
+        assert_eq!(parse_bits(Some("xyz")), None);
+        assert_eq!(parse_bits(None), None);
+    }
+
+    #[test]
+    fn numbers_or_strings() {
+        assert_eq!(value_f64(&serde_json::json!(25.5)), Some(25.5));
+        assert_eq!(value_f64(&serde_json::json!("25.5")), Some(25.5));
+        assert_eq!(value_f64(&serde_json::json!("")), None);
+        assert_eq!(value_f64(&serde_json::json!(null)), None);
     }
 }
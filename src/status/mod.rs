@@ -16,7 +16,11 @@ use std::{
 
 use crate::{
     config::PrinterConfig,
-    mqtt::message::{PrintAms, PrintData},
+    mqtt::{
+        command::generic_filament,
+        message::{PrintAms, PrintData},
+    },
+    ui::ui_types::{AmsState, FilamentSwapStep},
 };
 
 // use crate::app_types::StatusIcon;
@@ -155,34 +159,210 @@ impl PrintError {
 #[derive(Debug, Default, Clone)]
 pub struct AmsStatus {
     pub units: HashMap<i64, AmsUnit>,
+    /// vt_tray, None if no filament is set for it
+    pub external: Option<AmsSlot>,
     pub current_tray: Option<AmsCurrentSlot>,
     // pub id: Option<i64>,
     // pub humidity: Option<i64>,
     // pub temp: Option<i64>,
     // pub slots: [Option<AmsSlot>; 4],
     // pub current_slot: Option<u64>,
-    pub ams_exist_bits: Option<String>,
-    pub tray_exist_bits: Option<String>,
+    /// bit `ams_id` set for each connected unit
+    pub ams_exist_bits: Option<u32>,
+    /// bit `ams_id * 4 + tray_id` set for each tray
+    pub tray_exist_bits: Option<u32>,
+    pub tray_is_bbl_bits: Option<u32>,
+    pub tray_reading_bits: Option<u32>,
+    pub tray_read_done_bits: Option<u32>,
     pub tray_now: Option<String>,
     pub tray_pre: Option<String>,
     pub tray_tar: Option<String>,
     pub version: Option<i64>,
     pub state: Option<crate::ui::ui_types::AmsState>,
+    /// when `state` last changed
+    pub state_since: Option<Instant>,
+    /// `state_since` of the swap step already alerted as stalled
+    pub stall_alerted: Option<Instant>,
 }
 
+/// A pressure advance calibration saved on the printer, from `extrusion_cali_get`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KProfile {
+    pub cali_idx: i64,
+    pub filament_id: String,
+    pub name: String,
+    pub k: f64,
+    pub n_coef: f64,
+    pub nozzle_diameter: String,
+}
+
+/// decoded from the AMS bitfields
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AmsTrayFlags {
+    /// None until the printer reports `tray_exist_bits`
+    pub present: Option<bool>,
+    /// Bambu spool with an RFID tag
+    pub bambu_spool: bool,
+    /// RFID tag is being read
+    pub reading: bool,
+    pub read_done: bool,
+}
+
+impl AmsTrayFlags {
+    pub fn is_empty(&self) -> bool {
+        self.present == Some(false)
+    }
+
+    /// a spool is loaded and its tag has been read without finding a Bambu spool
+    pub fn is_third_party(&self) -> bool {
+        self.present == Some(true) && self.read_done && !self.bambu_spool
+    }
+}
+
+/// a filament swap step taking longer than this is assumed to be stuck
+pub const FILAMENT_SWAP_STALL: Duration = Duration::from_secs(5 * 60);
+
 impl AmsStatus {
     pub fn is_ams_unload(&self) -> bool {
         self.tray_tar.as_ref().map(|s| s.as_str()) == Some("255")
     }
+
+    /// from `ams_status`, 768 when the AMS isn't doing anything
+    pub fn set_state(&mut self, status_code: i64) {
+        let state = if status_code == 768 {
+            None
+        } else {
+            Some(crate::utils::parse_ams_status(self, status_code))
+        };
+        if state != self.state {
+            self.state_since = Some(Instant::now());
+        }
+        self.state = state;
+    }
+
+    /// Some while a filament load or unload is running
+    pub fn swap_step(&self) -> Option<FilamentSwapStep> {
+        match self.state {
+            Some(AmsState::FilamentChange(step)) => Some(step),
+            _ => None,
+        }
+    }
+
+    /// the tray being loaded, None when unloading
+    pub fn swap_target(&self) -> Option<AmsCurrentSlot> {
+        self.tray_tar
+            .as_ref()
+            .and_then(|t| t.parse::<u64>().ok())
+            .and_then(AmsCurrentSlot::from_tray_index)
+    }
+
+    /// the current swap step has lasted longer than [`FILAMENT_SWAP_STALL`]
+    pub fn swap_stalled(&self) -> bool {
+        self.swap_step().is_some()
+            && self
+                .state_since
+                .is_some_and(|t| t.elapsed() > FILAMENT_SWAP_STALL)
+    }
+
+    /// true once per stalled step, for sending a single alert
+    pub fn take_swap_stall(&mut self) -> bool {
+        if !self.swap_stalled() || self.stall_alerted == self.state_since {
+            return false;
+        }
+        self.stall_alerted = self.state_since;
+        true
+    }
+
+    /// the external spool is always in use on printers without an AMS
+    pub fn active_slot(&self) -> Option<AmsCurrentSlot> {
+        self.current_tray.or_else(|| {
+            (self.units.is_empty() && self.external.is_some())
+                .then_some(AmsCurrentSlot::ExternalSpool)
+        })
+    }
+
+    /// units reported by `ams_exist_bits`, falling back to the units seen so far
+    pub fn unit_ids(&self) -> Vec<i64> {
+        match self.ams_exist_bits {
+            Some(bits) => (0..4).filter(|i| bits & (1 << i) != 0).collect(),
+            None => {
+                let mut ids = self.units.keys().copied().collect::<Vec<_>>();
+                ids.sort();
+                ids
+            }
+        }
+    }
+
+    pub fn tray_flags(&self, ams_id: u64, tray_id: u64) -> AmsTrayFlags {
+        let bit = ams_id * 4 + tray_id;
+        let get = |bits: Option<u32>| bits.map(|b| bit < 32 && b & (1 << bit) != 0);
+        AmsTrayFlags {
+            present: get(self.tray_exist_bits),
+            bambu_spool: get(self.tray_is_bbl_bits).unwrap_or(false),
+            reading: get(self.tray_reading_bits).unwrap_or(false),
+            read_done: get(self.tray_read_done_bits).unwrap_or(false),
+        }
+    }
+
+    /// None for trays reported empty, even if their last filament is still known
+    pub fn get_slot(&self, slot: AmsCurrentSlot) -> Option<&AmsSlot> {
+        match slot {
+            AmsCurrentSlot::ExternalSpool => self.external.as_ref(),
+            AmsCurrentSlot::Tray { ams_id, tray_id }
+                if self.tray_flags(ams_id, tray_id).is_empty() =>
+            {
+                None
+            }
+            AmsCurrentSlot::Tray { ams_id, tray_id } => self
+                .units
+                .get(&(ams_id as i64))?
+                .slots
+                .get(tray_id as usize)?
+                .as_ref(),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AmsCurrentSlot {
     ExternalSpool,
     Tray { ams_id: u64, tray_id: u64 },
 }
 
 impl AmsCurrentSlot {
+    /// `tray_now`, `tray_pre` and `tray_tar` are `ams_id * 4 + tray_id`,
+    /// 254 for the external spool and 255 when nothing is loaded
+    pub fn from_tray_index(n: u64) -> Option<Self> {
+        match n {
+            0..=15 => Some(AmsCurrentSlot::Tray {
+                ams_id: n / 4,
+                tray_id: n % 4,
+            }),
+            254 => Some(AmsCurrentSlot::ExternalSpool),
+            255 => None,
+            _ => {
+                warn!("unknown tray index: {}", n);
+                None
+            }
+        }
+    }
+
+    pub fn to_tray_index(&self) -> u64 {
+        match self {
+            AmsCurrentSlot::ExternalSpool => 254,
+            AmsCurrentSlot::Tray { ams_id, tray_id } => ams_id * 4 + tray_id,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        match self {
+            AmsCurrentSlot::ExternalSpool => "External Spool".to_string(),
+            AmsCurrentSlot::Tray { ams_id, tray_id } => {
+                format!("AMS {} Tray {}", ams_id + 1, tray_id + 1)
+            }
+        }
+    }
+
     pub fn is_slot(&self, ams_id: u64, tray_id: u64) -> bool {
         match self {
             AmsCurrentSlot::Tray {
@@ -197,17 +377,126 @@ impl AmsCurrentSlot {
 #[derive(Debug, Default, Clone)]
 pub struct AmsUnit {
     pub id: i64,
-    pub humidity: i64,
+    pub humidity: Option<AmsHumidity>,
+    /// percent, only sent by newer firmware
+    pub humidity_percent: Option<i64>,
+    /// 0 if the unit has no sensor
     pub temp: f64,
     pub slots: [Option<AmsSlot>; 4],
 }
 
+impl AmsUnit {
+    /// e.g. `Humid (4), 45%, 28.5 °C`
+    pub fn humidity_text(&self) -> Option<String> {
+        let humidity = self.humidity?;
+        let mut out = format!("{} ({})", humidity.to_text(), humidity.level());
+        if let Some(p) = self.humidity_percent {
+            out.push_str(&format!(", {}%", p));
+        }
+        if self.temp > 0. {
+            out.push_str(&format!(", {:.1} °C", self.temp));
+        }
+        Some(out)
+    }
+}
+
+/// Humidity level from 1 (dry) to 5 (wet), as shown in Bambu Studio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AmsHumidity(u8);
+
+impl AmsHumidity {
+    pub const MAX: u8 = 5;
+
+    /// None for 0, sent before the AMS has measured it
+    pub fn from_level(s: &str) -> Option<Self> {
+        s.trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|l| (1..=Self::MAX).contains(l))
+            .map(Self)
+    }
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+
+    pub fn to_text(&self) -> &'static str {
+        match self.0 {
+            1 => "Dry",
+            2 => "Normal",
+            3 => "Slightly Humid",
+            4 => "Humid",
+            _ => "Wet",
+        }
+    }
+
+    pub fn color(&self) -> Color32 {
+        match self.0 {
+            1 | 2 => Color32::from_rgb(121, 173, 116),
+            3 => Color32::from_rgb(196, 178, 90),
+            4 => Color32::from_rgb(173, 125, 90),
+            _ => Color32::from_rgb(173, 90, 90),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct AmsSlot {
     pub material: String,
     pub k: f64,
     // pub color: [u8; 3],
     pub color: egui::Color32,
+    /// percent, None if the printer isn't tracking it
+    pub remain: Option<i64>,
+    /// grams of filament on a full spool
+    pub tray_weight: Option<f64>,
+    pub tray_sub_brands: Option<String>,
+    /// mm
+    pub tray_diameter: Option<f64>,
+    pub nozzle_temp_min: Option<i64>,
+    pub nozzle_temp_max: Option<i64>,
+    pub bed_temp: Option<i64>,
+    /// Bambu filament code, e.g. `A00-W1`
+    pub tray_id_name: Option<String>,
+    /// filament preset, e.g. `GFA00` for Bambu PLA Basic
+    pub tray_info_idx: Option<String>,
+    pub tag_uid: Option<String>,
+    /// all zeros for spools without RFID
+    pub tray_uuid: Option<String>,
+}
+
+impl AmsSlot {
+    /// the filament preset, or the material when there isn't one
+    pub fn filament_id(&self) -> String {
+        self.tray_info_idx
+            .clone()
+            .unwrap_or_else(|| self.material.clone())
+    }
+
+    /// e.g. `PLA Basic`, or just the material
+    pub fn filament_name(&self) -> String {
+        self.tray_sub_brands
+            .clone()
+            .unwrap_or_else(|| self.material.clone())
+    }
+
+    /// nozzle temperature to load or unload this filament at
+    pub fn swap_temp(&self) -> i64 {
+        match (self.nozzle_temp_min, self.nozzle_temp_max) {
+            (Some(min), Some(max)) => (min + max) / 2,
+            (Some(t), None) | (None, Some(t)) => t,
+            (None, None) => generic_filament(&self.material)
+                .map(|(_, _, min, max)| (min + max) / 2)
+                .unwrap_or(220),
+        }
+    }
+
+    /// None for third party spools
+    pub fn rfid_uuid(&self) -> Option<&str> {
+        self.tray_uuid
+            .as_deref()
+            .filter(|u| !u.is_empty() && u.chars().any(|c| c != '0'))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
@@ -353,3 +642,80 @@ impl PrintStage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tray_flags_from_bits() {
+        let ams = AmsStatus {
+            /// unit 0 full, tray 1 of unit 1
+            tray_exist_bits: Some(0x2f),
+            tray_is_bbl_bits: Some(0b0101),
+            tray_read_done_bits: Some(0x2f),
+            tray_reading_bits: Some(0b1000),
+            ..Default::default()
+        };
+
+        let flags = ams.tray_flags(0, 0);
+        assert_eq!(flags.present, Some(true));
+        assert!(flags.bambu_spool && flags.read_done && !flags.reading);
+        assert!(!flags.is_third_party());
+
+        assert!(ams.tray_flags(0, 1).is_third_party());
+        assert!(ams.tray_flags(0, 3).reading);
+        assert!(ams.tray_flags(1, 1).is_third_party());
+        assert!(ams.tray_flags(1, 0).is_empty());
+        assert!(ams.tray_flags(3, 3).is_empty());
+        /// past the end of the bitfield
+        assert!(ams.tray_flags(8, 0).is_empty());
+    }
+
+    #[test]
+    fn tray_flags_unknown_without_bits() {
+        let flags = AmsStatus::default().tray_flags(0, 0);
+        assert_eq!(flags, AmsTrayFlags::default());
+        assert!(!flags.is_empty());
+        assert!(!flags.is_third_party());
+    }
+
+    #[test]
+    fn unit_ids_from_bits_or_units() {
+        let mut ams = AmsStatus::default();
+        for id in [2, 0] {
+            ams.units.insert(
+                id,
+                AmsUnit {
+                    id,
+                    ..Default::default()
+                },
+            );
+        }
+        assert_eq!(ams.unit_ids(), vec![0, 2]);
+
+        ams.ams_exist_bits = Some(0b1011);
+        assert_eq!(ams.unit_ids(), vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn tray_index_round_trip() {
+        for n in (0..16).chain([254]) {
+            let slot = AmsCurrentSlot::from_tray_index(n).unwrap();
+            assert_eq!(slot.to_tray_index(), n);
+        }
+        assert_eq!(
+            AmsCurrentSlot::from_tray_index(6),
+            Some(AmsCurrentSlot::Tray {
+                ams_id: 1,
+                tray_id: 2
+            })
+        );
+        assert_eq!(
+            AmsCurrentSlot::from_tray_index(254),
+            Some(AmsCurrentSlot::ExternalSpool)
+        );
+        assert_eq!(AmsCurrentSlot::from_tray_index(255), None);
+        assert_eq!(AmsCurrentSlot::from_tray_index(16), None);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod alerts;
 pub mod dashboard;
 pub mod icons;
 pub mod options;
@@ -5,6 +6,8 @@ pub mod plotting;
 pub mod printer_widget;
 pub mod printers;
 pub mod project_view;
+pub mod spools;
+pub mod statistics;
 pub mod ui_types;
 pub mod ui_utils;
 
@@ -90,6 +93,9 @@ impl App {
         // alert_tx: tokio::sync::mpsc::Sender<(String, String)>,
         printer_textures: Arc<DashMap<PrinterId, WebcamTexture>>,
         graphs: plotting::Graphs,
+        alert_log: crate::alert::log::AlertLog,
+        job_db: crate::jobs::db::JobDb,
+        spools: crate::spools::SpoolInventory,
     ) -> Self {
         let mut out = if let Some(storage) = cc.storage {
             eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default()
@@ -114,6 +120,10 @@ impl App {
         out.stream_cmd_tx = Some(stream_cmd_tx);
 
         out.graphs = Some(graphs);
+        out.alert_log = alert_log;
+        out.job_db = Some(job_db);
+        out.spools = spools;
+        out.reload_local_jobs();
 
         out.unplaced_printers = out.config.printer_ids();
         /// for each printer that isn't in printer_order, queue to add
@@ -158,6 +168,12 @@ impl eframe::App for App {
         eframe::set_value(storage, eframe::APP_KEY, self);
     }
 
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Some(graphs) = self.graphs.as_ref() {
+            graphs.save();
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         self.read_channels();
 
@@ -172,10 +188,17 @@ impl eframe::App for App {
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.selectable_value(&mut self.current_tab, Tab::Dashboard, "Dashboard");
-                // ui.selectable_value(&mut self.current_tab, Tab::Graphs, "Graphs");
+                ui.selectable_value(&mut self.current_tab, Tab::Graphs, "Graphs");
                 // ui.selectable_value(&mut self.current_tab, Tab::Printers, "Printers");
-                // ui.selectable_value(&mut self.current_tab, Tab::Projects, "Projects");
+                ui.selectable_value(&mut self.current_tab, Tab::Projects, "Projects");
+                ui.selectable_value(&mut self.current_tab, Tab::Statistics, "Statistics");
+                ui.selectable_value(&mut self.current_tab, Tab::Spools, "Spools");
+                ui.selectable_value(&mut self.current_tab, Tab::Alerts, "Alerts");
                 ui.selectable_value(&mut self.current_tab, Tab::Options, "Options");
+
+                ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                    self.alert_badge(ui);
+                });
             });
         });
 
@@ -242,6 +265,21 @@ impl eframe::App for App {
             Tab::Printers => {
                 self.show_printers_config(ctx);
             }
+            Tab::Statistics => {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    self.show_statistics(ui);
+                });
+            }
+            Tab::Spools => {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    self.show_spools(ui);
+                });
+            }
+            Tab::Alerts => {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    self.show_alerts(ui);
+                });
+            }
             Tab::Options => {
                 egui::CentralPanel::default().show(ctx, |ui| {
                     self.show_options(ui);
@@ -275,6 +313,16 @@ impl App {
             PrinterConnMsg::SyncedProjects(projects) => {
                 self.projects = projects;
             }
+            PrinterConnMsg::SyncedCloudProjects(projects) => {
+                self.cloud_projects = projects;
+            }
+            PrinterConnMsg::JobHistoryChanged => {
+                self.reload_local_jobs();
+                self.fleet_stats = None;
+            }
+            PrinterConnMsg::SpoolmanSpools(spools) => {
+                self.spoolman_spools = spools;
+            }
             _ => {
                 warn!("unhandled message: {:?}", msg);
             }
@@ -0,0 +1,100 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use egui::{Color32, RichText};
+use egui_extras::Column;
+
+use crate::ui::ui_types::{App, Tab};
+
+impl App {
+    pub fn show_alerts(&mut self, ui: &mut egui::Ui) {
+        let num_entries = self.alert_log.num_entries();
+        let num_unacked = self.alert_log.num_unacknowledged();
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{} unacknowledged", num_unacked));
+            if ui
+                .add_enabled(num_unacked > 0, egui::Button::new("Acknowledge all"))
+                .clicked()
+            {
+                self.alert_log.acknowledge_all();
+            }
+        });
+
+        ui.separator();
+
+        egui_extras::TableBuilder::new(ui)
+            .column(Column::auto().at_least(150.))
+            .column(Column::auto().at_least(100.))
+            .column(Column::auto().at_least(100.))
+            .column(Column::remainder().at_least(200.))
+            .column(Column::auto().at_least(100.))
+            .resizable(true)
+            .striped(true)
+            .header(25., |mut h| {
+                for name in ["Time", "Printer", "Event", "Message", ""] {
+                    h.col(|ui| {
+                        ui.strong(name);
+                    });
+                }
+            })
+            .body(|mut body| {
+                body.rows(40., num_entries, |mut row| {
+                    let Some(entry) = self.alert_log.newest(row.index()) else {
+                        return;
+                    };
+                    let alert = &entry.alert;
+
+                    row.col(|ui| {
+                        ui.label(alert.time.format("%Y-%m-%d %I:%M:%S %p").to_string());
+                    });
+                    row.col(|ui| {
+                        ui.label(&alert.printer_name);
+                    });
+                    row.col(|ui| {
+                        let text = RichText::new(alert.event.to_text());
+                        if entry.is_acknowledged() {
+                            ui.label(text);
+                        } else {
+                            ui.label(text.color(Color32::RED));
+                        }
+                    });
+                    row.col(|ui| {
+                        ui.label(&alert.body).on_hover_text(&alert.title);
+                    });
+                    row.col(|ui| match entry.acknowledged {
+                        Some(t) if alert.event.needs_ack() => {
+                            ui.label(format!("Acknowledged {}", t.format("%I:%M %p")));
+                        }
+                        Some(_) => {}
+                        None => {
+                            if ui.button("Acknowledge").clicked() {
+                                self.alert_log.acknowledge(entry.id);
+                            }
+                        }
+                    });
+                });
+            });
+    }
+
+    /// red count of unacknowledged alerts, click to open the alerts tab
+    pub fn alert_badge(&mut self, ui: &mut egui::Ui) {
+        let n = self.alert_log.num_unacknowledged();
+        if n == 0 {
+            return;
+        }
+
+        let text = RichText::new(format!("{} {}", egui_phosphor::regular::WARNING, n))
+            .color(Color32::WHITE)
+            .strong();
+        let button = egui::Button::new(text).fill(Color32::from_rgb(200, 30, 30));
+
+        if ui
+            .add(button)
+            .on_hover_text(format!("{} unacknowledged alerts", n))
+            .clicked()
+        {
+            self.current_tab = Tab::Alerts;
+        }
+    }
+}
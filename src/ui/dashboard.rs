@@ -223,6 +223,10 @@ impl App {
                 PrinterState::Paused => Color32::from_rgb(173, 125, 90),
                 PrinterState::Printing => Color32::from_rgb(121, 173, 116),
                 PrinterState::Error(_) => Color32::from_rgb(173, 125, 90),
+                /// done, but the part is still on the plate
+                PrinterState::Finished if status.needs_plate_cleared() => {
+                    Color32::from_rgb(90, 140, 200)
+                }
                 _ => Color32::from_gray(127),
                 // _ => Color32::GREEN,
             }
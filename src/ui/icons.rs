@@ -182,12 +182,14 @@ pub fn printer_state_icon(ui: &mut egui::Ui, size: f32, state: &PrinterState) {
 
 /// Circle for each slot
 /// Line going down for active slot
+/// Returns the (ams_id, tray_id) under the pointer
 pub fn paint_ams(
     ui: &mut egui::Ui,
     size: f32,
     // size: f32,
     ams: &AmsStatus,
-) {
+    printer_type: Option<PrinterType>,
+) -> (egui::Response, Option<AmsCurrentSlot>) {
     #[cfg(feature = "nope")]
     ui.vertical(|ui| {
         ui.label(&format!("current tray: {:?}", ams.current_tray));
@@ -198,14 +200,22 @@ pub fn paint_ams(
         ui.label(&format!("state: {:?}", ams.state));
     });
 
-    let num_units = ams.units.len();
+    let unit_ids = ams.unit_ids();
+    let num_units = unit_ids.len();
+    let ams_lite = matches!(printer_type, Some(PrinterType::A1 | PrinterType::A1m));
 
     // debug!("size = {:#?}", size);
 
     let size = Vec2::new(ui.available_width(), size);
-    let (response, painter) = ui.allocate_painter(size, Sense::hover());
-
-    let rect = response.rect;
+    let (response, painter) = ui.allocate_painter(size, Sense::click());
+
+    /// the external spool gets a column on the right, or the whole width without an AMS
+    let ext_w = 32.;
+    let full_rect = response.rect;
+    let mut rect = full_rect;
+    if num_units > 0 {
+        rect.max.x -= ext_w;
+    }
     let c = rect.center();
     // let r = rect.width() / 2.0 - 1.0;
     // let r = size.x / 2.0 - 1.0;
@@ -231,19 +241,64 @@ pub fn paint_ams(
 
     let c = rect.center_top() + Vec2::new(0., y);
 
-    let small_circle_r = 12.;
     let small_spacing = 5.;
     let small_center_spacing = 4.;
+    /// 8 trays per row, shrunk to leave room for the external spool
+    let small_circle_r =
+        ((rect.width() - 2. * (small_center_spacing + 3. * small_spacing)) / 16.).clamp(6., 12.);
 
     let circle_stroke = 2.;
     let circle_stroke_color = Color32::from_gray(120);
     let y2_height = small_circle_r * 2. + circle_stroke * 2. + 2.;
 
+    let hover = response.hover_pos();
+    let mut hovered = None;
+
+    let stroke = egui::Stroke::new(circle_stroke, circle_stroke_color);
+
     if num_units == 0 {
-        error!("No units found in ams status");
-        return;
+        // only the external spool
+    } else if ams_lite && num_units == 1 {
+        /// AMS lite: four spools in an arc feeding a single hub
+        let unit_id = unit_ids[0];
+        let unit = ams.units.get(&unit_id);
+
+        let circle_r = 12.;
+        let spacing = rect.width() / 4.5;
+        let hub = Pos2::new(c.x, rect.min.y + 52.);
+
+        let trays = (0..4)
+            .map(|slot_idx| {
+                let offset = slot_idx as f32 - 1.5;
+                Pos2::new(c.x + offset * spacing, c.y + offset.abs().floor() * 6.)
+            })
+            .collect::<Vec<_>>();
+
+        for (slot_idx, tray) in trays.iter().enumerate() {
+            let active = ams
+                .current_tray
+                .is_some_and(|t| t.is_slot(unit_id as u64, slot_idx as u64));
+            let line = match unit.and_then(|u| u.slots[slot_idx].as_ref()) {
+                Some(slot) if active => egui::Stroke::new(2., slot.color),
+                _ => egui::Stroke::new(1., Color32::from_gray(180)),
+            };
+            painter.line_segment([*tray, hub], line);
+        }
+        painter.circle_filled(hub, 5., Color32::from_gray(140));
+
+        for (slot_idx, tray) in trays.into_iter().enumerate() {
+            if draw_tray(
+                &painter, ams, unit, unit_id, slot_idx, tray, circle_r, stroke, hover,
+            ) {
+                hovered = Some(AmsCurrentSlot::Tray {
+                    ams_id: unit_id as u64,
+                    tray_id: slot_idx as u64,
+                });
+            }
+        }
     } else if num_units == 1 {
-        let unit = &ams.units[&0];
+        let unit_id = unit_ids[0];
+        let unit = ams.units.get(&unit_id);
 
         let edge_padding = rect.width() / 8.0;
 
@@ -253,44 +308,25 @@ pub fn paint_ams(
         for slot_idx in 0..4 {
             let x = slot_idx as f32 * spacing + edge_padding;
             let c = p0 + Vec2::new(x, y);
-            // debug!("c: {:#?}", c);
-
-            match &unit.slots[slot_idx] {
-                Some(slot) => {
-                    painter.circle(
-                        c,
-                        circle_r,
-                        slot.color,
-                        egui::Stroke::new(2., circle_stroke_color),
-                    );
-
-                    if let Some(AmsCurrentSlot::Tray { ams_id, tray_id }) = ams.current_tray {
-                        if ams_id == 0 && slot_idx as u64 == tray_id {
-                            draw_ams_current(&painter, circle_r, circle_stroke, c, slot);
-                        }
-                    }
-                }
-                None => {
-                    painter.circle_stroke(
-                        c,
-                        circle_r,
-                        egui::Stroke::new(circle_stroke, circle_stroke_color),
-                    );
-                }
+
+            if draw_tray(
+                &painter, ams, unit, unit_id, slot_idx, c, circle_r, stroke, hover,
+            ) {
+                hovered = Some(AmsCurrentSlot::Tray {
+                    ams_id: unit_id as u64,
+                    tray_id: slot_idx as u64,
+                });
             }
-            // let color = unit.slots[slot].as_ref
-            // painter.circle_filled(c, r, Color32::RED);
         }
-    } else if num_units >= 2 {
+    } else {
+        /// two units per row, placed in order so gaps in the ids don't leave holes
         let y1 = c.y;
         let y2 = c.y + y2_height;
-        for unit in 0..4 {
-            if ams.units.get(&unit).is_none() {
-                continue;
-            }
-            let y = if unit < 2 { y1 } else { y2 };
+        for (pos, unit_id) in unit_ids.iter().copied().enumerate() {
+            let unit = ams.units.get(&unit_id);
+            let y = if pos < 2 { y1 } else { y2 };
 
-            let d = if unit % 2 == 0 { -1. } else { 1. };
+            let d = if pos % 2 == 0 { -1. } else { 1. };
             for slot_idx in 0..4 {
                 let x = c.x
                     + (small_center_spacing + small_circle_r) * d
@@ -298,32 +334,22 @@ pub fn paint_ams(
 
                 let c = Pos2::new(x, y);
 
-                match &ams.units[&unit].slots[slot_idx] {
-                    Some(slot) => {
-                        // painter.circle_filled(c, circle_r, slot.color);
-                        painter.circle(
-                            c,
-                            small_circle_r,
-                            slot.color,
-                            egui::Stroke::new(circle_stroke, circle_stroke_color),
-                        );
-
-                        if let Some(AmsCurrentSlot::Tray { ams_id, tray_id }) = ams.current_tray {
-                            if ams_id == unit as u64 && slot_idx as u64 == tray_id {
-                                draw_ams_current(&painter, small_circle_r, circle_stroke, c, slot);
-                            }
-                        }
-                    }
-                    None => {
-                        painter.circle_stroke(
-                            c,
-                            small_circle_r,
-                            egui::Stroke::new(circle_stroke, circle_stroke_color),
-                        );
-                    }
+                if draw_tray(
+                    &painter,
+                    ams,
+                    unit,
+                    unit_id,
+                    slot_idx,
+                    c,
+                    small_circle_r,
+                    stroke,
+                    hover,
+                ) {
+                    hovered = Some(AmsCurrentSlot::Tray {
+                        ams_id: unit_id as u64,
+                        tray_id: slot_idx as u64,
+                    });
                 }
-
-                //
             }
         }
 
@@ -332,28 +358,152 @@ pub fn paint_ams(
         painter.line_segment([c0, c1], egui::Stroke::new(1.0, Color32::from_gray(180)));
 
         if num_units > 2 {
-            // let top = y2 - small_circle_r;
-            // let c0 = rect.center_top() + Vec2::new(0., top);
-            // let c1 = rect.center_top() + Vec2::new(0., top + small_circle_r * 1. + 2.);
-
-            // let top = Pos2::new(c.x, c.y + y2_height);
-
-            // painter.circle_filled(top, 10., Color32::RED);
-
             let c0 = Pos2::new(c.x, c.y + y2_height - small_circle_r - 2.);
             let c1 = Pos2::new(c.x, c.y + y2_height + small_circle_r + 2.);
 
-            // let c0 = rect.center_top() + Vec2::new(0., top);
-            // let c1 = rect.center_top() + Vec2::new(0., top + small_circle_r * 1. + 2.);
-
             painter.line_segment([c0, c1], egui::Stroke::new(1.0, Color32::from_gray(180)));
-            // painter.line_segment([c0, c1], egui::Stroke::new(1.0, Color32::RED));
         }
+    }
+
+    let ext_c = if num_units == 0 {
+        full_rect.center_top() + Vec2::new(0., y)
     } else {
-        debug!("ams.units.len() = {:#?}", ams.units.len());
+        let x = full_rect.max.x - ext_w / 2.;
+        painter.line_segment(
+            [
+                Pos2::new(rect.max.x, full_rect.min.y + 4.),
+                Pos2::new(rect.max.x, full_rect.min.y + y * 2. - 4.),
+            ],
+            egui::Stroke::new(1.0, Color32::from_gray(180)),
+        );
+        Pos2::new(x, full_rect.min.y + y)
+    };
+    if hover.is_some_and(|p| p.distance(ext_c) <= small_circle_r) {
+        hovered = Some(AmsCurrentSlot::ExternalSpool);
     }
+    match ams.external.as_ref() {
+        Some(slot) => {
+            draw_ams_slot(&painter, ext_c, small_circle_r, stroke, slot);
+            if ams.active_slot() == Some(AmsCurrentSlot::ExternalSpool) {
+                draw_ams_current(&painter, small_circle_r, circle_stroke, ext_c, slot);
+            }
+        }
+        None => {
+            painter.circle_stroke(ext_c, small_circle_r, stroke);
+        }
+    }
+
+    /// the most humid unit under the external spool, every unit on hover
+    if let Some(worst) = ams.units.values().filter_map(|u| u.humidity).max() {
+        let pos = Pos2::new(
+            full_rect.max.x - ext_w / 2.,
+            full_rect.min.y + y + y2_height,
+        );
+        let text_rect = painter.text(
+            pos,
+            egui::Align2::CENTER_CENTER,
+            format!("{}{}", egui_phosphor::regular::DROP, worst.level()),
+            egui::FontId::proportional(13.),
+            worst.color(),
+        );
+        let mut units = ams.units.values().collect::<Vec<_>>();
+        units.sort_by_key(|u| u.id);
+        ui.interact(text_rect, response.id.with("humidity"), Sense::hover())
+            .on_hover_ui(|ui| {
+                ui.strong("Humidity");
+                for unit in units {
+                    if let Some(text) = unit.humidity_text() {
+                        ui.label(format!("AMS {}: {}", unit.id + 1, text));
+                    }
+                }
+            });
+    }
+
+    (response, hovered)
+}
+
+/// a single AMS tray, outlined when empty, with a mark on third party spools
+/// and a spinner while the RFID tag is read. Returns whether it is hovered
+#[allow(clippy::too_many_arguments)]
+fn draw_tray(
+    painter: &egui::Painter,
+    ams: &AmsStatus,
+    unit: Option<&AmsUnit>,
+    unit_id: i64,
+    slot_idx: usize,
+    c: Pos2,
+    r: f32,
+    stroke: egui::Stroke,
+    hover: Option<Pos2>,
+) -> bool {
+    let flags = ams.tray_flags(unit_id as u64, slot_idx as u64);
+    match unit
+        .and_then(|u| u.slots[slot_idx].as_ref())
+        .filter(|_| !flags.is_empty())
+    {
+        Some(slot) => {
+            draw_ams_slot(painter, c, r, stroke, slot);
+
+            if flags.is_third_party() {
+                painter.circle(
+                    c + Vec2::new(r, -r) * 0.7,
+                    (r * 0.3).max(3.),
+                    Color32::from_gray(60),
+                    egui::Stroke::new(1., Color32::WHITE),
+                );
+            }
+
+            if ams
+                .current_tray
+                .is_some_and(|t| t.is_slot(unit_id as u64, slot_idx as u64))
+            {
+                draw_ams_current(painter, r, stroke.width, c, slot);
+            }
+        }
+        None => {
+            painter.circle_stroke(c, r, stroke);
+        }
+    }
+    if flags.reading {
+        draw_rfid_spinner(painter, c, r);
+    }
+    hover.is_some_and(|p| p.distance(c) <= r)
+}
+
+/// half circle spinning around the tray
+fn draw_rfid_spinner(painter: &egui::Painter, c: Pos2, r: f32) {
+    let start = painter.ctx().input(|i| i.time) as f32 * std::f32::consts::TAU;
+    let points = (0..=16)
+        .map(|i| c + Vec2::angled(start + i as f32 / 16. * std::f32::consts::PI) * (r + 2.))
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.5, Color32::LIGHT_BLUE),
+    ));
+    painter.ctx().request_repaint();
+}
+
+/// filled up to the remaining percent when the printer reports it
+fn draw_ams_slot(
+    painter: &egui::Painter,
+    c: Pos2,
+    r: f32,
+    stroke: egui::Stroke,
+    slot: &crate::status::AmsSlot,
+) {
+    let Some(remain) = slot.remain else {
+        painter.circle(c, r, slot.color, stroke);
+        return;
+    };
+    painter.circle_filled(c, r, slot.color.gamma_multiply(0.25));
+
+    let mut clip = Rect::from_center_size(c, Vec2::splat(r * 2.));
+    clip.min.y = clip.max.y - r * 2. * remain.clamp(0, 100) as f32 / 100.;
+    painter
+        .with_clip_rect(clip.intersect(painter.clip_rect()))
+        .circle_filled(c, r, slot.color);
 
-    //
+    painter.circle_stroke(c, r, stroke);
 }
 
 fn draw_ams_current(
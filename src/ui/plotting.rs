@@ -5,23 +5,30 @@ use tracing::{debug, error, info, trace, warn};
 
 use dashmap::DashMap;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     sync::Arc,
     time::Instant,
 };
 
-use crate::{conn_manager::PrinterId, ui::ui_types::App};
+use crate::{
+    config::GraphConfig,
+    conn_manager::PrinterId,
+    export::{ExportFormat, ExportWindow},
+    ui::ui_types::App,
+};
 
 #[derive(Debug, Clone)]
 pub struct Graphs {
     pub printer_graphs: Arc<DashMap<PrinterId, PrinterGraphData>>,
+    retention: Arc<GraphConfig>,
 }
 
 /// new
 impl Graphs {
-    pub fn new() -> Self {
+    pub fn new(retention: Arc<GraphConfig>) -> Self {
         Self {
             printer_graphs: Arc::new(DashMap::new()),
+            retention,
         }
     }
 
@@ -51,28 +58,110 @@ impl Graphs {
 
         Self {
             printer_graphs: Arc::new(map),
+            retention: Arc::new(GraphConfig::default()),
         }
     }
 
-    #[cfg(feature = "nope")]
+    pub const FILENAME: &'static str = "graphs.bin";
+    const FILE_VERSION: u8 = 3;
+    /// seconds
+    pub const SAVE_INTERVAL: u64 = 5 * 60;
+
+    /// Written to a temp file first, so a crash while saving leaves the previous file intact
     pub fn save_to_file<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
-        let data = (*self.printer_graphs).clone();
-        let data = serde_json::to_string_pretty(&data)?;
-        std::fs::write(path, data)?;
+        let file: GraphFile<SeriesFile> = GraphFile {
+            version: Self::FILE_VERSION,
+            printers: self
+                .printer_graphs
+                .iter()
+                .map(|e| (e.key().to_string(), PrinterGraphFile::from(e.value())))
+                .collect(),
+        };
+        let data = borsh::to_vec(&file)?;
+
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, path)?;
         Ok(())
     }
 
-    #[cfg(feature = "nope")]
-    pub fn load_from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
-        let data = std::fs::read_to_string(path)?;
-        let data: HashMap<PrinterId, PrinterGraphData> = serde_json::from_str(&data)?;
-        let printer_graphs = Arc::new(DashMap::from(data));
-        Ok(Self { printer_graphs })
+    pub fn load_from_file<P: AsRef<std::path::Path>>(
+        path: P,
+        retention: Arc<GraphConfig>,
+    ) -> Result<Self> {
+        let data = std::fs::read(path)?;
+
+        /// version is the first byte in every format
+        let printers = match data.first() {
+            Some(1) => {
+                let file: GraphFileV1 = borsh::from_slice(&data)?;
+                file.printers
+                    .into_iter()
+                    .map(|(id, p)| (id, PrinterGraphFile::from(p).map(SeriesFile::from)))
+                    .collect()
+            }
+            Some(2) => {
+                let file: GraphFile<Vec<(i64, f64)>> = borsh::from_slice(&data)?;
+                file.printers
+                    .into_iter()
+                    .map(|(id, p)| (id, p.map(SeriesFile::from)))
+                    .collect()
+            }
+            Some(&Self::FILE_VERSION) => {
+                let file: GraphFile<SeriesFile> = borsh::from_slice(&data)?;
+                file.printers
+            }
+            v => bail!("unknown graph file version: {:?}", v),
+        };
+
+        let printer_graphs = DashMap::new();
+        for (id, data) in printers {
+            printer_graphs.insert(Arc::new(id), PrinterGraphData::from(data));
+        }
+        let out = Self {
+            printer_graphs: Arc::new(printer_graphs),
+            retention,
+        };
+        out.compact();
+        Ok(out)
+    }
+
+    /// a missing, corrupted or partial file starts with empty graphs
+    pub fn load_or_new(retention: Arc<GraphConfig>) -> Self {
+        match Self::load_from_file(Self::FILENAME, retention.clone()) {
+            Ok(graphs) => graphs,
+            Err(e) => {
+                if std::path::Path::new(Self::FILENAME).exists() {
+                    warn!("failed to load graphs, starting empty: {:?}", e);
+                }
+                Self::new(retention)
+            }
+        }
+    }
+
+    /// Also compacts printers that have stopped reporting
+    pub fn save(&self) {
+        /// saved on an interval and when jobs end, both write the same temp file
+        static SAVE_LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+        let _guard = SAVE_LOCK.lock();
+        self.compact();
+        if let Err(e) = self.save_to_file(Self::FILENAME) {
+            error!("failed to save graphs: {:?}", e);
+        }
+    }
+
+    fn compact(&self) {
+        let cutoffs = Cutoffs::new(&self.retention, chrono::Local::now());
+        for mut entry in self.printer_graphs.iter_mut() {
+            entry.compact(&cutoffs);
+        }
     }
 
     pub fn update_printer(&self, id: &PrinterId, data: &crate::mqtt::message::PrintData) {
         let t = chrono::Local::now();
         let mut entry = self.printer_graphs.entry(id.clone()).or_default();
+        let entry = &mut *entry;
 
         if let Some(temp) = data.nozzle_temper {
             entry.temp_nozzle.vals.push_back((t, temp));
@@ -89,11 +178,94 @@ impl Graphs {
         if let Some(temp) = data.chamber_temper {
             entry.temp_chamber.vals.push_back((t, temp));
         }
+        if let Some(temp) = data.chamber_target_temper {
+            entry.temp_chamber_tgt.vals.push_back((t, temp));
+        }
+
+        let fans = [
+            (&data.cooling_fan_speed, &mut entry.fan_part),
+            (&data.big_fan1_speed, &mut entry.fan_aux),
+            (&data.big_fan2_speed, &mut entry.fan_chamber),
+            (&data.heatbreak_fan_speed, &mut entry.fan_heatbreak),
+        ];
+        for (speed, series) in fans {
+            if let Some(speed) = speed.as_deref().and_then(fan_percent) {
+                series.vals.push_back((t, speed));
+            }
+        }
+
+        if let Some(p) = data.mc_percent {
+            entry.print_percent.vals.push_back((t, p as f64));
+        }
+        if let Some(layer) = data.layer_num {
+            let prev = entry.layer.vals.back().map(|(_, l)| *l as i64);
+            if prev.is_some() && prev != Some(layer) {
+                entry.markers.push_back((t, GraphMarker::Layer(layer)));
+            }
+            entry.layer.vals.push_back((t, layer as f64));
+        }
+        if let Some(lvl) = data.spd_lvl {
+            entry.speed_level.vals.push_back((t, lvl as f64));
+        }
+        if let Some(signal) = data
+            .wifi_signal
+            .as_ref()
+            .and_then(|s| s.trim_end_matches("dBm").parse::<f64>().ok())
+        {
+            entry.wifi_signal.vals.push_back((t, signal));
+        }
+
+        if let Some(units) = data.ams.as_ref().and_then(|a| a.ams.as_ref()) {
+            for unit in units.iter() {
+                let Ok(unit_id) = unit.id.parse::<u8>() else {
+                    continue;
+                };
+                if let Some(h) = crate::status::AmsHumidity::from_level(&unit.humidity) {
+                    entry
+                        .ams_humidity
+                        .entry(unit_id)
+                        .or_default()
+                        .vals
+                        .push_back((t, h.level() as f64));
+                }
+                /// 0 on units without a sensor
+                if let Some(temp) = unit.temp.parse::<f64>().ok().filter(|t| *t > 0.) {
+                    entry
+                        .ams_temp
+                        .entry(unit_id)
+                        .or_default()
+                        .vals
+                        .push_back((t, temp));
+                }
+            }
+        }
 
-        // self.printer_graphs.insert(id.clone(), data);
+        if let Some(state) = data.gcode_state.as_ref() {
+            if entry.last_marker(GraphMarker::is_state) != Some(state.as_str()) {
+                entry
+                    .markers
+                    .push_back((t, GraphMarker::State(state.clone())));
+            }
+        }
+        /// tray_now changes when filament is swapped, 255 is unloaded
+        if let Some(tray) = data.ams.as_ref().and_then(|a| a.tray_now.as_ref()) {
+            if entry.last_marker(GraphMarker::is_tray) != Some(tray.as_str()) {
+                entry
+                    .markers
+                    .push_back((t, GraphMarker::Tray(tray.clone())));
+            }
+        }
+
+        entry.compact(&Cutoffs::new(&self.retention, t));
     }
 }
 
+/// fan speeds are reported as a gear from 0 to 15
+fn fan_percent(speed: &str) -> Option<f64> {
+    let gear = speed.parse::<i64>().ok()?;
+    Some(((gear as f64 / 1.5).round() * 10.).min(100.))
+}
+
 #[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
 pub struct PrinterGraphData {
     temp_nozzle: GraphValues<f64>,
@@ -102,77 +274,1241 @@ pub struct PrinterGraphData {
     temp_bed_tgt: GraphValues<f64>,
     temp_chamber: GraphValues<f64>,
     temp_chamber_tgt: GraphValues<f64>,
+
+    /// percent
+    fan_part: GraphValues<f64>,
+    fan_aux: GraphValues<f64>,
+    fan_chamber: GraphValues<f64>,
+    fan_heatbreak: GraphValues<f64>,
+
+    print_percent: GraphValues<f64>,
+    layer: GraphValues<f64>,
+    speed_level: GraphValues<f64>,
+    /// dBm
+    wifi_signal: GraphValues<f64>,
+
+    /// per AMS unit id
+    ams_humidity: BTreeMap<u8, GraphValues<f64>>,
+    ams_temp: BTreeMap<u8, GraphValues<f64>>,
+
+    markers: VecDeque<(chrono::DateTime<chrono::Local>, GraphMarker)>,
 }
 
+/// Recent values at full resolution, older values in min/max/avg buckets
 #[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
 pub struct GraphValues<T> {
     vals: VecDeque<(chrono::DateTime<chrono::Local>, T)>,
+    /// one per entry in `BUCKET_WIDTHS`, oldest first
+    tiers: [VecDeque<GraphBucket>; 2],
 }
 
-impl App {
-    pub fn show_graphs(&mut self, ui: &mut egui::Ui) {
-        ui.label("TODO: Graphs");
+/// seconds, for each tier
+const BUCKET_WIDTHS: [i64; 2] = [60, 15 * 60];
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub struct GraphBucket {
+    start: chrono::DateTime<chrono::Local>,
+    min: f64,
+    max: f64,
+    avg: f64,
+    count: u32,
+}
+
+impl GraphBucket {
+    fn new(t: chrono::DateTime<chrono::Local>, v: f64) -> Self {
+        Self {
+            start: t,
+            min: v,
+            max: v,
+            avg: v,
+            count: 1,
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        let count = self.count + other.count;
+        self.avg = (self.avg * self.count as f64 + other.avg * other.count as f64) / count as f64;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count = count;
+    }
+}
+
+/// Where each tier ends, computed once per update
+struct Cutoffs {
+    full_resolution: chrono::DateTime<chrono::Local>,
+    tiers: [chrono::DateTime<chrono::Local>; 2],
+}
+
+impl Cutoffs {
+    fn new(config: &GraphConfig, now: chrono::DateTime<chrono::Local>) -> Self {
+        let full_resolution = now - chrono::Duration::minutes(config.full_resolution as i64);
+        let minutes = full_resolution - chrono::Duration::hours(config.minute_buckets as i64);
+        let retention = (now - chrono::Duration::days(config.retention as i64)).min(minutes);
+        Self {
+            full_resolution,
+            tiers: [minutes, retention],
+        }
+    }
+}
+
+/// A point to draw, or several merged together
+#[derive(Debug, Clone, Copy)]
+pub struct GraphSample {
+    /// unix seconds
+    pub t: f64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub count: u32,
+}
+
+impl GraphSample {
+    fn merge(&mut self, other: &Self) {
+        let count = self.count + other.count;
+        self.avg = (self.avg * self.count as f64 + other.avg * other.count as f64) / count as f64;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count = count;
+    }
+}
+
+/// An event drawn as a vertical line across all graphs
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    serde::Deserialize,
+    serde::Serialize,
+    borsh::BorshSerialize,
+    borsh::BorshDeserialize,
+)]
+pub enum GraphMarker {
+    Layer(i64),
+    /// gcode_state
+    State(String),
+    /// tray_now
+    Tray(String),
+}
+
+impl GraphMarker {
+    fn is_state(&self) -> Option<&str> {
+        match self {
+            Self::State(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn is_tray(&self) -> Option<&str> {
+        match self {
+            Self::Tray(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        match self {
+            Self::Layer(l) => format!("Layer {}", l),
+            Self::State(s) => s.clone(),
+            Self::Tray(t) if t == "255" => "Unloaded".to_string(),
+            Self::Tray(t) if t == "254" => "External Spool".to_string(),
+            Self::Tray(t) => match t.parse::<u64>() {
+                Ok(t) => format!("Tray {}-{}", t / 4 + 1, t % 4 + 1),
+                Err(_) => format!("Tray {}", t),
+            },
+        }
+    }
+
+    fn color(&self) -> egui::Color32 {
+        match self {
+            Self::Layer(_) => egui::Color32::from_gray(120).gamma_multiply(0.4),
+            Self::State(_) => egui::Color32::from_rgb(200, 170, 60),
+            Self::Tray(_) => egui::Color32::from_rgb(180, 100, 200),
+        }
+    }
+}
+
+/// on-disk format, timestamps as unix millis
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize)]
+struct GraphFile<S> {
+    version: u8,
+    printers: Vec<(String, PrinterGraphFile<S>)>,
+}
+
+/// `S` is `Vec<(i64, f64)>` in version 2, `SeriesFile` since version 3
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize)]
+struct PrinterGraphFile<S> {
+    temp_nozzle: S,
+    temp_nozzle_tgt: S,
+    temp_bed: S,
+    temp_bed_tgt: S,
+    temp_chamber: S,
+    temp_chamber_tgt: S,
+    fan_part: S,
+    fan_aux: S,
+    fan_chamber: S,
+    fan_heatbreak: S,
+    print_percent: S,
+    layer: S,
+    speed_level: S,
+    wifi_signal: S,
+    ams_humidity: Vec<(u8, S)>,
+    ams_temp: Vec<(u8, S)>,
+    markers: Vec<(i64, GraphMarker)>,
+}
+
+impl<S> PrinterGraphFile<S> {
+    fn map<S2>(self, f: impl Fn(S) -> S2) -> PrinterGraphFile<S2> {
+        PrinterGraphFile {
+            temp_nozzle: f(self.temp_nozzle),
+            temp_nozzle_tgt: f(self.temp_nozzle_tgt),
+            temp_bed: f(self.temp_bed),
+            temp_bed_tgt: f(self.temp_bed_tgt),
+            temp_chamber: f(self.temp_chamber),
+            temp_chamber_tgt: f(self.temp_chamber_tgt),
+            fan_part: f(self.fan_part),
+            fan_aux: f(self.fan_aux),
+            fan_chamber: f(self.fan_chamber),
+            fan_heatbreak: f(self.fan_heatbreak),
+            print_percent: f(self.print_percent),
+            layer: f(self.layer),
+            speed_level: f(self.speed_level),
+            wifi_signal: f(self.wifi_signal),
+            ams_humidity: self
+                .ams_humidity
+                .into_iter()
+                .map(|(id, v)| (id, f(v)))
+                .collect(),
+            ams_temp: self
+                .ams_temp
+                .into_iter()
+                .map(|(id, v)| (id, f(v)))
+                .collect(),
+            markers: self.markers,
+        }
+    }
+}
+
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize)]
+struct SeriesFile {
+    vals: Vec<(i64, f64)>,
+    tiers: Vec<Vec<BucketFile>>,
+}
+
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize)]
+struct BucketFile {
+    start: i64,
+    min: f64,
+    max: f64,
+    avg: f64,
+    count: u32,
+}
+
+impl From<Vec<(i64, f64)>> for SeriesFile {
+    fn from(vals: Vec<(i64, f64)>) -> Self {
+        Self {
+            vals,
+            tiers: vec![],
+        }
+    }
+}
+
+/// version 1, temperatures only
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize)]
+struct GraphFileV1 {
+    version: u8,
+    printers: Vec<(String, PrinterGraphFileV1)>,
+}
+
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize)]
+struct PrinterGraphFileV1 {
+    temp_nozzle: Vec<(i64, f64)>,
+    temp_nozzle_tgt: Vec<(i64, f64)>,
+    temp_bed: Vec<(i64, f64)>,
+    temp_bed_tgt: Vec<(i64, f64)>,
+    temp_chamber: Vec<(i64, f64)>,
+    temp_chamber_tgt: Vec<(i64, f64)>,
+}
+
+impl From<PrinterGraphFileV1> for PrinterGraphFile<Vec<(i64, f64)>> {
+    fn from(v1: PrinterGraphFileV1) -> Self {
+        Self {
+            temp_nozzle: v1.temp_nozzle,
+            temp_nozzle_tgt: v1.temp_nozzle_tgt,
+            temp_bed: v1.temp_bed,
+            temp_bed_tgt: v1.temp_bed_tgt,
+            temp_chamber: v1.temp_chamber,
+            temp_chamber_tgt: v1.temp_chamber_tgt,
+            fan_part: vec![],
+            fan_aux: vec![],
+            fan_chamber: vec![],
+            fan_heatbreak: vec![],
+            print_percent: vec![],
+            layer: vec![],
+            speed_level: vec![],
+            wifi_signal: vec![],
+            ams_humidity: vec![],
+            ams_temp: vec![],
+            markers: vec![],
+        }
+    }
+}
+
+fn time_from_millis(t: i64) -> Option<chrono::DateTime<chrono::Local>> {
+    let t = chrono::DateTime::from_timestamp_millis(t)?;
+    Some(t.with_timezone(&chrono::Local))
+}
+
+impl GraphValues<f64> {
+    fn to_file(&self) -> SeriesFile {
+        SeriesFile {
+            vals: self
+                .vals
+                .iter()
+                .map(|(t, v)| (t.timestamp_millis(), *v))
+                .collect(),
+            tiers: self
+                .tiers
+                .iter()
+                .map(|tier| {
+                    tier.iter()
+                        .map(|b| BucketFile {
+                            start: b.start.timestamp_millis(),
+                            min: b.min,
+                            max: b.max,
+                            avg: b.avg,
+                            count: b.count,
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    fn from_file(file: SeriesFile) -> Self {
+        let mut out = Self {
+            vals: file
+                .vals
+                .into_iter()
+                .filter_map(|(t, v)| Some((time_from_millis(t)?, v)))
+                .collect(),
+            tiers: Default::default(),
+        };
+        for (tier, buckets) in out.tiers.iter_mut().zip(file.tiers) {
+            *tier = buckets
+                .into_iter()
+                .filter_map(|b| {
+                    Some(GraphBucket {
+                        start: time_from_millis(b.start)?,
+                        min: b.min,
+                        max: b.max,
+                        avg: b.avg,
+                        count: b.count,
+                    })
+                })
+                .collect();
+        }
+        out
+    }
+}
+
+impl From<&PrinterGraphData> for PrinterGraphFile<SeriesFile> {
+    fn from(data: &PrinterGraphData) -> Self {
+        Self {
+            temp_nozzle: data.temp_nozzle.to_file(),
+            temp_nozzle_tgt: data.temp_nozzle_tgt.to_file(),
+            temp_bed: data.temp_bed.to_file(),
+            temp_bed_tgt: data.temp_bed_tgt.to_file(),
+            temp_chamber: data.temp_chamber.to_file(),
+            temp_chamber_tgt: data.temp_chamber_tgt.to_file(),
+            fan_part: data.fan_part.to_file(),
+            fan_aux: data.fan_aux.to_file(),
+            fan_chamber: data.fan_chamber.to_file(),
+            fan_heatbreak: data.fan_heatbreak.to_file(),
+            print_percent: data.print_percent.to_file(),
+            layer: data.layer.to_file(),
+            speed_level: data.speed_level.to_file(),
+            wifi_signal: data.wifi_signal.to_file(),
+            ams_humidity: data
+                .ams_humidity
+                .iter()
+                .map(|(id, v)| (*id, v.to_file()))
+                .collect(),
+            ams_temp: data
+                .ams_temp
+                .iter()
+                .map(|(id, v)| (*id, v.to_file()))
+                .collect(),
+            markers: data
+                .markers
+                .iter()
+                .map(|(t, m)| (t.timestamp_millis(), m.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl From<PrinterGraphFile<SeriesFile>> for PrinterGraphData {
+    fn from(file: PrinterGraphFile<SeriesFile>) -> Self {
+        Self {
+            temp_nozzle: GraphValues::from_file(file.temp_nozzle),
+            temp_nozzle_tgt: GraphValues::from_file(file.temp_nozzle_tgt),
+            temp_bed: GraphValues::from_file(file.temp_bed),
+            temp_bed_tgt: GraphValues::from_file(file.temp_bed_tgt),
+            temp_chamber: GraphValues::from_file(file.temp_chamber),
+            temp_chamber_tgt: GraphValues::from_file(file.temp_chamber_tgt),
+            fan_part: GraphValues::from_file(file.fan_part),
+            fan_aux: GraphValues::from_file(file.fan_aux),
+            fan_chamber: GraphValues::from_file(file.fan_chamber),
+            fan_heatbreak: GraphValues::from_file(file.fan_heatbreak),
+            print_percent: GraphValues::from_file(file.print_percent),
+            layer: GraphValues::from_file(file.layer),
+            speed_level: GraphValues::from_file(file.speed_level),
+            wifi_signal: GraphValues::from_file(file.wifi_signal),
+            ams_humidity: file
+                .ams_humidity
+                .into_iter()
+                .map(|(id, v)| (id, GraphValues::from_file(v)))
+                .collect(),
+            ams_temp: file
+                .ams_temp
+                .into_iter()
+                .map(|(id, v)| (id, GraphValues::from_file(v)))
+                .collect(),
+            markers: file
+                .markers
+                .into_iter()
+                .filter_map(|(t, m)| Some((time_from_millis(t)?, m)))
+                .collect(),
+        }
+    }
+}
+
+/// A line that can be drawn in the graphs tab
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum GraphSeries {
+    Nozzle,
+    NozzleTarget,
+    Bed,
+    BedTarget,
+    Chamber,
+    ChamberTarget,
+    PartFan,
+    AuxFan,
+    ChamberFan,
+    HeatbreakFan,
+    Progress,
+    Layer,
+    SpeedLevel,
+    WifiSignal,
+    /// AMS unit id
+    AmsHumidity(u8),
+    AmsTemp(u8),
+}
+
+impl GraphSeries {
+    /// every series except the per AMS unit ones
+    pub const ALL: [Self; 14] = [
+        Self::Nozzle,
+        Self::NozzleTarget,
+        Self::Bed,
+        Self::BedTarget,
+        Self::Chamber,
+        Self::ChamberTarget,
+        Self::PartFan,
+        Self::AuxFan,
+        Self::ChamberFan,
+        Self::HeatbreakFan,
+        Self::Progress,
+        Self::Layer,
+        Self::SpeedLevel,
+        Self::WifiSignal,
+    ];
+
+    pub const DEFAULT: [Self; 6] = [
+        Self::Nozzle,
+        Self::NozzleTarget,
+        Self::Bed,
+        Self::BedTarget,
+        Self::Chamber,
+        Self::ChamberTarget,
+    ];
+
+    pub fn to_text(&self) -> String {
+        match self {
+            Self::Nozzle => "Nozzle".to_string(),
+            Self::NozzleTarget => "Nozzle Target".to_string(),
+            Self::Bed => "Bed".to_string(),
+            Self::BedTarget => "Bed Target".to_string(),
+            Self::Chamber => "Chamber".to_string(),
+            Self::ChamberTarget => "Chamber Target".to_string(),
+            Self::PartFan => "Part Fan".to_string(),
+            Self::AuxFan => "Aux Fan".to_string(),
+            Self::ChamberFan => "Chamber Fan".to_string(),
+            Self::HeatbreakFan => "Heatbreak Fan".to_string(),
+            Self::Progress => "Progress".to_string(),
+            Self::Layer => "Layer".to_string(),
+            Self::SpeedLevel => "Speed Level".to_string(),
+            Self::WifiSignal => "Wifi Signal".to_string(),
+            Self::AmsHumidity(id) => format!("AMS {} Humidity", id + 1),
+            Self::AmsTemp(id) => format!("AMS {} Temp", id + 1),
+        }
+    }
+
+    pub fn group(&self) -> GraphGroup {
+        match self {
+            Self::Nozzle
+            | Self::NozzleTarget
+            | Self::Bed
+            | Self::BedTarget
+            | Self::Chamber
+            | Self::ChamberTarget
+            | Self::AmsTemp(_) => GraphGroup::Temperature,
+            Self::PartFan | Self::AuxFan | Self::ChamberFan | Self::HeatbreakFan => {
+                GraphGroup::Fans
+            }
+            Self::Progress => GraphGroup::Progress,
+            Self::Layer => GraphGroup::Layer,
+            Self::SpeedLevel => GraphGroup::Speed,
+            Self::WifiSignal => GraphGroup::Wifi,
+            Self::AmsHumidity(_) => GraphGroup::Humidity,
+        }
+    }
+
+    fn color(&self) -> egui::Color32 {
+        match self {
+            Self::Nozzle | Self::NozzleTarget => egui::Color32::from_rgb(230, 100, 80),
+            Self::Bed | Self::BedTarget => egui::Color32::from_rgb(80, 140, 230),
+            Self::Chamber | Self::ChamberTarget => egui::Color32::from_rgb(110, 190, 100),
+            Self::PartFan => egui::Color32::from_rgb(80, 190, 200),
+            Self::AuxFan => egui::Color32::from_rgb(200, 130, 220),
+            Self::ChamberFan => egui::Color32::from_rgb(110, 190, 100),
+            Self::HeatbreakFan => egui::Color32::from_rgb(230, 160, 60),
+            Self::Progress | Self::Layer | Self::SpeedLevel | Self::WifiSignal => {
+                egui::Color32::from_rgb(80, 140, 230)
+            }
+            Self::AmsHumidity(id) | Self::AmsTemp(id) => [
+                egui::Color32::from_rgb(220, 200, 80),
+                egui::Color32::from_rgb(80, 200, 160),
+                egui::Color32::from_rgb(200, 110, 160),
+                egui::Color32::from_rgb(150, 150, 230),
+            ][*id as usize % 4],
+        }
+    }
+
+    /// targets are drawn dashed over their temperature
+    fn is_target(&self) -> bool {
+        matches!(
+            self,
+            Self::NozzleTarget | Self::BedTarget | Self::ChamberTarget
+        )
+    }
+}
+
+/// Series sharing a unit are drawn in the same plot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GraphGroup {
+    Temperature,
+    Fans,
+    Progress,
+    Layer,
+    Speed,
+    Wifi,
+    Humidity,
+}
+
+impl GraphGroup {
+    pub const ALL: [Self; 7] = [
+        Self::Temperature,
+        Self::Fans,
+        Self::Progress,
+        Self::Layer,
+        Self::Speed,
+        Self::Wifi,
+        Self::Humidity,
+    ];
+
+    pub fn to_text(&self) -> &'static str {
+        match self {
+            Self::Temperature => "Temperature",
+            Self::Fans => "Fans",
+            Self::Progress => "Progress",
+            Self::Layer => "Layer",
+            Self::Speed => "Speed",
+            Self::Wifi => "Wifi",
+            Self::Humidity => "AMS Humidity",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Self::Temperature => "°C",
+            Self::Fans | Self::Progress => "%",
+            Self::Layer => "",
+            Self::Speed => "",
+            Self::Wifi => "dBm",
+            Self::Humidity => "",
+        }
+    }
+
+    fn axis_label(&self) -> &'static str {
+        match self {
+            Self::Temperature => "°C",
+            Self::Fans => "Fan %",
+            Self::Progress => "%",
+            Self::Layer => "Layer",
+            Self::Speed => "Level",
+            Self::Wifi => "dBm",
+            Self::Humidity => "Level",
+        }
+    }
+}
+
+impl PrinterGraphData {
+    pub fn series(&self, series: GraphSeries) -> Option<&GraphValues<f64>> {
+        Some(match series {
+            GraphSeries::Nozzle => &self.temp_nozzle,
+            GraphSeries::NozzleTarget => &self.temp_nozzle_tgt,
+            GraphSeries::Bed => &self.temp_bed,
+            GraphSeries::BedTarget => &self.temp_bed_tgt,
+            GraphSeries::Chamber => &self.temp_chamber,
+            GraphSeries::ChamberTarget => &self.temp_chamber_tgt,
+            GraphSeries::PartFan => &self.fan_part,
+            GraphSeries::AuxFan => &self.fan_aux,
+            GraphSeries::ChamberFan => &self.fan_chamber,
+            GraphSeries::HeatbreakFan => &self.fan_heatbreak,
+            GraphSeries::Progress => &self.print_percent,
+            GraphSeries::Layer => &self.layer,
+            GraphSeries::SpeedLevel => &self.speed_level,
+            GraphSeries::WifiSignal => &self.wifi_signal,
+            GraphSeries::AmsHumidity(id) => self.ams_humidity.get(&id)?,
+            GraphSeries::AmsTemp(id) => self.ams_temp.get(&id)?,
+        })
+    }
+
+    /// AMS units that have reported at least once
+    pub fn ams_units(&self) -> Vec<u8> {
+        self.ams_humidity
+            .keys()
+            .chain(self.ams_temp.keys())
+            .copied()
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// every fixed series, then both series for each AMS unit
+    pub fn available_series(&self) -> Vec<GraphSeries> {
+        let mut out = GraphSeries::ALL.to_vec();
+        for unit in self.ams_units() {
+            out.push(GraphSeries::AmsTemp(unit));
+            out.push(GraphSeries::AmsHumidity(unit));
+        }
+        out
+    }
+
+    pub fn markers_between(
+        &self,
+        start: Option<chrono::DateTime<chrono::Local>>,
+        end: Option<chrono::DateTime<chrono::Local>>,
+    ) -> impl Iterator<Item = &(chrono::DateTime<chrono::Local>, GraphMarker)> {
+        self.markers.iter().filter(move |(t, _)| {
+            start.map(|s| *t >= s).unwrap_or(true) && end.map(|e| *t <= e).unwrap_or(true)
+        })
+    }
+
+    fn last_marker(&self, f: fn(&GraphMarker) -> Option<&str>) -> Option<&str> {
+        self.markers.iter().rev().find_map(|(_, m)| f(m))
+    }
+
+    fn compact(&mut self, cutoffs: &Cutoffs) {
+        for series in [
+            &mut self.temp_nozzle,
+            &mut self.temp_nozzle_tgt,
+            &mut self.temp_bed,
+            &mut self.temp_bed_tgt,
+            &mut self.temp_chamber,
+            &mut self.temp_chamber_tgt,
+            &mut self.fan_part,
+            &mut self.fan_aux,
+            &mut self.fan_chamber,
+            &mut self.fan_heatbreak,
+            &mut self.print_percent,
+            &mut self.layer,
+            &mut self.speed_level,
+            &mut self.wifi_signal,
+        ] {
+            series.compact(cutoffs);
+        }
+        for series in self
+            .ams_humidity
+            .values_mut()
+            .chain(self.ams_temp.values_mut())
+        {
+            series.compact(cutoffs);
+        }
+
+        let retention = cutoffs.tiers[cutoffs.tiers.len() - 1];
+        while self
+            .markers
+            .front()
+            .map(|(t, _)| *t < retention)
+            .unwrap_or(false)
+        {
+            self.markers.pop_front();
+        }
+    }
+}
+
+impl GraphValues<f64> {
+    fn compact(&mut self, cutoffs: &Cutoffs) {
+        while let Some(&(t, v)) = self.vals.front() {
+            if t >= cutoffs.full_resolution {
+                break;
+            }
+            self.vals.pop_front();
+            Self::merge_into(&mut self.tiers[0], GraphBucket::new(t, v), BUCKET_WIDTHS[0]);
+        }
+
+        for i in 0..self.tiers.len() {
+            while let Some(bucket) = self.tiers[i].front().copied() {
+                if bucket.start >= cutoffs.tiers[i] {
+                    break;
+                }
+                self.tiers[i].pop_front();
+                /// the last tier just drops expired buckets
+                if let Some(next) = self.tiers.get_mut(i + 1) {
+                    Self::merge_into(next, bucket, BUCKET_WIDTHS[i + 1]);
+                }
+            }
+        }
+    }
+
+    fn merge_into(tier: &mut VecDeque<GraphBucket>, mut bucket: GraphBucket, width: i64) {
+        let width = width * 1000;
+        let start = bucket.start.timestamp_millis().div_euclid(width) * width;
+        match tier.back_mut() {
+            Some(last) if last.start.timestamp_millis() == start => last.merge(&bucket),
+            _ => {
+                bucket.start = time_from_millis(start).unwrap_or(bucket.start);
+                tier.push_back(bucket);
+            }
+        }
+    }
+
+    /// Every stored value and bucket in the window, oldest first
+    pub fn samples_between(
+        &self,
+        start: Option<chrono::DateTime<chrono::Local>>,
+        end: Option<chrono::DateTime<chrono::Local>>,
+    ) -> Vec<GraphSample> {
+        let start = start.map(|s| s.timestamp_millis() as f64 / 1000.);
+        let end = end.map(|e| e.timestamp_millis() as f64 / 1000.);
+        let in_range =
+            |t: f64| start.map(|s| t >= s).unwrap_or(true) && end.map(|e| t <= e).unwrap_or(true);
+
+        let mut samples = vec![];
+        for (tier, width) in self.tiers.iter().zip(BUCKET_WIDTHS).rev() {
+            for b in tier.iter() {
+                /// drawn at the middle of the bucket
+                let t = b.start.timestamp_millis() as f64 / 1000. + width as f64 / 2.;
+                if in_range(t) {
+                    samples.push(GraphSample {
+                        t,
+                        min: b.min,
+                        max: b.max,
+                        avg: b.avg,
+                        count: b.count,
+                    });
+                }
+            }
+        }
+        for (t, v) in self.vals.iter() {
+            let t = t.timestamp_millis() as f64 / 1000.;
+            if in_range(t) {
+                samples.push(GraphSample {
+                    t,
+                    min: *v,
+                    max: *v,
+                    avg: *v,
+                    count: 1,
+                });
+            }
+        }
 
-        const MINS_PER_DAY: f64 = 24.0 * 60.0;
-        const MINS_PER_H: f64 = 60.0;
+        samples
+    }
 
-        fn day(x: f64) -> f64 {
-            (x / MINS_PER_DAY).floor()
+    /// Oldest first, merged down to at most `max_points` so long ranges stay cheap to draw
+    pub fn samples_since(
+        &self,
+        start: Option<chrono::DateTime<chrono::Local>>,
+        max_points: usize,
+    ) -> Vec<GraphSample> {
+        let samples = self.samples_between(start, None);
+
+        let max_points = max_points.max(1);
+        let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+            return samples;
+        };
+        if samples.len() <= max_points {
+            return samples;
         }
 
-        fn hour(x: f64) -> f64 {
-            (x.rem_euclid(MINS_PER_DAY) / MINS_PER_H).floor()
+        let t0 = first.t;
+        let width = (last.t - t0) / max_points as f64;
+        let mut out: Vec<GraphSample> = Vec::with_capacity(max_points + 1);
+        let mut bin = None;
+        for s in samples {
+            let b = ((s.t - t0) / width) as usize;
+            match out.last_mut() {
+                Some(prev) if bin == Some(b) => prev.merge(&s),
+                _ => {
+                    out.push(s);
+                    bin = Some(b);
+                }
+            }
         }
+        out
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum GraphRange {
+    Minutes15,
+    #[default]
+    Hour1,
+    Hours6,
+    Day1,
+    All,
+}
+
+impl GraphRange {
+    pub const ALL: [Self; 5] = [
+        Self::Minutes15,
+        Self::Hour1,
+        Self::Hours6,
+        Self::Day1,
+        Self::All,
+    ];
 
-        fn minute(x: f64) -> f64 {
-            x.rem_euclid(MINS_PER_H).floor()
+    pub fn to_text(&self) -> &'static str {
+        match self {
+            Self::Minutes15 => "15 minutes",
+            Self::Hour1 => "1 hour",
+            Self::Hours6 => "6 hours",
+            Self::Day1 => "24 hours",
+            Self::All => "All",
         }
+    }
 
-        fn is_approx_integer(val: f64) -> bool {
-            val.fract().abs() < 1e-6
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        match self {
+            Self::Minutes15 => Some(chrono::Duration::minutes(15)),
+            Self::Hour1 => Some(chrono::Duration::hours(1)),
+            Self::Hours6 => Some(chrono::Duration::hours(6)),
+            Self::Day1 => Some(chrono::Duration::days(1)),
+            Self::All => None,
         }
+    }
+}
 
-        let time_formatter = |mark: GridMark, _digits, _range: &std::ops::RangeInclusive<f64>| {
-            let minutes = mark.value;
-            if minutes < 0.0 || 5.0 * MINS_PER_DAY <= minutes {
-                // No labels outside value bounds
-                String::new()
-            } else if is_approx_integer(minutes / MINS_PER_DAY) {
-                // Days
-                format!("Day {}", day(minutes))
+impl App {
+    pub fn show_graphs(&mut self, ui: &mut egui::Ui) {
+        let printer_ids = self.config.printer_ids();
+        let now = chrono::Local::now();
+
+        if self.options.graph_printer.is_none() {
+            self.options.graph_printer = printer_ids.first().cloned();
+        }
+        let Some(id) = self.options.graph_printer.clone() else {
+            ui.label("No printers");
+            return;
+        };
+
+        let names = printer_ids
+            .iter()
+            .map(|p| {
+                let name = self
+                    .config
+                    .get_printer(p)
+                    .map(|c| c.blocking_read().name.clone())
+                    .unwrap_or_else(|| p.to_string());
+                (p.clone(), name)
+            })
+            .collect::<Vec<_>>();
+        let selected_name = names
+            .iter()
+            .find(|(p, _)| p == &id)
+            .map(|(_, n)| n.clone())
+            .unwrap_or_default();
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("graph_printer")
+                .selected_text(selected_name)
+                .show_ui(ui, |ui| {
+                    for (p, name) in names.iter() {
+                        ui.selectable_value(&mut self.options.graph_printer, Some(p.clone()), name);
+                    }
+                });
+
+            egui::ComboBox::from_id_source("graph_range")
+                .selected_text(self.options.graph_range.to_text())
+                .show_ui(ui, |ui| {
+                    for r in GraphRange::ALL {
+                        ui.selectable_value(&mut self.options.graph_range, r, r.to_text());
+                    }
+                });
+
+            ui.separator();
+            self.graph_series_checkboxes(ui, &id);
+
+            ui.separator();
+            ui.menu_button("Markers", |ui| {
+                ui.checkbox(&mut self.options.graph_layer_markers, "Layer Changes");
+                ui.checkbox(
+                    &mut self.options.graph_state_markers,
+                    "State and Filament Changes",
+                );
+            });
+
+            ui.separator();
+            ui.menu_button("Export", |ui| {
+                for format in ExportFormat::ALL {
+                    if ui.button(format.to_text()).clicked() {
+                        let start = self.options.graph_range.duration().map(|d| now - d);
+                        let name = format!("{}_{}", id, now.format("%Y%m%d-%H%M%S"));
+                        let window = ExportWindow { start, end: None };
+                        self.export_telemetry(&id, window, &name, format);
+                        ui.close_menu();
+                    }
+                }
+            });
+            self.export_message_label(ui);
+        });
+
+        let selected = self.graph_series(&id).clone();
+
+        let Some(graphs) = self.graphs.clone() else {
+            return;
+        };
+
+        let groups = GraphGroup::ALL
+            .into_iter()
+            .filter(|g| selected.iter().any(|s| s.group() == *g))
+            .collect::<Vec<_>>();
+        if groups.is_empty() {
+            ui.label("No series selected");
+            return;
+        }
+
+        let start = self.options.graph_range.duration().map(|d| now - d);
+
+        /// about one point per pixel
+        let max_points = ui.available_width().max(100.) as usize;
+
+        /// copied out so the map isn't locked while drawing,
+        /// PrinterConnManager writes to it on every report
+        let (series, markers) = {
+            let Some(data) = graphs.printer_graphs.get(&id) else {
+                ui.label("No data yet");
+                return;
+            };
+
+            let series = data
+                .available_series()
+                .into_iter()
+                .filter(|s| selected.contains(s))
+                .filter_map(|s| Some((s, data.series(s)?.samples_since(start, max_points))))
+                .filter(|(_, samples)| !samples.is_empty())
+                .collect::<Vec<_>>();
+
+            let markers = data
+                .markers_between(start, None)
+                .filter(|(_, m)| match m {
+                    GraphMarker::Layer(_) => self.options.graph_layer_markers,
+                    GraphMarker::State(_) | GraphMarker::Tray(_) => {
+                        self.options.graph_state_markers
+                    }
+                })
+                .map(|(t, m)| (t.timestamp_millis() as f64 / 1000., m.clone()))
+                .collect::<Vec<_>>();
+
+            (series, markers)
+        };
+
+        /// every layer change over a long range is just a solid block
+        let num_layers = markers
+            .iter()
+            .filter(|(_, m)| matches!(m, GraphMarker::Layer(_)))
+            .count();
+        let markers = markers
+            .into_iter()
+            .filter(|(_, m)| !matches!(m, GraphMarker::Layer(_)) || num_layers <= max_points / 8)
+            .collect::<Vec<_>>();
+
+        let spacing = ui.spacing().item_spacing.y;
+        let height = ((ui.available_height() + spacing) / groups.len() as f32 - spacing).max(150.);
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for group in groups {
+                Self::show_graph_group(ui, group, &series, &markers, start, now, height);
+            }
+        });
+    }
+
+    fn show_graph_group(
+        ui: &mut egui::Ui,
+        group: GraphGroup,
+        series: &[(GraphSeries, Vec<GraphSample>)],
+        markers: &[(f64, GraphMarker)],
+        start: Option<chrono::DateTime<chrono::Local>>,
+        now: chrono::DateTime<chrono::Local>,
+        height: f32,
+    ) {
+        let time_formatter = |mark: GridMark, _digits, range: &std::ops::RangeInclusive<f64>| {
+            let Some(t) = chrono::DateTime::from_timestamp_millis((mark.value * 1000.) as i64)
+            else {
+                return String::new();
+            };
+            let t = t.with_timezone(&chrono::Local);
+            if range.end() - range.start() > 24. * 60. * 60. {
+                t.format("%m-%d %H:%M").to_string()
             } else {
-                // Hours and minutes
-                format!("{h}:{m:02}", h = hour(minutes), m = minute(minutes))
+                t.format("%H:%M").to_string()
             }
         };
 
-        let x_axes = vec![
-            AxisHints::new_x().label("Time").formatter(time_formatter),
-            AxisHints::new_x().label("Value"),
-        ];
+        let unit = group.unit();
+        let label_formatter = move |name: &str, value: &egui_plot::PlotPoint| {
+            let t = chrono::DateTime::from_timestamp_millis((value.x * 1000.) as i64)
+                .map(|t| {
+                    t.with_timezone(&chrono::Local)
+                        .format("%H:%M:%S")
+                        .to_string()
+                })
+                .unwrap_or_default();
+            if name.is_empty() {
+                format!("{}\n{:.1}{}", t, value.y, unit)
+            } else {
+                format!("{}\n{}\n{:.1}{}", name, t, value.y, unit)
+            }
+        };
 
-        let plot = Plot::new("Printer Plot")
-            .auto_bounds(Vec2b::new(false, false))
-            .view_aspect(16. / 9.)
-            .width(600.)
+        let mut plot = Plot::new(("Printer Plot", group))
+            .height(height)
             .legend(Legend::default())
-            .custom_x_axes(x_axes)
-            // .legend(Legend::default())
-            ;
-
-        let inner = plot.show(ui, |plot_ui| {
-            for id in self.config.printer_ids() {
-                let data = self
-                    .graphs
-                    .as_ref()
-                    .unwrap()
-                    .printer_graphs
-                    .get(&id)
-                    .unwrap();
-
-                // let graph = data.temp_nozzle.vals.iter().map(|(t, v)| [*t, *v]);
-                // plot_ui.line(Line::new(PlotPoints::from(graph)).name("curve"));
+            .custom_x_axes(vec![AxisHints::new_x()
+                .label("Time")
+                .formatter(time_formatter)])
+            .y_axis_label(group.axis_label())
+            .label_formatter(label_formatter)
+            .link_axis("printer_plots", true, false)
+            .link_cursor("printer_plots", true, false)
+            .include_x(now.timestamp() as f64);
+        if group != GraphGroup::Wifi {
+            plot = plot.include_y(0.);
+        }
+        if let Some(start) = start {
+            plot = plot.include_x(start.timestamp() as f64);
+        }
+
+        plot.show(ui, |plot_ui| {
+            let mut y_max: Option<f64> = None;
+            for (series, samples) in series.iter() {
+                if series.group() != group {
+                    continue;
+                }
+                for s in samples.iter() {
+                    y_max = Some(y_max.map_or(s.max, |y| y.max(s.max)));
+                }
+
+                let points = samples.iter().map(|s| [s.t, s.avg]).collect::<Vec<_>>();
+                let mut line = egui_plot::Line::new(egui_plot::PlotPoints::from(points))
+                    .name(series.to_text())
+                    .color(series.color());
+                if series.is_target() {
+                    line = line.style(egui_plot::LineStyle::dashed_loose());
+                }
+                plot_ui.line(line);
+
+                /// faint min/max envelope where values were merged,
+                /// same name so the legend toggles them together
+                if !series.is_target() && samples.iter().any(|s| s.min != s.max) {
+                    for f in [|s: &GraphSample| s.min, |s: &GraphSample| s.max] {
+                        let points = samples.iter().map(|s| [s.t, f(s)]).collect::<Vec<_>>();
+                        plot_ui.line(
+                            egui_plot::Line::new(egui_plot::PlotPoints::from(points))
+                                .name(series.to_text())
+                                .color(series.color().gamma_multiply(0.3)),
+                        );
+                    }
+                }
+            }
+
+            for (x, marker) in markers.iter() {
+                let name = match marker {
+                    GraphMarker::Layer(_) => "Layer Change",
+                    GraphMarker::State(_) => "State Change",
+                    GraphMarker::Tray(_) => "Filament Change",
+                };
+                plot_ui.vline(egui_plot::VLine::new(*x).name(name).color(marker.color()));
+                /// layers are too dense to label
+                if let (GraphMarker::State(_) | GraphMarker::Tray(_), Some(y)) = (marker, y_max) {
+                    plot_ui.text(
+                        egui_plot::Text::new(egui_plot::PlotPoint::new(*x, y), marker.to_text())
+                            .anchor(egui::Align2::LEFT_TOP)
+                            .color(marker.color()),
+                    );
+                }
             }
         });
     }
+
+    /// writes every series for the printer within the window to `EXPORT_DIR`
+    pub fn export_telemetry(
+        &mut self,
+        id: &PrinterId,
+        window: ExportWindow,
+        name: &str,
+        format: ExportFormat,
+    ) {
+        let Some(graphs) = self.graphs.as_ref() else {
+            return;
+        };
+        let result = crate::export::export_telemetry(graphs, id, window, format)
+            .and_then(|contents| crate::export::write_export(name, format, &contents));
+        self.export_message = Some(match result {
+            Ok(path) => format!("Exported to {}", path.display()),
+            Err(e) => {
+                error!("failed to export telemetry: {:?}", e);
+                format!("Export failed: {}", e)
+            }
+        });
+    }
+
+    pub fn export_message_label(&mut self, ui: &mut egui::Ui) {
+        let Some(msg) = self.export_message.as_ref() else {
+            return;
+        };
+        ui.label(msg);
+        if ui.small_button("x").clicked() {
+            self.export_message = None;
+        }
+    }
+
+    /// series shown for a printer, temperatures by default
+    pub fn graph_series(&mut self, id: &PrinterId) -> &mut Vec<GraphSeries> {
+        self.options
+            .graph_series
+            .entry(id.clone())
+            .or_insert_with(|| GraphSeries::DEFAULT.to_vec())
+    }
+
+    /// one menu per group, AMS series only once the printer has reported an AMS
+    pub fn graph_series_checkboxes(&mut self, ui: &mut egui::Ui, id: &PrinterId) {
+        let available = self
+            .graphs
+            .as_ref()
+            .and_then(|g| g.printer_graphs.get(id).map(|d| d.available_series()))
+            .unwrap_or_else(|| GraphSeries::ALL.to_vec());
+
+        let selected = self.graph_series(id);
+        for group in GraphGroup::ALL {
+            let series = available
+                .iter()
+                .filter(|s| s.group() == group)
+                .copied()
+                .collect::<Vec<_>>();
+            if series.is_empty() {
+                continue;
+            }
+            ui.menu_button(group.to_text(), |ui| {
+                for series in series {
+                    let mut checked = selected.contains(&series);
+                    if ui.checkbox(&mut checked, series.to_text()).changed() {
+                        if checked {
+                            selected.push(series);
+                        } else {
+                            selected.retain(|s| *s != series);
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// on a 15 minute boundary so buckets line up with the samples
+    fn now() -> chrono::DateTime<chrono::Local> {
+        time_from_millis(1_700_000_100_000).unwrap()
+    }
+
+    fn series(vals: &[(i64, f64)]) -> GraphValues<f64> {
+        let now = now();
+        GraphValues {
+            vals: vals
+                .iter()
+                .map(|(secs, v)| (now - chrono::Duration::seconds(*secs), *v))
+                .collect(),
+            tiers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn old_values_merge_into_minute_buckets() {
+        let mut s = series(&[
+            (40 * 60, 1.),
+            (40 * 60 - 10, 5.),
+            (40 * 60 - 20, 3.),
+            (39 * 60, 7.),
+            (5 * 60, 9.),
+        ]);
+        s.compact(&Cutoffs::new(&GraphConfig::default(), now()));
+
+        assert_eq!(s.vals.len(), 1);
+        assert_eq!(s.tiers[0].len(), 2);
+        let b = s.tiers[0][0];
+        assert_eq!(b.start, now() - chrono::Duration::minutes(40));
+        assert_eq!((b.min, b.max, b.avg, b.count), (1., 5., 3., 3));
+        assert_eq!(s.tiers[0][1].count, 1);
+        assert!(s.tiers[1].is_empty());
+    }
+
+    #[test]
+    fn minute_buckets_age_into_tiers_and_expire() {
+        let config = GraphConfig::default();
+        let mut s = series(&[
+            (8 * 24 * 3600, 100.),
+            (25 * 3600, 2.),
+            (25 * 3600 - 60, 4.),
+            (60, 0.),
+        ]);
+        s.compact(&Cutoffs::new(&config, now()));
+
+        assert_eq!(s.vals.len(), 1);
+        assert!(s.tiers[0].is_empty());
+        assert_eq!(s.tiers[1].len(), 1);
+        let b = s.tiers[1][0];
+        assert_eq!(b.start, now() - chrono::Duration::hours(25));
+        assert_eq!((b.min, b.max, b.avg, b.count), (2., 4., 3., 2));
+    }
+
+    #[test]
+    fn samples_are_merged_down_to_max_points() {
+        let vals: Vec<(i64, f64)> = (0..100).map(|i| (100 - i, i as f64)).collect();
+        let s = series(&vals);
+
+        let samples = s.samples_since(None, 10);
+        assert!(samples.len() <= 11);
+        assert_eq!(samples.iter().map(|s| s.count).sum::<u32>(), 100);
+        assert_eq!(samples[0].min, 0.);
+        assert_eq!(samples[samples.len() - 1].max, 99.);
+
+        assert_eq!(s.samples_since(None, 1000).len(), 100);
+    }
 }
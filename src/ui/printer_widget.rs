@@ -6,10 +6,14 @@ use egui::{Color32, Label, Layout, Response, RichText, Rounding, Sense, Vec2};
 use crate::{
     config::{ConfigArc, PrinterConfig},
     conn_manager::{PrinterConnCmd, PrinterId},
-    status::{bambu::PrinterStatus, PrinterState},
+    mqtt::command::{generic_filament, ChangeAMSFilamentSetting, GENERIC_FILAMENTS},
+    spools::TrayLocation,
+    status::{
+        bambu::PrinterStatus, AmsCurrentSlot, AmsSlot, AmsStatus, AmsTrayFlags, PrinterState,
+    },
     ui::{
         icons::*,
-        ui_types::{App, GridLocation, Tab},
+        ui_types::{App, FilamentSwapStep, GridLocation, Tab},
     },
 };
 
@@ -313,6 +317,25 @@ impl App {
                     // ui.ctx()
                     //     .debug_painter()
                     //     .debug_rect(ui.max_rect(), Color32::RED, "");
+                    if status.needs_plate_cleared() {
+                        let button = egui::Button::new(
+                            RichText::new("Clear Plate").strong().color(Color32::WHITE),
+                        )
+                        .fill(Color32::from_rgb(90, 140, 200));
+                        if ui
+                            .add(button)
+                            .on_hover_text("Part has been removed")
+                            .clicked()
+                        {
+                            if let Some(tx) = self.cmd_tx.as_ref() {
+                                let _ = tx.send(crate::conn_manager::PrinterConnCmd::ClearBed(
+                                    printer.serial.clone(),
+                                ));
+                            }
+                        }
+                        return;
+                    }
+
                     let p = status.print_percent.unwrap_or(0);
                     ui.add(
                         egui::ProgressBar::new(p as f32 / 100.0)
@@ -660,13 +683,237 @@ impl App {
             return;
         };
 
+        /// a running load or unload replaces the trays until it finishes
+        if let Some(step) = ams.swap_step() {
+            show_swap_progress(ui, ams, step);
+            return;
+        }
+
         let size = 62.;
 
-        crate::ui::icons::paint_ams(ui, size, ams);
+        let (resp, hovered) = crate::ui::icons::paint_ams(ui, size, ams, status.printer_type);
+
+        /// right click to set the filament
+        let edit_id = egui::Id::new(("filament_edit", &printer.serial));
+        if let Some(hovered) = hovered.filter(|_| resp.secondary_clicked()) {
+            let edit = FilamentEdit::new(hovered, ams.get_slot(hovered));
+            ui.data_mut(|d| d.insert_temp(edit_id, edit));
+        }
+        let menu = resp.context_menu(|ui| {
+            let Some(mut edit) = ui.data(|d| d.get_temp::<FilamentEdit>(edit_id)) else {
+                ui.close_menu();
+                return;
+            };
+
+            let can_swap = !matches!(
+                status.state,
+                PrinterState::Printing | PrinterState::Disconnected
+            );
+            ui.horizontal(|ui| {
+                let loaded = ams.current_tray == Some(edit.slot);
+                let load = ui
+                    .add_enabled(
+                        can_swap && !loaded && ams.get_slot(edit.slot).is_some(),
+                        egui::Button::new("Load"),
+                    )
+                    .on_hover_text("Load the filament in this tray");
+                if load.clicked() {
+                    if let Some(tx) = self.cmd_tx.as_ref() {
+                        let _ = tx.send(PrinterConnCmd::ChangeFilament(
+                            printer.serial.clone(),
+                            edit.slot,
+                        ));
+                    }
+                    ui.close_menu();
+                }
+                let unload = ui
+                    .add_enabled(
+                        can_swap && ams.current_tray.is_some(),
+                        egui::Button::new("Unload"),
+                    )
+                    .on_hover_text("Unload the current filament");
+                if unload.clicked() {
+                    if let Some(tx) = self.cmd_tx.as_ref() {
+                        let _ = tx.send(PrinterConnCmd::UnloadFilament(printer.serial.clone()));
+                    }
+                    ui.close_menu();
+                }
+            });
+            ui.separator();
+
+            if let Some(setting) = edit.show(ui) {
+                if let Some(tx) = self.cmd_tx.as_ref() {
+                    let _ = tx.send(PrinterConnCmd::ChangeAMSFilamentSetting(
+                        printer.serial.clone(),
+                        setting,
+                    ));
+                }
+                ui.close_menu();
+            }
+
+            if let Some(slot) = ams.get_slot(edit.slot) {
+                ui.separator();
+                self.k_value_menu(ui, printer, &status, slot, &mut edit);
+            }
+            ui.data_mut(|d| d.insert_temp(edit_id, edit));
+        });
+
+        if let Some(hovered) = hovered.filter(|_| menu.is_none()) {
+            let slot = ams.get_slot(hovered);
+            let location = match hovered {
+                AmsCurrentSlot::ExternalSpool => TrayLocation::external(printer.serial.clone()),
+                AmsCurrentSlot::Tray { ams_id, tray_id } => {
+                    TrayLocation::tray(printer.serial.clone(), ams_id, tray_id)
+                }
+            };
+            egui::show_tooltip_at_pointer(
+                ui.ctx(),
+                egui::Id::new(("ams_tooltip", &printer.serial)),
+                |ui| {
+                    ui.strong(location.to_text());
+                    let flags = match hovered {
+                        AmsCurrentSlot::ExternalSpool => AmsTrayFlags::default(),
+                        AmsCurrentSlot::Tray { ams_id, tray_id } => ams.tray_flags(ams_id, tray_id),
+                    };
+                    match slot {
+                        Some(slot) => ams_slot_details(ui, slot),
+                        None => {
+                            ui.label("Empty");
+                        }
+                    }
+                    if flags.reading {
+                        ui.label("Reading RFID tag...");
+                    } else if flags.is_third_party() {
+                        ui.label("Third party spool");
+                    }
+                    if let AmsCurrentSlot::Tray { ams_id, .. } = hovered {
+                        if let Some(text) = ams
+                            .units
+                            .get(&(ams_id as i64))
+                            .and_then(|u| u.humidity_text())
+                        {
+                            ui.label(format!("Humidity: {}", text));
+                        }
+                    }
+                    if let Some(spool) = self.spools.spool_at(&location) {
+                        ui.label(format!(
+                            "Spool: {}, {:.0} g left",
+                            spool.name(),
+                            spool.remaining_weight
+                        ));
+                    }
+                    if let Some(id) = self.spools.spoolman_at(&location) {
+                        match self.spoolman_spools.iter().find(|s| s.id == id) {
+                            Some(s) => ui.label(format!(
+                                "Spoolman: {}, {:.0} g left",
+                                s.name(),
+                                s.remaining_weight.unwrap_or(0.)
+                            )),
+                            None => ui.label(format!("Spoolman: #{}", id)),
+                        };
+                    }
+                    ui.weak("Right click to load or set the filament");
+                },
+            );
+        }
 
         //
     }
 
+    /// Pressure advance for a tray: set it by hand, use a calibration saved on the printer,
+    /// or re-apply a value used before with the same filament and nozzle
+    fn k_value_menu(
+        &self,
+        ui: &mut egui::Ui,
+        printer: &PrinterConfig,
+        status: &PrinterStatus,
+        slot: &AmsSlot,
+        edit: &mut FilamentEdit,
+    ) {
+        let send = |cmd: PrinterConnCmd| {
+            if let Some(tx) = self.cmd_tx.as_ref() {
+                let _ = tx.send(cmd);
+            }
+        };
+        let nozzle = status
+            .nozzle_diameter
+            .clone()
+            .unwrap_or_else(|| "0.4".to_string());
+        let filament_id = slot.filament_id();
+
+        egui::CollapsingHeader::new(format!("Pressure advance (K {:.3})", slot.k))
+            .id_source("k_value_menu")
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut edit.k)
+                            .speed(0.001)
+                            .clamp_range(0.0..=1.0)
+                            .fixed_decimals(3),
+                    );
+                    if ui.button("Set K").clicked() {
+                        send(PrinterConnCmd::SetK(
+                            printer.serial.clone(),
+                            edit.slot,
+                            edit.k,
+                        ));
+                        ui.close_menu();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.strong(format!("Saved on printer, {} mm", nozzle));
+                    if ui
+                        .small_button(egui_phosphor::regular::ARROWS_CLOCKWISE)
+                        .on_hover_text("Refresh")
+                        .clicked()
+                    {
+                        send(PrinterConnCmd::GetKProfiles(printer.serial.clone()));
+                    }
+                });
+                let profiles = status
+                    .k_profiles
+                    .iter()
+                    .filter(|p| p.filament_id == filament_id && p.nozzle_diameter == nozzle);
+                for p in profiles {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{}: {:.3}", p.name, p.k));
+                        if ui.small_button("Use").clicked() {
+                            send(PrinterConnCmd::SelectKProfile(
+                                printer.serial.clone(),
+                                edit.slot,
+                                p.cali_idx,
+                            ));
+                            ui.close_menu();
+                        }
+                    });
+                }
+                if ui.small_button("Use default").clicked() {
+                    send(PrinterConnCmd::SelectKProfile(
+                        printer.serial.clone(),
+                        edit.slot,
+                        -1,
+                    ));
+                    ui.close_menu();
+                }
+
+                let history = self.spools.k_history(&filament_id, &nozzle);
+                if !history.is_empty() {
+                    ui.strong(format!("Used before with {}", slot.filament_name()));
+                }
+                for r in history.iter().take(5) {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{:.3}", r.k));
+                        ui.weak(r.time.format("%Y-%m-%d").to_string());
+                        if ui.small_button("Apply").clicked() {
+                            send(PrinterConnCmd::SetK(printer.serial.clone(), edit.slot, r.k));
+                            ui.close_menu();
+                        }
+                    });
+                }
+            });
+    }
+
     /// MARK: show_current_print
     #[cfg(feature = "nope")]
     fn show_current_print(
@@ -757,3 +1004,220 @@ impl App {
         //
     }
 }
+
+/// what the AMS read from the spool, fields it didn't report are left out
+fn ams_slot_details(ui: &mut egui::Ui, slot: &AmsSlot) {
+    egui::Grid::new("ams_slot_details")
+        .num_columns(2)
+        .spacing([12., 2.])
+        .show(ui, |ui| {
+            let mut row = |name: &str, value: String| {
+                ui.label(name);
+                ui.label(value);
+                ui.end_row();
+            };
+
+            match slot.tray_sub_brands.as_ref() {
+                Some(brand) => row("Filament", format!("{} ({})", brand, slot.material)),
+                None => row("Filament", slot.material.clone()),
+            }
+            if let Some(remain) = slot.remain {
+                row("Remaining", format!("{}%", remain));
+            }
+            if let Some(w) = slot.tray_weight {
+                row("Spool", format!("{:.0} g", w));
+            }
+            if let Some(d) = slot.tray_diameter {
+                row("Diameter", format!("{:.2} mm", d));
+            }
+            match (slot.nozzle_temp_min, slot.nozzle_temp_max) {
+                (Some(min), Some(max)) => row("Nozzle", format!("{}-{} °C", min, max)),
+                (Some(t), None) | (None, Some(t)) => row("Nozzle", format!("{} °C", t)),
+                (None, None) => {}
+            }
+            if let Some(t) = slot.bed_temp {
+                row("Bed", format!("{} °C", t));
+            }
+            row("K", format!("{:.3}", slot.k));
+            if let Some(id) = slot.tray_id_name.as_ref() {
+                row("Filament ID", id.clone());
+            }
+            if let Some(uid) = slot.tag_uid.as_ref() {
+                row("RFID tag", uid.clone());
+            }
+        });
+}
+
+/// steps of the running load or unload, and a warning if it's stuck on one
+fn show_swap_progress(ui: &mut egui::Ui, ams: &AmsStatus, step: FilamentSwapStep) {
+    let unloading = ams.is_ams_unload();
+    let steps: &[FilamentSwapStep] = if unloading {
+        &FilamentSwapStep::UNLOAD
+    } else {
+        &FilamentSwapStep::LOAD
+    };
+    /// Idling before the first step
+    let done = steps.iter().position(|s| *s == step).map_or(0, |i| i + 1);
+    let elapsed = ams.state_since.map(|t| t.elapsed()).unwrap_or_default();
+
+    let resp = ui
+        .vertical(|ui| {
+            let title = match ams.swap_target() {
+                Some(target) if !unloading => format!("Loading {}", target.to_text()),
+                _ => "Unloading filament".to_string(),
+            };
+            ui.strong(title);
+            ui.add(
+                egui::ProgressBar::new(done as f32 / steps.len() as f32)
+                    .desired_width(ui.available_width())
+                    .text(format!("{}/{} {}", done, steps.len(), step.to_text())),
+            );
+            if ams.swap_stalled() {
+                ui.colored_label(
+                    Color32::RED,
+                    format!(
+                        "{} Stalled, no progress for {} min",
+                        egui_phosphor::regular::WARNING,
+                        elapsed.as_secs() / 60
+                    ),
+                );
+            } else {
+                ui.weak(format!("{} s on this step", elapsed.as_secs()));
+            }
+        })
+        .response;
+
+    resp.on_hover_ui(|ui| {
+        for (i, s) in steps.iter().enumerate() {
+            let icon = match (i + 1).cmp(&done) {
+                std::cmp::Ordering::Less => egui_phosphor::regular::CHECK,
+                std::cmp::Ordering::Equal => egui_phosphor::regular::CARET_RIGHT,
+                std::cmp::Ordering::Greater => egui_phosphor::regular::CIRCLE,
+            };
+            ui.label(format!("{} {}", icon, s.to_text()));
+        }
+    });
+}
+
+/// the right click menu for setting the filament in a tray
+#[derive(Debug, Clone)]
+struct FilamentEdit {
+    slot: AmsCurrentSlot,
+    tray_type: String,
+    /// material and preset the slot had when opened, kept unless the material changes
+    /// so Bambu spools don't lose their RFID preset
+    current: Option<(String, String)>,
+    color: [u8; 3],
+    nozzle_temp_min: i64,
+    nozzle_temp_max: i64,
+    k: f64,
+}
+
+impl FilamentEdit {
+    fn new(slot: AmsCurrentSlot, current: Option<&AmsSlot>) -> Self {
+        let tray_type = current
+            .map(|s| s.material.clone())
+            .unwrap_or_else(|| "PLA".to_string());
+        let (_, _, min, max) = generic_filament(&tray_type).unwrap_or(GENERIC_FILAMENTS[0]);
+        Self {
+            slot,
+            current: current.and_then(|s| {
+                s.tray_info_idx
+                    .clone()
+                    .filter(|idx| !idx.is_empty())
+                    .map(|idx| (s.material.clone(), idx))
+            }),
+            tray_type,
+            color: current
+                .map(|s| [s.color.r(), s.color.g(), s.color.b()])
+                .unwrap_or([255, 255, 255]),
+            nozzle_temp_min: current.and_then(|s| s.nozzle_temp_min).unwrap_or(min),
+            nozzle_temp_max: current.and_then(|s| s.nozzle_temp_max).unwrap_or(max),
+            k: current.map(|s| s.k).unwrap_or(0.02),
+        }
+    }
+
+    fn tray_info_idx(&self) -> String {
+        match self.current.as_ref() {
+            Some((material, idx)) if *material == self.tray_type => idx.clone(),
+            _ => generic_filament(&self.tray_type)
+                .map(|(_, idx, _, _)| idx.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the setting to send when Set is clicked
+    fn show(&mut self, ui: &mut egui::Ui) -> Option<ChangeAMSFilamentSetting> {
+        let (ams_id, tray_id) = match self.slot {
+            AmsCurrentSlot::ExternalSpool => {
+                ui.strong("External Spool");
+                (
+                    ChangeAMSFilamentSetting::EXTERNAL_AMS_ID,
+                    ChangeAMSFilamentSetting::EXTERNAL_TRAY_ID,
+                )
+            }
+            AmsCurrentSlot::Tray { ams_id, tray_id } => {
+                ui.strong(format!("AMS {} Tray {}", ams_id + 1, tray_id + 1));
+                (ams_id as i64, tray_id as i64)
+            }
+        };
+
+        egui::Grid::new("filament_edit")
+            .num_columns(2)
+            .spacing([12., 4.])
+            .show(ui, |ui| {
+                ui.label("Material");
+                egui::ComboBox::from_id_source("filament_edit_type")
+                    .selected_text(&self.tray_type)
+                    .show_ui(ui, |ui| {
+                        for (t, _, min, max) in GENERIC_FILAMENTS {
+                            if ui.selectable_label(self.tray_type == t, t).clicked() {
+                                self.tray_type = t.to_string();
+                                self.nozzle_temp_min = min;
+                                self.nozzle_temp_max = max;
+                            }
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Color");
+                ui.color_edit_button_srgb(&mut self.color);
+                ui.end_row();
+
+                ui.label("Nozzle");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut self.nozzle_temp_min)
+                            .clamp_range(150..=350)
+                            .suffix(" °C"),
+                    );
+                    ui.label("-");
+                    ui.add(
+                        egui::DragValue::new(&mut self.nozzle_temp_max)
+                            .clamp_range(150..=350)
+                            .suffix(" °C"),
+                    );
+                });
+                ui.end_row();
+            });
+
+        let mut out = None;
+        ui.horizontal(|ui| {
+            if ui.button("Set").clicked() {
+                out = Some(ChangeAMSFilamentSetting {
+                    ams_id,
+                    tray_id,
+                    tray_info_idx: self.tray_info_idx(),
+                    tray_color: self.color,
+                    nozzle_temp_min: self.nozzle_temp_min,
+                    nozzle_temp_max: self.nozzle_temp_max.max(self.nozzle_temp_min),
+                    tray_type: self.tray_type.clone(),
+                });
+            }
+            if ui.button("Cancel").clicked() {
+                ui.close_menu();
+            }
+        });
+        out
+    }
+}
@@ -123,6 +123,10 @@ impl App {
                     ui.label("Access Code");
                     ui.text_edit_singleline(&mut cfg.access_code);
                     ui.end_row();
+
+                    ui.label("Group");
+                    ui.text_edit_singleline(&mut cfg.group);
+                    ui.end_row();
                 });
 
             if ui.button("Save").clicked() {
@@ -143,7 +147,9 @@ impl App {
 
         egui::Frame::group(ui.style()).show(ui, |ui| {
             ui.strong("Graph Values");
-            //
+            ui.horizontal_wrapped(|ui| {
+                self.graph_series_checkboxes(ui, &id);
+            });
         });
 
         // let mut printer = self.config
@@ -3,8 +3,18 @@ use tracing::{debug, error, info, trace, warn};
 
 use egui::{Color32, Sense};
 use egui_extras::Column;
-
-use crate::{cloud::projects::ProjectData, ui::ui_types::App};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    cloud::projects::{ProjectData, TaskData},
+    export::{ExportFormat, ExportWindow},
+    jobs::{
+        cost::JobCost,
+        filament_check::{FilamentCheck, RequiredFilament},
+        JobResult,
+    },
+    ui::ui_types::App,
+};
 
 use super::ui_types::projects_list::{SortDir, SortType};
 
@@ -26,12 +36,281 @@ impl App {
                     .send(crate::conn_manager::PrinterConnCmd::SyncProjects)
                     .unwrap();
             }
+
+            ui.separator();
+            self.plate_check(ui);
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.heading("Local History");
+                self.export_message_label(ui);
+            });
+            self.local_job_list(ui);
             //
         });
 
         //
     }
 
+    pub fn reload_local_jobs(&mut self) {
+        let Some(db) = self.job_db.as_ref() else {
+            return;
+        };
+        match db.jobs(Self::LOCAL_JOBS_LIMIT) {
+            Ok(jobs) => self.local_jobs = jobs,
+            Err(e) => error!("failed to read job history: {:?}", e),
+        }
+    }
+
+    const LOCAL_JOBS_LIMIT: usize = 500;
+
+    /// Filament each sliced plate needs against what's loaded in the chosen printer,
+    /// before sending the plate to print
+    fn plate_check(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Plate Filament Check");
+            if ui.button("Sync Cloud Projects").clicked() {
+                if let Some(tx) = self.cmd_tx.as_ref() {
+                    let _ = tx.send(crate::conn_manager::PrinterConnCmd::SyncCloudProjects);
+                }
+            }
+        });
+
+        let printers = self
+            .config
+            .printers()
+            .into_iter()
+            .map(|p| {
+                let p = p.blocking_read();
+                (p.serial.clone(), p.name.clone())
+            })
+            .collect::<Vec<_>>();
+        let selected_name = self
+            .plate_check_printer
+            .as_ref()
+            .and_then(|id| printers.iter().find(|(s, _)| s == id))
+            .map(|(_, name)| name.clone())
+            .unwrap_or_else(|| "Select printer".to_string());
+        egui::ComboBox::from_id_source("plate_check_printer")
+            .selected_text(selected_name)
+            .show_ui(ui, |ui| {
+                for (serial, name) in printers.iter() {
+                    ui.selectable_value(&mut self.plate_check_printer, Some(serial.clone()), name);
+                }
+            });
+
+        if self.cloud_projects.is_empty() {
+            ui.weak("No cloud projects synced");
+            return;
+        }
+
+        let status = self
+            .plate_check_printer
+            .as_ref()
+            .and_then(|id| self.printer_states.get(id));
+        let ams = status.as_ref().and_then(|s| s.ams.as_ref());
+
+        egui::ScrollArea::vertical()
+            .id_source("plate_check")
+            .max_height(300.)
+            .show(ui, |ui| {
+                egui::Grid::new("plate_check_grid")
+                    .num_columns(4)
+                    .spacing([12., 4.])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for project in self.cloud_projects.iter() {
+                            for plate in project.plates.iter() {
+                                ui.label(&project.name);
+                                ui.label(format!("Plate {}", plate.index));
+                                ui.horizontal(|ui| {
+                                    for f in plate.filaments.iter() {
+                                        let (rect, _) = ui.allocate_exact_size(
+                                            egui::Vec2::splat(12.),
+                                            Sense::hover(),
+                                        );
+                                        ui.painter().rect_filled(
+                                            rect,
+                                            2.,
+                                            Color32::from_rgb(f.color[0], f.color[1], f.color[2]),
+                                        );
+                                        ui.label(format!("{} {:.1} g", f.type_field, f.used_g));
+                                    }
+                                });
+                                match ams {
+                                    Some(ams) => filament_check_label(
+                                        ui,
+                                        &FilamentCheck::new(
+                                            RequiredFilament::from_plate(plate),
+                                            ams,
+                                        ),
+                                    ),
+                                    None => {
+                                        ui.weak("-");
+                                    }
+                                }
+                                ui.end_row();
+                            }
+                        }
+                    });
+            });
+    }
+
+    /// against what's loaded now in the printer the task is running on
+    fn task_filament_check(&self, task: &TaskData) -> Option<FilamentCheck> {
+        if task.ams_detail_mapping.is_empty() {
+            return None;
+        }
+        let status = self.printer_states.get(&task.device_id)?;
+        Some(FilamentCheck::new(
+            RequiredFilament::from_task(task),
+            status.ams.as_ref()?,
+        ))
+    }
+
+    /// by serial
+    fn printer_watts(&self) -> HashMap<String, Option<f64>> {
+        self.config
+            .printers()
+            .into_iter()
+            .map(|p| {
+                let p = p.blocking_read();
+                (p.serial.to_string(), p.watts)
+            })
+            .collect()
+    }
+
+    /// jobs recorded from MQTT, works for LAN-only printers
+    fn local_job_list(&mut self, ui: &mut egui::Ui) {
+        let mut export = None;
+
+        let costs = self.config.costs();
+        let watts = self.printer_watts();
+        let job_costs = self
+            .local_jobs
+            .iter()
+            .map(|job| {
+                let watts = watts.get(job.printer.as_str()).copied().flatten();
+                JobCost::job(job, &costs, &self.spools, watts)
+            })
+            .collect::<Vec<_>>();
+
+        egui_extras::TableBuilder::new(ui)
+            .column(Column::auto().at_least(100.))
+            .column(Column::auto().at_least(150.))
+            .columns(Column::auto().at_least(80.), 6)
+            .column(Column::auto().at_least(100.))
+            .column(Column::remainder().at_least(60.))
+            .resizable(true)
+            .striped(true)
+            .header(25., |mut h| {
+                for name in [
+                    "Printer",
+                    "Name",
+                    "Start",
+                    "Duration",
+                    "Result",
+                    "Layers",
+                    "Material",
+                    "Cost",
+                    "Error",
+                    "Telemetry",
+                ] {
+                    h.col(|ui| {
+                        ui.strong(name);
+                    });
+                }
+            })
+            .body(|mut body| {
+                body.rows(30., self.local_jobs.len(), |mut row| {
+                    let index = row.index();
+                    let job = &self.local_jobs[index];
+
+                    row.col(|ui| {
+                        ui.label(&job.printer_name);
+                    });
+                    row.col(|ui| {
+                        ui.add(egui::Label::new(&job.name).truncate(true));
+                    });
+                    row.col(|ui| {
+                        ui.label(job.start.format("%Y-%m-%d %I:%M %p").to_string());
+                    });
+                    row.col(|ui| {
+                        let t = job.duration();
+                        ui.label(format!(
+                            "{:02}h {:02}m",
+                            t.num_hours(),
+                            t.num_minutes() % 60
+                        ));
+                    });
+                    row.col(|ui| {
+                        let color = match job.result {
+                            JobResult::Running => Color32::from_rgb(121, 173, 116),
+                            JobResult::Finished => ui.visuals().text_color(),
+                            JobResult::Failed => Color32::from_rgb(173, 90, 90),
+                            JobResult::Cancelled => Color32::from_rgb(173, 125, 90),
+                            JobResult::Interrupted => ui.visuals().weak_text_color(),
+                        };
+                        ui.colored_label(color, job.result.to_text());
+                    });
+                    row.col(|ui| {
+                        if let (Some(layer), Some(total)) = (job.layer_reached, job.total_layers) {
+                            ui.label(format!("{}/{}", layer, total));
+                        }
+                    });
+                    row.col(|ui| {
+                        ui.horizontal(|ui| {
+                            for tray in job.trays.iter() {
+                                let [r, g, b] = tray.color;
+                                let (rect, resp) =
+                                    ui.allocate_exact_size(egui::vec2(12., 12.), Sense::hover());
+                                ui.painter()
+                                    .rect_filled(rect, 2., Color32::from_rgb(r, g, b));
+                                let name = if tray.ams_id.is_none() {
+                                    "External spool"
+                                } else {
+                                    &tray.material
+                                };
+                                match tray.grams {
+                                    Some(g) => resp.on_hover_text(format!("{}, {:.1} g", name, g)),
+                                    None => resp.on_hover_text(name),
+                                };
+                            }
+                        });
+                    });
+                    row.col(|ui| {
+                        cost_label(ui, &costs, &job_costs[index]);
+                    });
+                    row.col(|ui| {
+                        if let Some(text) = job.error_text.as_ref() {
+                            ui.label(text)
+                                .on_hover_text(format!("{:08X}", job.error_code.unwrap_or(0)));
+                        }
+                    });
+                    row.col(|ui| {
+                        ui.menu_button(egui_phosphor::regular::EXPORT, |ui| {
+                            for format in ExportFormat::ALL {
+                                if ui.button(format.to_text()).clicked() {
+                                    export = Some((index, format));
+                                    ui.close_menu();
+                                }
+                            }
+                        })
+                        .response
+                        .on_hover_text("Export telemetry");
+                    });
+                });
+            });
+
+        if let Some((i, format)) = export {
+            let job = &self.local_jobs[i];
+            let id = job.printer.clone();
+            let window = ExportWindow::job(job);
+            let name = format!("{}_job{}", job.printer, job.id);
+            self.export_telemetry(&id, window, &name, format);
+        }
+    }
+
     fn project_list(&mut self, ui: &mut egui::Ui) {
         let row_height = 80.0;
         let thumbnail_size = 80.0;
@@ -41,6 +320,18 @@ impl App {
             //
         });
 
+        let costs = self.config.costs();
+        let watts = self.printer_watts();
+
+        /// the filament check is only shown for tasks still printing
+        let running = self
+            .local_jobs
+            .iter()
+            .filter(|job| job.result == JobResult::Running)
+            .filter_map(|job| crate::jobs::match_cloud_task(job, self.projects.tasks()))
+            .map(|task| task.id)
+            .collect::<HashSet<_>>();
+
         let mut builder = egui_extras::TableBuilder::new(ui)
             .column(Column::exact(thumbnail_size + 20.))
             .column(Column::auto().at_least(150.))
@@ -140,6 +431,20 @@ impl App {
                         // });
                     });
 
+                    row.col(|ui| {
+                        let watts = watts.get(&p.device_id).copied().flatten();
+                        cost_label(ui, &costs, &JobCost::task(p, &costs, watts));
+                    });
+
+                    row.col(|ui| {
+                        if !running.contains(&p.id) {
+                            return;
+                        }
+                        if let Some(check) = self.task_filament_check(p) {
+                            filament_check_label(ui, &check);
+                        }
+                    });
+
                     // row.col(|ui| {
                     //     //
                     // });
@@ -290,17 +595,73 @@ impl App {
     }
 }
 
+/// with the breakdown on hover
+fn cost_label(ui: &mut egui::Ui, costs: &crate::config::CostConfig, cost: &JobCost) {
+    let Some(total) = cost.total() else {
+        return;
+    };
+    let part = |x: Option<f64>| {
+        x.map(|x| costs.format(x))
+            .unwrap_or_else(|| "unknown".to_string())
+    };
+    ui.label(costs.format(total)).on_hover_text(format!(
+        "Filament: {}\nElectricity: {}",
+        part(cost.filament),
+        part(cost.electricity)
+    ));
+}
+
+/// OK or the number of issues, with each filament and the suggested tray on hover
+fn filament_check_label(ui: &mut egui::Ui, check: &FilamentCheck) {
+    let resp = if check.is_ok() {
+        ui.label(format!("{} OK", egui_phosphor::regular::CHECK))
+    } else {
+        ui.colored_label(
+            Color32::YELLOW,
+            format!(
+                "{} {} issue(s)",
+                egui_phosphor::regular::WARNING,
+                check.num_issues()
+            ),
+        )
+    };
+    resp.on_hover_ui(|ui| {
+        egui::Grid::new("filament_check")
+            .num_columns(4)
+            .spacing([8., 4.])
+            .show(ui, |ui| {
+                for f in check.filaments.iter() {
+                    let (rect, _) = ui.allocate_exact_size(egui::Vec2::splat(12.), Sense::hover());
+                    ui.painter().rect_filled(rect, 2., f.required.color);
+                    ui.label(format!("{} {:.1} g", f.required.material, f.required.grams));
+                    ui.label(f.tray.map(|t| t.to_text()).unwrap_or_default());
+                    match (&f.issue, f.suggestion) {
+                        (None, _) => ui.label("OK"),
+                        (Some(issue), Some(s)) => ui.colored_label(
+                            Color32::YELLOW,
+                            format!("{}, use {}", issue.to_text(), s.to_text()),
+                        ),
+                        (Some(issue), None) => ui.colored_label(Color32::YELLOW, issue.to_text()),
+                    };
+                    ui.end_row();
+                }
+            });
+    });
+}
+
 struct ProjectRowViewer;
 
 impl ProjectRowViewer {
     /// Title, sortable
-    const ROWS: [(&'static str, Option<SortType>); 6] = [
+    const ROWS: [(&'static str, Option<SortType>); 8] = [
         ("Thumbnail", None),
         ("Name", Some(SortType::Name)),
         ("Date", Some(SortType::PrintDate)),
         ("Status", Some(SortType::Status)),
         ("Time", Some(SortType::PrintTime)),
         ("Material", Some(SortType::Material)),
+        ("Cost", None),
+        ("Filament", None),
         // ("Plate", None),
     ];
 }
@@ -0,0 +1,390 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use egui::{Color32, Sense};
+use egui_extras::Column;
+
+use crate::{
+    conn_manager::PrinterConnCmd,
+    spools::{spoolman::SpoolmanSpool, Spool, TrayLocation},
+    ui::ui_types::App,
+};
+
+/// the add/edit spool form
+#[derive(Debug, Clone)]
+pub struct NewSpoolEntry {
+    /// None when adding a new spool
+    pub id: Option<u64>,
+    pub brand: String,
+    pub material: String,
+    pub color: [u8; 3],
+    pub initial_weight: f64,
+    pub remaining_weight: f64,
+    pub price_per_kg: Option<f64>,
+}
+
+impl Default for NewSpoolEntry {
+    fn default() -> Self {
+        Self {
+            id: None,
+            brand: String::new(),
+            material: "PLA".to_string(),
+            color: [255, 255, 255],
+            initial_weight: 1000.,
+            remaining_weight: 1000.,
+            price_per_kg: None,
+        }
+    }
+}
+
+impl NewSpoolEntry {
+    fn from_spool(spool: &Spool) -> Self {
+        Self {
+            id: Some(spool.id),
+            brand: spool.brand.clone(),
+            material: spool.material.clone(),
+            color: spool.color,
+            initial_weight: spool.initial_weight,
+            remaining_weight: spool.remaining_weight,
+            price_per_kg: spool.price_per_kg,
+        }
+    }
+}
+
+fn spoolman_text(spool: &SpoolmanSpool) -> String {
+    match spool.remaining_weight {
+        Some(w) => format!("#{} {}, {:.0} g", spool.id, spool.name(), w),
+        None => format!("#{} {}", spool.id, spool.name()),
+    }
+}
+
+enum SpoolAction {
+    Assign(u64, Option<TrayLocation>),
+    Edit(Spool),
+    Remove(u64),
+}
+
+impl App {
+    /// every tray on every printer, from the last status report
+    fn tray_locations(&self) -> Vec<(String, TrayLocation)> {
+        let mut out = vec![];
+        for printer in self.config.printers() {
+            let printer = printer.blocking_read();
+            let id = printer.serial.clone();
+
+            let mut units: Vec<i64> = self
+                .printer_states
+                .get(&id)
+                .and_then(|s| s.ams.as_ref().map(|a| a.units.keys().copied().collect()))
+                .unwrap_or_default();
+            units.sort();
+            for unit in units {
+                for tray in 0..4 {
+                    let loc = TrayLocation::tray(id.clone(), unit as u64, tray);
+                    out.push((format!("{}: {}", printer.name, loc.to_text()), loc));
+                }
+            }
+
+            let loc = TrayLocation::external(id.clone());
+            out.push((format!("{}: {}", printer.name, loc.to_text()), loc));
+        }
+        out
+    }
+
+    fn location_name(&self, location: &TrayLocation) -> String {
+        let printer = self
+            .config
+            .get_printer(&location.printer)
+            .map(|p| p.blocking_read().name.clone())
+            .unwrap_or_else(|| location.printer.to_string());
+        format!("{}: {}", printer, location.to_text())
+    }
+
+    pub fn show_spools(&mut self, ui: &mut egui::Ui) {
+        let spools = self.spools.spools();
+        let low_weight = self.config.spools().low_weight;
+        let num_low = spools
+            .iter()
+            .filter(|s| s.remaining_weight < low_weight)
+            .count();
+
+        ui.horizontal(|ui| {
+            if ui.button("Add spool").clicked() {
+                self.new_spool = Some(NewSpoolEntry::default());
+            }
+            ui.label(format!("{} spools", spools.len()));
+            if num_low > 0 {
+                ui.colored_label(
+                    Color32::from_rgb(173, 90, 90),
+                    format!("{} below {:.0} g", num_low, low_weight),
+                );
+            }
+        });
+
+        self.new_spool_form(ui);
+
+        ui.separator();
+
+        let locations = self.tray_locations();
+
+        if self.config.spools().spoolman_url.is_some() {
+            self.spoolman_trays(ui, &locations);
+            ui.separator();
+        }
+
+        let mut action = None;
+
+        egui_extras::TableBuilder::new(ui)
+            .column(Column::auto().at_least(40.))
+            .column(Column::auto().at_least(100.))
+            .column(Column::auto().at_least(80.))
+            .column(Column::auto().at_least(180.))
+            .column(Column::auto().at_least(220.))
+            .column(Column::remainder().at_least(80.))
+            .resizable(true)
+            .striped(true)
+            .header(25., |mut h| {
+                for name in ["Color", "Brand", "Material", "Remaining", "Location", ""] {
+                    h.col(|ui| {
+                        ui.strong(name);
+                    });
+                }
+            })
+            .body(|mut body| {
+                body.rows(30., spools.len(), |mut row| {
+                    let spool = &spools[row.index()];
+
+                    row.col(|ui| {
+                        let [r, g, b] = spool.color;
+                        let (rect, resp) =
+                            ui.allocate_exact_size(egui::vec2(20., 20.), Sense::hover());
+                        ui.painter()
+                            .rect_filled(rect, 2., Color32::from_rgb(r, g, b));
+                        if spool.tray_uuid.is_some() {
+                            resp.on_hover_text("RFID");
+                        }
+                    });
+                    row.col(|ui| {
+                        ui.label(&spool.brand);
+                    });
+                    row.col(|ui| {
+                        ui.label(&spool.material);
+                    });
+                    row.col(|ui| {
+                        let text = format!(
+                            "{:.0} / {:.0} g",
+                            spool.remaining_weight, spool.initial_weight
+                        );
+                        let mut bar =
+                            egui::ProgressBar::new(spool.remaining_fraction() as f32).text(text);
+                        if spool.remaining_weight < low_weight {
+                            bar = bar.fill(Color32::from_rgb(173, 90, 90));
+                        }
+                        ui.add(bar);
+                    });
+                    row.col(|ui| {
+                        let selected = spool
+                            .location
+                            .as_ref()
+                            .map(|l| self.location_name(l))
+                            .unwrap_or_else(|| "Not loaded".to_string());
+                        egui::ComboBox::from_id_source(("spool_location", spool.id))
+                            .width(200.)
+                            .selected_text(selected)
+                            .show_ui(ui, |ui| {
+                                if ui
+                                    .selectable_label(spool.location.is_none(), "Not loaded")
+                                    .clicked()
+                                {
+                                    action = Some(SpoolAction::Assign(spool.id, None));
+                                }
+                                for (name, loc) in locations.iter() {
+                                    if ui
+                                        .selectable_label(
+                                            spool.location.as_ref() == Some(loc),
+                                            name,
+                                        )
+                                        .clicked()
+                                    {
+                                        action =
+                                            Some(SpoolAction::Assign(spool.id, Some(loc.clone())));
+                                    }
+                                }
+                            });
+                    });
+                    row.col(|ui| {
+                        if ui
+                            .button(egui_phosphor::regular::PENCIL_SIMPLE)
+                            .on_hover_text("Edit")
+                            .clicked()
+                        {
+                            action = Some(SpoolAction::Edit(spool.clone()));
+                        }
+                        if ui
+                            .button(egui_phosphor::regular::TRASH)
+                            .on_hover_text("Remove")
+                            .clicked()
+                        {
+                            action = Some(SpoolAction::Remove(spool.id));
+                        }
+                    });
+                });
+            });
+
+        match action {
+            Some(SpoolAction::Assign(id, loc)) => self.spools.assign(id, loc),
+            Some(SpoolAction::Edit(spool)) => {
+                self.new_spool = Some(NewSpoolEntry::from_spool(&spool));
+            }
+            Some(SpoolAction::Remove(id)) => self.spools.remove(id),
+            None => {}
+        }
+    }
+
+    /// which Spoolman spool is loaded in each tray
+    fn spoolman_trays(&mut self, ui: &mut egui::Ui, locations: &[(String, TrayLocation)]) {
+        ui.horizontal(|ui| {
+            ui.heading("Spoolman");
+            if ui.button("Refresh").clicked() {
+                if let Some(tx) = self.cmd_tx.as_ref() {
+                    let _ = tx.send(PrinterConnCmd::SyncSpoolman);
+                }
+            }
+            ui.label(format!("{} spools", self.spoolman_spools.len()));
+        });
+
+        egui::Grid::new("spoolman_trays")
+            .num_columns(2)
+            .spacing([20., 4.])
+            .striped(true)
+            .show(ui, |ui| {
+                for (name, loc) in locations.iter() {
+                    ui.label(name);
+
+                    let current = self.spools.spoolman_at(loc);
+                    let selected = match current {
+                        Some(id) => self
+                            .spoolman_spools
+                            .iter()
+                            .find(|s| s.id == id)
+                            .map(spoolman_text)
+                            .unwrap_or_else(|| format!("Spool #{}", id)),
+                        None => "None".to_string(),
+                    };
+
+                    let mut new = current;
+                    egui::ComboBox::from_id_source(("spoolman_tray", name))
+                        .width(300.)
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut new, None, "None");
+                            for s in self.spoolman_spools.iter() {
+                                ui.selectable_value(&mut new, Some(s.id), spoolman_text(s));
+                            }
+                        });
+                    if new != current {
+                        self.spools.set_spoolman(loc.clone(), new);
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn new_spool_form(&mut self, ui: &mut egui::Ui) {
+        let Some(entry) = self.new_spool.as_mut() else {
+            return;
+        };
+
+        let mut done = false;
+        ui.group(|ui| {
+            egui::Grid::new("new_spool")
+                .num_columns(2)
+                .spacing([20., 4.])
+                .show(ui, |ui| {
+                    ui.label("Brand");
+                    ui.text_edit_singleline(&mut entry.brand);
+                    ui.end_row();
+
+                    ui.label("Material");
+                    ui.text_edit_singleline(&mut entry.material);
+                    ui.end_row();
+
+                    ui.label("Color");
+                    ui.color_edit_button_srgb(&mut entry.color);
+                    ui.end_row();
+
+                    ui.label("Initial weight");
+                    ui.add(
+                        egui::DragValue::new(&mut entry.initial_weight)
+                            .clamp_range(0. ..=10000.)
+                            .suffix(" g"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Remaining weight");
+                    ui.add(
+                        egui::DragValue::new(&mut entry.remaining_weight)
+                            .clamp_range(0. ..=10000.)
+                            .suffix(" g"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Price");
+                    ui.horizontal(|ui| {
+                        let mut set = entry.price_per_kg.is_some();
+                        ui.checkbox(&mut set, "")
+                            .on_hover_text("Otherwise the price for the material is used");
+                        match (set, entry.price_per_kg.as_mut()) {
+                            (true, Some(price)) => {
+                                ui.add(
+                                    egui::DragValue::new(price)
+                                        .clamp_range(0. ..=1000.)
+                                        .speed(0.1)
+                                        .suffix(" per kg"),
+                                );
+                            }
+                            (true, None) => entry.price_per_kg = Some(0.),
+                            (false, _) => entry.price_per_kg = None,
+                        }
+                    });
+                    ui.end_row();
+                });
+
+            ui.horizontal(|ui| {
+                let label = if entry.id.is_some() { "Save" } else { "Add" };
+                if ui.button(label).clicked() {
+                    match entry.id.and_then(|id| self.spools.get(id)) {
+                        Some(mut spool) => {
+                            spool.brand = entry.brand.trim().to_string();
+                            spool.material = entry.material.trim().to_string();
+                            spool.color = entry.color;
+                            spool.initial_weight = entry.initial_weight;
+                            spool.remaining_weight = entry.remaining_weight;
+                            spool.price_per_kg = entry.price_per_kg;
+                            self.spools.update(spool);
+                        }
+                        None => {
+                            let mut spool = Spool::new(
+                                entry.brand.trim(),
+                                entry.material.trim(),
+                                entry.color,
+                                entry.initial_weight,
+                            );
+                            spool.remaining_weight = entry.remaining_weight;
+                            spool.price_per_kg = entry.price_per_kg;
+                            self.spools.add(spool);
+                        }
+                    }
+                    done = true;
+                }
+                if ui.button("Cancel").clicked() {
+                    done = true;
+                }
+            });
+        });
+
+        if done {
+            self.new_spool = None;
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+use egui::{Color32, Sense};
+use egui_extras::Column;
+use std::time::{Duration, Instant};
+
+use crate::{
+    cloud::errors::ErrorMap,
+    config::PrinterConfig,
+    export::ExportFormat,
+    jobs::{
+        cost::JobCost,
+        stats::{FleetStats, StatsFilter},
+        JobRecord,
+    },
+    ui::ui_types::App,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum StatsRange {
+    Last7Days,
+    #[default]
+    Last30Days,
+    ThisMonth,
+    LastMonth,
+    ThisYear,
+    All,
+    Custom,
+}
+
+impl StatsRange {
+    pub const ALL: [Self; 7] = [
+        Self::Last7Days,
+        Self::Last30Days,
+        Self::ThisMonth,
+        Self::LastMonth,
+        Self::ThisYear,
+        Self::All,
+        Self::Custom,
+    ];
+
+    pub fn to_text(&self) -> &'static str {
+        match self {
+            Self::Last7Days => "Last 7 days",
+            Self::Last30Days => "Last 30 days",
+            Self::ThisMonth => "This month",
+            Self::LastMonth => "Last month",
+            Self::ThisYear => "This year",
+            Self::All => "All time",
+            Self::Custom => "Custom",
+        }
+    }
+
+    /// start inclusive, end exclusive, whole days so the filter doesn't change every frame
+    fn days(&self, today: NaiveDate, from: NaiveDate, to: NaiveDate) -> [Option<NaiveDate>; 2] {
+        let month = today.with_day(1).unwrap_or(today);
+        match self {
+            Self::Last7Days => [Some(today - chrono::Duration::days(6)), None],
+            Self::Last30Days => [Some(today - chrono::Duration::days(29)), None],
+            Self::ThisMonth => [Some(month), None],
+            Self::LastMonth => {
+                let prev = (month - chrono::Duration::days(1)).with_day(1);
+                [prev, Some(month)]
+            }
+            Self::ThisYear => [NaiveDate::from_ymd_opt(today.year(), 1, 1), None],
+            Self::All => [None, None],
+            Self::Custom => [Some(from), to.succ_opt()],
+        }
+    }
+}
+
+fn local_midnight(d: NaiveDate) -> Option<DateTime<Local>> {
+    d.and_hms_opt(0, 0, 0)?.and_local_timezone(Local).earliest()
+}
+
+/// recomputed when the filter changes, or every so often for jobs that are running
+pub struct StatsCache {
+    filter: StatsFilter,
+    computed: Instant,
+    stats: FleetStats,
+}
+
+impl StatsCache {
+    const MAX_AGE: Duration = Duration::from_secs(60);
+}
+
+fn percent(x: Option<f64>) -> String {
+    x.map(|x| format!("{:.0}%", x * 100.))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+impl App {
+    fn stats_filter(&self) -> StatsFilter {
+        let [start, end] = self.options.stats_range.days(
+            Local::now().date_naive(),
+            self.options.stats_from,
+            self.options.stats_to,
+        );
+        StatsFilter {
+            start: start.and_then(local_midnight),
+            end: end.and_then(local_midnight),
+            group: self.options.stats_group.clone(),
+        }
+    }
+
+    fn stats_printers(&self) -> Vec<PrinterConfig> {
+        self.config
+            .printers()
+            .into_iter()
+            .map(|p| p.blocking_read().clone())
+            .collect()
+    }
+
+    /// with the watts looked up from `printers`, to avoid locking each printer for every job
+    fn job_cost_with(&self, job: &JobRecord, printers: &[PrinterConfig]) -> JobCost {
+        let watts = printers
+            .iter()
+            .find(|p| p.serial == job.printer)
+            .and_then(|p| p.watts);
+        JobCost::job(job, &self.config.costs(), &self.spools, watts)
+    }
+
+    fn update_stats(&mut self, filter: StatsFilter) {
+        if let Some(cache) = self.fleet_stats.as_ref() {
+            if cache.filter == filter && cache.computed.elapsed() < StatsCache::MAX_AGE {
+                return;
+            }
+        }
+
+        let Some(db) = self.job_db.as_ref() else {
+            return;
+        };
+        let jobs = match db.all_jobs() {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                error!("failed to read job history: {:?}", e);
+                vec![]
+            }
+        };
+
+        if self.error_map.is_none() {
+            self.error_map = ErrorMap::read_error_codes().ok();
+        }
+
+        let printers = self.stats_printers();
+
+        let stats = FleetStats::compute(
+            &jobs,
+            &printers,
+            self.error_map.as_ref(),
+            &filter,
+            Local::now(),
+            |job| self.job_cost_with(job, &printers),
+        );
+        self.fleet_stats = Some(StatsCache {
+            filter,
+            computed: Instant::now(),
+            stats,
+        });
+    }
+
+    /// every job in the filter, written to `EXPORT_DIR`
+    fn export_costs(&mut self, filter: &StatsFilter, format: ExportFormat) {
+        let Some(db) = self.job_db.as_ref() else {
+            return;
+        };
+        let printers = self.stats_printers();
+        let costs = self.config.costs();
+
+        let result = db
+            .all_jobs()
+            .and_then(|jobs| {
+                let jobs = jobs
+                    .into_iter()
+                    .filter(|j| filter.matches(j, &printers))
+                    .map(|j| {
+                        let cost = self.job_cost_with(&j, &printers);
+                        (j, cost)
+                    })
+                    .collect::<Vec<_>>();
+                crate::export::export_job_costs(&jobs, &costs.currency, format)
+            })
+            .and_then(|contents| {
+                let day = |t: Option<DateTime<Local>>| {
+                    t.map(|t| t.format("%Y%m%d").to_string())
+                        .unwrap_or_else(|| "all".to_string())
+                };
+                let name = format!("costs_{}_{}", day(filter.start), day(filter.end));
+                crate::export::write_export(&name, format, &contents)
+            });
+
+        self.export_message = Some(match result {
+            Ok(path) => format!("Exported to {}", path.display()),
+            Err(e) => {
+                error!("failed to export costs: {:?}", e);
+                format!("Export failed: {}", e)
+            }
+        });
+    }
+
+    pub fn show_statistics(&mut self, ui: &mut egui::Ui) {
+        let mut groups = self
+            .config
+            .printers()
+            .into_iter()
+            .filter_map(|p| p.blocking_read().group.clone())
+            .collect::<Vec<_>>();
+        groups.sort();
+        groups.dedup();
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("stats_range")
+                .selected_text(self.options.stats_range.to_text())
+                .show_ui(ui, |ui| {
+                    for r in StatsRange::ALL {
+                        ui.selectable_value(&mut self.options.stats_range, r, r.to_text());
+                    }
+                });
+
+            if self.options.stats_range == StatsRange::Custom {
+                ui.add(
+                    egui_extras::DatePickerButton::new(&mut self.options.stats_from)
+                        .id_source("stats_from"),
+                );
+                ui.label("to");
+                ui.add(
+                    egui_extras::DatePickerButton::new(&mut self.options.stats_to)
+                        .id_source("stats_to"),
+                );
+            }
+
+            egui::ComboBox::from_id_source("stats_group")
+                .selected_text(
+                    self.options
+                        .stats_group
+                        .as_deref()
+                        .unwrap_or("All printers"),
+                )
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.options.stats_group, None, "All printers");
+                    for g in groups.iter() {
+                        ui.selectable_value(&mut self.options.stats_group, Some(g.clone()), g);
+                    }
+                });
+
+            if ui.button("Refresh").clicked() {
+                self.fleet_stats = None;
+            }
+        });
+
+        let filter = self.stats_filter();
+
+        ui.horizontal(|ui| {
+            ui.menu_button("Export costs", |ui| {
+                for format in ExportFormat::ALL {
+                    if ui.button(format.to_text()).clicked() {
+                        self.export_costs(&filter, format);
+                        ui.close_menu();
+                    }
+                }
+            });
+            self.export_message_label(ui);
+        });
+
+        self.update_stats(filter);
+        let costs = self.config.costs();
+
+        ui.separator();
+
+        let Some(cache) = self.fleet_stats.as_ref() else {
+            ui.label("No job history");
+            return;
+        };
+        let stats = &cache.stats;
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("stats_summary")
+                .num_columns(2)
+                .spacing([20., 4.])
+                .show(ui, |ui| {
+                    ui.label("Jobs");
+                    ui.label(stats.total_jobs().to_string());
+                    ui.end_row();
+
+                    ui.label("Success rate");
+                    ui.label(percent(stats.success_rate()));
+                    ui.end_row();
+
+                    ui.label("Failure rate");
+                    ui.label(percent(stats.failure_rate()));
+                    ui.end_row();
+
+                    ui.label("Hours printed");
+                    ui.label(format!("{:.1}", stats.total_hours()));
+                    ui.end_row();
+
+                    ui.label("Filament used");
+                    ui.label(format!("{:.0} g", stats.total_grams()));
+                    ui.end_row();
+
+                    ui.label("Cost");
+                    ui.label(costs.format(stats.total_cost()));
+                    ui.end_row();
+                });
+
+            ui.add_space(10.);
+            ui.heading("Printers");
+            ui.push_id("stats_printers", |ui| {
+                egui_extras::TableBuilder::new(ui)
+                    .vscroll(false)
+                    .column(Column::auto().at_least(120.))
+                    .column(Column::auto().at_least(80.))
+                    .columns(Column::auto().at_least(70.), 9)
+                    .column(Column::remainder().at_least(120.))
+                    .striped(true)
+                    .header(25., |mut h| {
+                        for name in [
+                            "Printer",
+                            "Group",
+                            "Jobs",
+                            "Finished",
+                            "Failed",
+                            "Cancelled",
+                            "Interrupted",
+                            "Success",
+                            "Failure",
+                            "Hours",
+                            "Cost",
+                            "Utilization",
+                        ] {
+                            h.col(|ui| {
+                                ui.strong(name);
+                            });
+                        }
+                    })
+                    .body(|mut body| {
+                        body.rows(25., stats.printers.len(), |mut row| {
+                            let p = &stats.printers[row.index()];
+                            row.col(|ui| {
+                                ui.label(&p.name);
+                            });
+                            row.col(|ui| {
+                                ui.label(p.group.as_deref().unwrap_or(""));
+                            });
+                            for n in [p.jobs, p.finished, p.failed, p.cancelled, p.interrupted] {
+                                row.col(|ui| {
+                                    ui.label(n.to_string());
+                                });
+                            }
+                            row.col(|ui| {
+                                ui.label(percent(p.success_rate()));
+                            });
+                            row.col(|ui| {
+                                ui.label(percent(p.failure_rate()));
+                            });
+                            row.col(|ui| {
+                                ui.label(format!("{:.1}", p.hours));
+                            });
+                            row.col(|ui| {
+                                ui.label(costs.format(p.cost));
+                            });
+                            row.col(|ui| {
+                                ui.add(
+                                    egui::ProgressBar::new(p.utilization as f32)
+                                        .text(format!("{:.1}%", p.utilization * 100.)),
+                                );
+                            });
+                        });
+                    });
+            });
+
+            ui.add_space(10.);
+            ui.heading("Filament");
+            if stats.jobs_without_filament > 0 {
+                ui.label(format!(
+                    "{} jobs have no filament usage, Sync Projects to fetch it from the cloud",
+                    stats.jobs_without_filament
+                ));
+            }
+            ui.push_id("stats_filament", |ui| {
+                egui_extras::TableBuilder::new(ui)
+                    .vscroll(false)
+                    .column(Column::auto().at_least(100.))
+                    .column(Column::auto().at_least(60.))
+                    .column(Column::auto().at_least(80.))
+                    .column(Column::remainder().at_least(60.))
+                    .striped(true)
+                    .header(25., |mut h| {
+                        for name in ["Material", "Color", "Grams", "Jobs"] {
+                            h.col(|ui| {
+                                ui.strong(name);
+                            });
+                        }
+                    })
+                    .body(|mut body| {
+                        body.rows(25., stats.filament.len(), |mut row| {
+                            let f = &stats.filament[row.index()];
+                            row.col(|ui| {
+                                ui.label(&f.material);
+                            });
+                            row.col(|ui| {
+                                let [r, g, b] = f.color;
+                                let (rect, resp) =
+                                    ui.allocate_exact_size(egui::vec2(16., 16.), Sense::hover());
+                                ui.painter()
+                                    .rect_filled(rect, 2., Color32::from_rgb(r, g, b));
+                                resp.on_hover_text(format!("#{:02X}{:02X}{:02X}", r, g, b));
+                            });
+                            row.col(|ui| {
+                                ui.label(format!("{:.1}", f.grams));
+                            });
+                            row.col(|ui| {
+                                ui.label(f.jobs.to_string());
+                            });
+                        });
+                    });
+            });
+
+            ui.add_space(10.);
+            ui.heading("Errors");
+            ui.push_id("stats_errors", |ui| {
+                egui_extras::TableBuilder::new(ui)
+                    .vscroll(false)
+                    .column(Column::auto().at_least(100.))
+                    .column(Column::auto().at_least(300.))
+                    .column(Column::remainder().at_least(60.))
+                    .striped(true)
+                    .header(25., |mut h| {
+                        for name in ["Code", "Description", "Count"] {
+                            h.col(|ui| {
+                                ui.strong(name);
+                            });
+                        }
+                    })
+                    .body(|mut body| {
+                        body.rows(25., stats.errors.len(), |mut row| {
+                            let e = &stats.errors[row.index()];
+                            row.col(|ui| {
+                                ui.label(format!("{:08X}", e.code));
+                            });
+                            row.col(|ui| {
+                                ui.add(egui::Label::new(&e.text).truncate(true))
+                                    .on_hover_text(&e.text);
+                            });
+                            row.col(|ui| {
+                                ui.label(e.count.to_string());
+                            });
+                        });
+                    });
+            });
+        });
+    }
+}
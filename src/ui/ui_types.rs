@@ -9,18 +9,25 @@ use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    alert::log::AlertLog,
     cloud::{
         errors::ErrorMap,
         streaming::{StreamCmd, WebcamTexture},
     },
     config::{ConfigArc, PrinterConfig},
     conn_manager::{PrinterConnCmd, PrinterConnMsg, PrinterId},
+    jobs::{db::JobDb, JobRecord},
+    spools::{spoolman::SpoolmanSpool, SpoolInventory},
     status::bambu::PrinterStatus,
 };
 
 pub use self::projects_list::ProjectsList;
 
-use super::plotting::Graphs;
+use super::{
+    plotting::{GraphRange, GraphSeries, Graphs},
+    spools::NewSpoolEntry,
+    statistics::{StatsCache, StatsRange},
+};
 
 #[derive(Default, Deserialize, Serialize)]
 #[serde(default)]
@@ -70,9 +77,41 @@ pub struct App {
 
     // #[serde(skip)]
     pub projects: ProjectsList,
+    /// with their sliced plates, for the filament check before printing
+    #[serde(skip)]
+    pub cloud_projects: Vec<crate::cloud::projects::ProjectData>,
+    pub plate_check_printer: Option<PrinterId>,
 
     #[serde(skip)]
     pub graphs: Option<Graphs>,
+
+    #[serde(skip)]
+    pub alert_log: AlertLog,
+
+    #[serde(skip)]
+    pub job_db: Option<JobDb>,
+    /// cached from job_db, reloaded on PrinterConnMsg::JobHistoryChanged
+    #[serde(skip)]
+    pub local_jobs: Vec<JobRecord>,
+
+    /// result of the last telemetry export
+    #[serde(skip)]
+    pub export_message: Option<String>,
+
+    /// only the cached file, loaded when statistics are first shown
+    #[serde(skip)]
+    pub error_map: Option<ErrorMap>,
+    /// cleared on PrinterConnMsg::JobHistoryChanged
+    #[serde(skip)]
+    pub fleet_stats: Option<StatsCache>,
+
+    #[serde(skip)]
+    pub spools: SpoolInventory,
+    #[serde(skip)]
+    pub new_spool: Option<NewSpoolEntry>,
+    /// from PrinterConnMsg::SpoolmanSpools
+    #[serde(skip)]
+    pub spoolman_spools: Vec<SpoolmanSpool>,
 }
 
 #[derive(PartialEq, serde::Deserialize, serde::Serialize)]
@@ -81,6 +120,9 @@ pub enum Tab {
     Graphs,
     Printers,
     Projects,
+    Statistics,
+    Spools,
+    Alerts,
     Options,
     // Debugging,
 }
@@ -129,11 +171,25 @@ impl Default for AppLogin {
 }
 
 #[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct AppOptions {
     // pub dark_mode: bool,
     pub dashboard_size: (usize, usize),
     pub selected_printer: Option<PrinterId>,
     pub selected_printer_cfg: Option<NewPrinterEntry>,
+
+    pub graph_printer: Option<PrinterId>,
+    pub graph_range: GraphRange,
+    /// which lines to draw for each printer
+    pub graph_series: HashMap<PrinterId, Vec<GraphSeries>>,
+    pub graph_layer_markers: bool,
+    pub graph_state_markers: bool,
+
+    pub stats_range: StatsRange,
+    /// only used with StatsRange::Custom, end is inclusive
+    pub stats_from: chrono::NaiveDate,
+    pub stats_to: chrono::NaiveDate,
+    pub stats_group: Option<String>,
 }
 
 impl Default for AppOptions {
@@ -143,6 +199,15 @@ impl Default for AppOptions {
             dashboard_size: (4, 2),
             selected_printer: None,
             selected_printer_cfg: None,
+            graph_printer: None,
+            graph_range: GraphRange::default(),
+            graph_series: HashMap::new(),
+            graph_layer_markers: true,
+            graph_state_markers: true,
+            stats_range: StatsRange::default(),
+            stats_from: chrono::Local::now().date_naive() - chrono::Duration::days(30),
+            stats_to: chrono::Local::now().date_naive(),
+            stats_group: None,
         }
     }
 }
@@ -153,6 +218,9 @@ pub struct NewPrinterEntry {
     pub host: String,
     pub access_code: String,
     pub serial: String,
+    /// missing from app state saved before groups were added
+    #[serde(default)]
+    pub group: String,
 }
 
 impl NewPrinterEntry {
@@ -162,6 +230,7 @@ impl NewPrinterEntry {
             host: cfg.host.clone(),
             access_code: cfg.access_code.clone(),
             serial: (*cfg.serial).clone(),
+            group: cfg.group.clone().unwrap_or_default(),
         }
     }
 }
@@ -218,6 +287,10 @@ pub mod projects_list {
             self.projects.len()
         }
 
+        pub fn tasks(&self) -> &[TaskData] {
+            &self.projects
+        }
+
         pub fn get_project(&self, index: usize) -> Option<&TaskData> {
             self.projects.get(self.index_map[index])
         }
@@ -339,6 +412,37 @@ pub enum FilamentSwapStep {
     CheckFilamentPosition,
 }
 
+impl FilamentSwapStep {
+    /// in the order the printer goes through them
+    pub const LOAD: [Self; 6] = [
+        Self::HeatNozzle,
+        Self::CutFilament,
+        Self::PullBackCurrentFilament,
+        Self::PushNewFilament,
+        Self::PurgeOldFilament,
+        Self::CheckFilamentPosition,
+    ];
+    pub const UNLOAD: [Self; 3] = [
+        Self::HeatNozzle,
+        Self::CutFilament,
+        Self::PullBackCurrentFilament,
+    ];
+
+    pub fn to_text(&self) -> &'static str {
+        match self {
+            Self::Idling => "Starting",
+            Self::HeatNozzle => "Heat nozzle",
+            Self::CutFilament => "Cut filament",
+            Self::PullBackCurrentFilament => "Pull back current filament",
+            Self::PushNewFilament => "Push new filament",
+            Self::PurgeOldFilament => "Purge old filament",
+            Self::FeedFilament => "Feed filament",
+            Self::ConfirmExtruded => "Confirm extruded",
+            Self::CheckFilamentPosition => "Check filament position",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub enum AmsState {
     /// 0